@@ -1,9 +1,12 @@
 use getopts::{Matches, Options};
 use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::iter::FromIterator;
 use std::process::exit;
 
 use haku::errors::HakuError;
+use haku::vm::ListOrder;
 
 pub struct Config {
     pub dry_run: bool,
@@ -14,10 +17,39 @@ pub struct Config {
     pub filename: String,
     pub recipe: String,
     pub features: Vec<String>,
+    pub all_features: bool,
     pub show_all: bool,
     pub show_features: bool,
     pub show_recipe: String,
+    pub describe_recipe: String,
     pub show_time: bool,
+    pub progress: bool,
+    pub trace: bool,
+    pub debug_vars: bool,
+    pub strict: bool,
+    pub warn_unused: bool,
+    pub warn_shadow: bool,
+    pub ignore_case: bool,
+    pub allow_missing_default: bool,
+    pub keep_going: bool,
+    pub dump_ops: bool,
+    pub dump_recipes: bool,
+    pub dump_disabled: bool,
+    pub at: Option<(String, usize)>,
+    pub comments: Vec<String>,
+    pub env_vars: Vec<(String, String)>,
+    pub max_include_depth: usize,
+    pub print_shell: bool,
+    pub no_shell: bool,
+    pub clean_temp: bool,
+    pub continue_from: String,
+    pub only: String,
+    pub list_functions: bool,
+    pub dump_config: bool,
+    pub dry_run_smart: bool,
+    pub list_order: ListOrder,
+    pub list_by_file: bool,
+    pub recipe_after_sep: bool,
 }
 
 impl Config {
@@ -33,10 +65,201 @@ impl Config {
             filename: String::new(),
             recipe: String::new(),
             features: Vec::new(),
+            all_features: false,
             show_recipe: String::new(),
+            describe_recipe: String::new(),
             show_time: false,
+            progress: false,
+            trace: false,
+            debug_vars: false,
+            strict: false,
+            warn_unused: false,
+            warn_shadow: false,
+            ignore_case: false,
+            allow_missing_default: false,
+            keep_going: false,
+            dump_ops: false,
+            dump_recipes: false,
+            dump_disabled: false,
+            at: None,
+            comments: Vec::new(),
+            env_vars: Vec::new(),
+            max_include_depth: 0,
+            print_shell: false,
+            no_shell: false,
+            clean_temp: true,
+            continue_from: String::new(),
+            only: String::new(),
+            list_functions: false,
+            dump_config: false,
+            dry_run_smart: false,
+            list_order: ListOrder::Name,
+            list_by_file: false,
+            recipe_after_sep: false,
         }
     }
+
+    /// Returns effective options as `key=value` lines. Values of environment variables
+    /// are not shown, only their names
+    pub fn dump(&self) -> Vec<String> {
+        let env_names: Vec<&str> = self.env_vars.iter().map(|(name, _)| name.as_str()).collect();
+        vec![
+            format!("file={}", self.filename),
+            format!("recipe={}", self.recipe),
+            format!("args={}", self.args.join(" ")),
+            format!("features={}", self.features.join(",")),
+            format!("all_features={}", self.all_features),
+            format!("dry_run={}", self.dry_run),
+            format!("dry_run_smart={}", self.dry_run_smart),
+            format!("verbosity={}", self.verbose),
+            format!("time={}", self.show_time),
+            format!("progress={}", self.progress),
+            format!("trace={}", self.trace),
+            format!("debug_vars={}", self.debug_vars),
+            format!("strict={}", self.strict),
+            format!("warn_unused={}", self.warn_unused),
+            format!("warn_shadow={}", self.warn_shadow),
+            format!("ignore_case={}", self.ignore_case),
+            format!("allow_missing_default={}", self.allow_missing_default),
+            format!("keep_going={}", self.keep_going),
+            format!("continue_from={}", self.continue_from),
+            format!("only={}", self.only),
+            format!("comments={}", self.comments.join(",")),
+            format!("env_vars={}", env_names.join(",")),
+            format!("max_include_depth={}", self.max_include_depth),
+            format!("no_builtin_shell={}", self.no_shell),
+            format!("clean_temp={}", self.clean_temp),
+            format!("list_order={}", if self.list_order == ListOrder::File { "file" } else { "name" }),
+            format!("list_by_file={}", self.list_by_file),
+        ]
+    }
+}
+
+/// Reads a list of features from a file: one feature per line. Empty lines and everything
+/// after `#` are ignored.
+fn read_features_file(path: &str) -> Result<Vec<String>, HakuError> {
+    let input = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(HakuError::FileOpenFailure(path.to_string(), e.to_string())),
+    };
+    let mut feats = Vec::new();
+    for line in BufReader::new(input).lines() {
+        let l = match line {
+            Ok(l) => l,
+            Err(_) => return Err(HakuError::FileReadFailure(path.to_string())),
+        };
+        let l = match l.find('#') {
+            Some(pos) => &l[..pos],
+            None => l.as_str(),
+        };
+        let l = l.trim();
+        if !l.is_empty() {
+            feats.push(l.to_string());
+        }
+    }
+    Ok(feats)
+}
+
+/// Reads environment variables from a file: one `NAME=VALUE` pair per line. Empty lines and
+/// lines starting with `#` are ignored. Optional `export ` before the name and quotes around
+/// the value are removed.
+fn read_env_file(path: &str) -> Result<Vec<(String, String)>, HakuError> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(s) => s,
+        Err(e) => return Err(HakuError::FileOpenFailure(path.to_string(), e.to_string())),
+    };
+    let mut vars = Vec::new();
+    for line in content.lines() {
+        let l = line.trim();
+        if l.is_empty() || l.starts_with('#') {
+            continue;
+        }
+        let l = l.strip_prefix("export ").unwrap_or(l);
+        let (name, val) = match l.find('=') {
+            Some(pos) if pos > 0 => (l[..pos].trim(), l[pos + 1..].trim()),
+            _ => return Err(HakuError::EnvFileError(path.to_string(), line.to_string())),
+        };
+        let quoted = val.len() > 1
+            && ((val.starts_with('"') && val.ends_with('"')) || (val.starts_with('\'') && val.ends_with('\'')));
+        let val = if quoted { &val[1..val.len() - 1] } else { val };
+        vars.push((name.to_string(), val.to_string()));
+    }
+    Ok(vars)
+}
+
+/// Splits command line free arguments into a list of recipes to run and their arguments.
+/// The first word is a recipe name. Every next word that is a name of an existing
+/// recipe starts a new recipe, other words are arguments of the last recipe.
+/// If the first word is not a recipe name, and the default recipe has arguments, all
+/// words are arguments of the default recipe. A `--` that immediately follows a name of
+/// the next recipe in the chain separates the recipe from its arguments and is dropped,
+/// any other `--` is an argument.
+///
+/// * `recipe` - the first free argument (empty if no recipe is set)
+/// * `args` - the rest of free arguments
+/// * `is_recipe` - returns `true` if a word is a name of an existing recipe
+/// * `default_args` - `true` if an unknown first word must be passed to the default recipe
+pub fn split_recipes<F>(recipe: &str, args: &[String], is_recipe: F, default_args: bool) -> Vec<(String, Vec<String>)>
+where
+    F: Fn(&str) -> bool,
+{
+    if !recipe.is_empty() && default_args && !is_recipe(recipe) {
+        let mut all = vec![recipe.to_string()];
+        all.extend_from_slice(args);
+        return vec![(String::new(), all)];
+    }
+    let mut chain: Vec<(String, Vec<String>)> = vec![(recipe.to_string(), Vec::new())];
+    if recipe.is_empty() {
+        return chain;
+    }
+    let mut after_name = false;
+    for arg in args {
+        if after_name && arg == "--" {
+            after_name = false;
+            continue;
+        }
+        after_name = is_recipe(arg);
+        if after_name {
+            chain.push((arg.clone(), Vec::new()));
+        } else if let Some(last) = chain.last_mut() {
+            last.1.push(arg.clone());
+        }
+    }
+    chain
+}
+
+/// Removes hidden developer option `--dump-ops` from command line arguments. Returns `true`
+/// if the option was found before `--`.
+fn take_dump_ops(args: &mut Vec<String>) -> bool {
+    let sep = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    let found = args[..sep].iter().any(|a| a == "--dump-ops");
+    let mut idx = 0;
+    args.retain(|a| {
+        idx += 1;
+        idx > sep || a != "--dump-ops"
+    });
+    found
+}
+
+/// Parses a position in a script in format `FILE:LINE`. The file name can contain `:`
+/// (e.g., a Windows path with drive letter)
+fn parse_position(pos: &str) -> Option<(String, usize)> {
+    let (file, line) = pos.rsplit_once(':')?;
+    if file.is_empty() {
+        return None;
+    }
+    let line = line.trim().parse::<usize>().ok()?;
+    Some((file.to_string(), line))
+}
+
+/// Splits command line arguments at the first `--`. The arguments before it are parsed as
+/// usual, and all arguments after it are free ones(they are never treated as options).
+/// The arguments after the first `--` are returned unchanged, including any later `--`.
+fn split_at_separator(args: &[String]) -> (&[String], Vec<String>) {
+    match args.iter().position(|a| a == "--") {
+        None => (args, Vec::new()),
+        Some(idx) => (&args[..idx], args[idx + 1..].to_vec()),
+    }
 }
 
 fn print_usage(program: &str, opts: &Options) {
@@ -45,24 +268,67 @@ fn print_usage(program: &str, opts: &Options) {
 }
 
 pub fn parse_args() -> Result<Config, HakuError> {
-    let args: Vec<String> = env::args().collect();
+    parse_args_from(env::args().collect())
+}
+
+fn parse_args_from(mut args: Vec<String>) -> Result<Config, HakuError> {
     let program = args[0].clone();
     let mut conf = Config::new();
+    // a hidden option for debugging: it is not shown in help
+    conf.dump_ops = take_dump_ops(&mut args);
 
     let mut opts = Options::new();
     opts.optflag("h", "help", "Show this help");
     opts.optflagmulti("v", "verbose", "Display extra information");
     opts.optflag("", "version", "Display application version");
-    opts.optflag("", "dry-run", "Dry run: do not external command, only display them");
+    opts.optflagopt(
+        "",
+        "dry-run",
+        "Dry run: do not execute external commands, only display them. With `smart` commands in backticks are executed",
+        "smart",
+    );
     opts.optflag("l", "list", "list available commands");
     opts.optopt("f", "file", "Haku file path", "FILENAME");
     opts.optopt("", "feature", "use features", "Feature1,Feature2");
+    opts.optopt("", "features-file", "use features listed in a file (one per line)", "FILENAME");
+    opts.optflag("", "all-features", "enable all user-defined features");
+    opts.optmulti("", "env-file", "load environment variables from a file (NAME=VALUE per line)", "FILENAME");
     opts.optflag("a", "all", "list all recipes: available and disabled ones");
     opts.optflag("", "list-features", "list user-defined features used by a script");
+    opts.optflag("", "list-functions", "list all built-in functions grouped by category");
+    opts.optopt("", "max-include-depth", "maximum depth of nested includes (0 - no limit)", "DEPTH");
+    opts.optopt("", "list-order", "order of recipes in the list: name(default) or file", "name|file");
+    opts.optflag("", "list-by-file", "group recipes in the list by scripts where they are defined");
+    opts.optopt("", "at", "show the name of the recipe that contains the line", "FILE:LINE");
+    opts.optflag("", "dump-config", "print effective options and exit");
+    opts.optflag("", "print-shell", "print the shell that executes external commands and exit");
+    opts.optflag("", "no-builtin-shell", "run external commands directly, without a shell");
+    opts.optflagopt(
+        "",
+        "clean-temp",
+        "remove temporary files created by a script when the run finishes (default: yes)",
+        "yes|no",
+    );
+    opts.optflag("", "dump-recipes", "list all recipes with their locations: name, file, and line");
+    opts.optflag("", "dump-disabled", "list disabled recipes and features that disable them");
     opts.optopt("", "show", "show recipe content", "RECIPE_NAME");
+    opts.optopt("", "describe", "show recipe description and arguments", "RECIPE_NAME");
     opts.optflag("", "time", "show time taken by a recipe");
+    opts.optflag("", "progress", "show the number of the current iteration of FOR loops");
+    opts.optflag("", "trace", "print every shell command and assignment before executing it");
+    opts.optflag("", "debug-vars", "print every variable lookup and where the variable is found");
+    opts.optflag("", "strict", "fail if an undefined variable is used in a string or a shell command");
+    opts.optflag("", "warn-unused", "warn about recipe arguments that are never used");
+    opts.optflag("", "warn-shadow", "warn when a recipe argument hides a global variable");
+    opts.optflag("", "ignore-case", "match recipe names case-insensitively");
+    opts.optmulti("", "comment", "line comment prefix (can be used a few times)", "PREFIX");
+    opts.optflag("k", "keep-going", "when running a few recipes, do not stop on the first failed one");
+    opts.optopt("", "continue-from", "resume a failed run: skip all recipes before the given one", "RECIPE_NAME");
+    opts.optopt("", "only", "run only the given recipe from the list of dependencies", "RECIPE_NAME");
+    opts.optflag("", "allow-missing-default", "do nothing if no recipe is set and no default recipe exists");
 
-    let matches: Matches = match opts.parse(&args[1..]) {
+    let (args, mut tail) = split_at_separator(&args[1..]);
+    let matches: Matches = match opts.parse(args) {
         Ok(m) => m,
         Err(e) => {
             eprintln!("{}", e);
@@ -77,19 +343,59 @@ pub fn parse_args() -> Result<Config, HakuError> {
     }
 
     conf.list = matches.opt_present("l");
+    conf.list_by_file = matches.opt_present("list-by-file");
     conf.dry_run = matches.opt_present("dry-run");
+    conf.dry_run_smart = match matches.opt_str("dry-run") {
+        None => false,
+        Some(s) if s == "smart" => true,
+        Some(s) => {
+            eprintln!("Invalid dry-run mode '{}': only 'smart' is supported", s);
+            exit(1);
+        }
+    };
     conf.show_all = matches.opt_present("a");
     conf.show_features = matches.opt_present("list-features");
+    conf.all_features = matches.opt_present("all-features");
+    conf.dump_recipes = matches.opt_present("dump-recipes");
+    conf.dump_disabled = matches.opt_present("dump-disabled");
+    conf.print_shell = matches.opt_present("print-shell");
+    conf.no_shell = matches.opt_present("no-builtin-shell");
+    conf.clean_temp = match matches.opt_str("clean-temp") {
+        None => true,
+        Some(s) => match s.to_lowercase().as_str() {
+            "yes" | "on" | "true" => true,
+            "no" | "off" | "false" => false,
+            _ => {
+                eprintln!("Invalid clean-temp value '{}': it must be 'yes' or 'no'", s);
+                exit(1);
+            }
+        },
+    };
+    conf.list_functions = matches.opt_present("list-functions");
+    conf.dump_config = matches.opt_present("dump-config");
     if matches.opt_present("v") {
         conf.verbose = matches.opt_count("v");
     }
     conf.version = matches.opt_present("version");
     conf.show_time = matches.opt_present("time");
-    if !matches.free.is_empty() {
-        conf.recipe = matches.free[0].clone();
+    conf.progress = matches.opt_present("progress");
+    conf.trace = matches.opt_present("trace");
+    conf.debug_vars = matches.opt_present("debug-vars");
+    conf.strict = matches.opt_present("strict");
+    conf.warn_unused = matches.opt_present("warn-unused");
+    conf.warn_shadow = matches.opt_present("warn-shadow");
+    conf.ignore_case = matches.opt_present("ignore-case");
+    conf.allow_missing_default = matches.opt_present("allow-missing-default");
+    conf.keep_going = matches.opt_present("keep-going");
+    conf.comments = matches.opt_strs("comment");
+    let mut free = matches.free.clone();
+    conf.recipe_after_sep = free.is_empty() && !tail.is_empty();
+    free.append(&mut tail);
+    if !free.is_empty() {
+        conf.recipe = free[0].clone();
     }
-    if matches.free.len() > 1 {
-        conf.args = Vec::from_iter(matches.free[1..].iter().cloned());
+    if free.len() > 1 {
+        conf.args = Vec::from_iter(free[1..].iter().cloned());
     }
     if let Some(s) = matches.opt_str("f") {
         conf.filename = s;
@@ -97,9 +403,264 @@ pub fn parse_args() -> Result<Config, HakuError> {
     if let Some(s) = matches.opt_str("feature") {
         conf.features = s.split(',').map(|s| s.to_string()).collect();
     }
+    if let Some(s) = matches.opt_str("features-file") {
+        let mut feats = read_features_file(&s)?;
+        conf.features.append(&mut feats);
+    }
+    for path in matches.opt_strs("env-file") {
+        let mut vars = read_env_file(&path)?;
+        conf.env_vars.append(&mut vars);
+    }
+    if let Some(s) = matches.opt_str("at") {
+        conf.at = match parse_position(&s) {
+            Some(pos) => Some(pos),
+            None => {
+                eprintln!("Invalid position '{}': it must be FILE:LINE", s);
+                exit(1);
+            }
+        };
+    }
+    if let Some(s) = matches.opt_str("max-include-depth") {
+        conf.max_include_depth = match s.parse::<usize>() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("Invalid include depth '{}': it must be a non-negative number", s);
+                exit(1);
+            }
+        };
+    }
+    if let Some(s) = matches.opt_str("list-order") {
+        conf.list_order = match s.to_lowercase().as_str() {
+            "name" => ListOrder::Name,
+            "file" => ListOrder::File,
+            _ => {
+                eprintln!("Invalid list order '{}': it must be 'name' or 'file'", s);
+                exit(1);
+            }
+        };
+    }
     if let Some(s) = matches.opt_str("show") {
         conf.show_recipe = s;
     }
+    if let Some(s) = matches.opt_str("describe") {
+        conf.describe_recipe = s;
+    }
+    if let Some(s) = matches.opt_str("continue-from") {
+        conf.continue_from = s;
+    }
+    if let Some(s) = matches.opt_str("only") {
+        if !conf.continue_from.is_empty() {
+            eprintln!("Options --only and --continue-from cannot be used together");
+            exit(1);
+        }
+        conf.only = s;
+    }
 
     Ok(conf)
 }
+
+#[cfg(test)]
+mod config_test {
+    use super::*;
+    use std::fs;
+
+    use haku::vm::{Engine, RunOpts};
+
+    #[test]
+    fn features_from_file() {
+        let path = env::temp_dir().join("haku-test-features-file.features");
+        fs::write(&path, "# CI features\nzip\n\n  rar  # archivers\n").unwrap();
+        let feats = read_features_file(&path.to_string_lossy());
+        let _ = fs::remove_file(&path);
+        let feats = feats.unwrap();
+        assert_eq!(feats, vec!["zip".to_string(), "rar".to_string()]);
+
+        let mut eng = Engine::new(RunOpts::new().with_features(feats));
+        assert!(eng.load_from_str("#[feature(rar)]\npack:\n  echo pack").is_ok());
+        assert!(eng.recipes().iter().any(|r| r.name == "pack"));
+        assert!(eng.disabled_recipes().is_empty());
+
+        assert!(read_features_file("haku-no-such-file.features").is_err());
+    }
+
+    #[test]
+    fn all_features() {
+        let args: Vec<String> = ["haku", "--all-features"].iter().map(|s| s.to_string()).collect();
+        let conf = parse_args_from(args).unwrap();
+        assert!(conf.all_features);
+        let mut eng = Engine::new(RunOpts::new().with_all_features(conf.all_features));
+        assert!(eng.load_from_str("#[feature(zip)]\nzip:\n  echo zip\n").is_ok());
+        assert_eq!(eng.recipe_list(), vec!["zip"]);
+    }
+
+    #[test]
+    fn env_file() {
+        let path = env::temp_dir().join("haku-test-env-file.env");
+        fs::write(&path, "# CI values\nHAKU_TEST_ENV_A=first\n\nexport HAKU_TEST_ENV_B = \"second value\"\n").unwrap();
+        let vars = read_env_file(&path.to_string_lossy());
+        let _ = fs::remove_file(&path);
+        let vars = vars.unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                ("HAKU_TEST_ENV_A".to_string(), "first".to_string()),
+                ("HAKU_TEST_ENV_B".to_string(), "second value".to_string())
+            ]
+        );
+        assert!(read_env_file("haku-no-such-file.env").is_err());
+
+        #[cfg(not(windows))]
+        {
+            let mut eng = Engine::new(RunOpts::new().with_env_vars(vars));
+            let script = "_default:\n  @test \"$HAKU_TEST_ENV_B\" = \"second value\"\nfail:\n  @test \"$HAKU_TEST_ENV_A\" = \"\"";
+            assert!(eng.load_from_str(script).is_ok());
+            assert!(eng.run_recipe("").is_ok());
+            assert!(eng.run_recipe("fail").is_err());
+        }
+    }
+
+    #[test]
+    fn dump_config() {
+        let args: Vec<String> = ["haku", "--feature", "x,y", "--trace", "--max-include-depth", "3", "build", "arg1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let conf = parse_args_from(args).unwrap();
+        let dump = conf.dump();
+        assert!(dump.contains(&"features=x,y".to_string()), "{:?}", dump);
+        assert!(dump.contains(&"trace=true".to_string()));
+        assert!(dump.contains(&"dry_run=false".to_string()));
+        assert!(dump.contains(&"max_include_depth=3".to_string()));
+        assert!(dump.contains(&"recipe=build".to_string()));
+        assert!(dump.contains(&"args=arg1".to_string()));
+    }
+
+    #[test]
+    fn list_order() {
+        let to_args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let conf = parse_args_from(to_args(&["haku", "--list"])).unwrap();
+        assert_eq!(conf.list_order, ListOrder::Name);
+        let conf = parse_args_from(to_args(&["haku", "--list", "--list-order", "file"])).unwrap();
+        assert_eq!(conf.list_order, ListOrder::File);
+        assert!(conf.dump().contains(&"list_order=file".to_string()));
+    }
+
+    #[test]
+    fn dry_run_mode() {
+        let to_args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let conf = parse_args_from(to_args(&["haku", "--dry-run", "build"])).unwrap();
+        assert!(conf.dry_run && !conf.dry_run_smart);
+        assert_eq!(conf.recipe, "build");
+        let conf = parse_args_from(to_args(&["haku", "--dry-run=smart", "build"])).unwrap();
+        assert!(conf.dry_run && conf.dry_run_smart);
+        assert_eq!(conf.recipe, "build");
+        let conf = parse_args_from(to_args(&["haku", "build"])).unwrap();
+        assert!(!conf.dry_run && !conf.dry_run_smart);
+    }
+
+    #[test]
+    fn position() {
+        assert_eq!(parse_position("Taskfile:12"), Some(("Taskfile".to_string(), 12)));
+        assert_eq!(parse_position("c:\\dir\\Taskfile:3"), Some(("c:\\dir\\Taskfile".to_string(), 3)));
+        assert_eq!(parse_position("Taskfile"), None);
+        assert_eq!(parse_position(":3"), None);
+        assert_eq!(parse_position("Taskfile:x"), None);
+    }
+
+    #[test]
+    fn hidden_dump_ops() {
+        let mut args: Vec<String> =
+            ["haku", "--dump-ops", "build", "--", "--dump-ops"].iter().map(|s| s.to_string()).collect();
+        assert!(take_dump_ops(&mut args));
+        assert_eq!(args, vec!["haku".to_string(), "build".to_string(), "--".to_string(), "--dump-ops".to_string()]);
+        assert!(!take_dump_ops(&mut args));
+        assert_eq!(args.len(), 4);
+    }
+
+    #[test]
+    fn recipe_chain() {
+        let mut eng = Engine::new(RunOpts::new());
+        assert!(eng.load_from_str("clean:\n  echo clean\nbuild ver:\n  echo ${ver}\ntest:\n  echo test").is_ok());
+        let args: Vec<String> = vec!["v1.0".to_string(), "test".to_string()];
+        let chain = split_recipes("build", &args, |name| eng.has_recipe(name), false);
+        assert_eq!(chain, vec![("build".to_string(), vec!["v1.0".to_string()]), ("test".to_string(), Vec::new())]);
+
+        let args: Vec<String> = vec!["build".to_string(), "v2.0".to_string(), "extra".to_string()];
+        let chain = split_recipes("clean", &args, |name| eng.has_recipe(name), false);
+        assert_eq!(
+            chain,
+            vec![
+                ("clean".to_string(), Vec::new()),
+                ("build".to_string(), vec!["v2.0".to_string(), "extra".to_string()])
+            ]
+        );
+
+        let chain = split_recipes("", &[], |name| eng.has_recipe(name), false);
+        assert_eq!(chain, vec![(String::new(), Vec::new())]);
+    }
+
+    #[test]
+    fn default_recipe_args() {
+        let mut eng = Engine::new(RunOpts::new());
+        assert!(eng.load_from_str("_default env os:\n  echo ${env} ${os}\nbuild:\n  echo build").is_ok());
+        assert!(eng.default_has_args());
+        let args: Vec<String> = vec!["arg2".to_string()];
+        let chain = split_recipes("arg1", &args, |name| eng.has_recipe(name), eng.default_has_args());
+        assert_eq!(chain, vec![(String::new(), vec!["arg1".to_string(), "arg2".to_string()])]);
+        // the first word is a recipe
+        let chain = split_recipes("build", &args, |name| eng.has_recipe(name), eng.default_has_args());
+        assert_eq!(chain, vec![("build".to_string(), vec!["arg2".to_string()])]);
+
+        let mut eng = Engine::new(RunOpts::new());
+        assert!(eng.load_from_str("_default:\n  echo default").is_ok());
+        assert!(!eng.default_has_args());
+        let chain = split_recipes("arg1", &args, |name| eng.has_recipe(name), eng.default_has_args());
+        assert_eq!(chain, vec![("arg1".to_string(), vec!["arg2".to_string()])]);
+    }
+
+    #[test]
+    fn recipe_chain_args() {
+        let mut eng = Engine::new(RunOpts::new());
+        assert!(eng.load_from_str("build ver:\n  echo ${ver}\ntest mode:\n  echo ${mode}").is_ok());
+        let args: Vec<String> =
+            ["-v", "build", "--", "--release", "test", "--", "--quick"].iter().map(|s| s.to_string()).collect();
+        let (head, tail) = split_at_separator(&args);
+        assert_eq!(head, &["-v".to_string(), "build".to_string()]);
+        assert_eq!(tail, vec!["--release".to_string(), "test".to_string(), "--".to_string(), "--quick".to_string()]);
+        let chain = split_recipes("build", &tail, |name| eng.has_recipe(name), false);
+        assert_eq!(
+            chain,
+            vec![
+                ("build".to_string(), vec!["--release".to_string()]),
+                ("test".to_string(), vec!["--quick".to_string()])
+            ]
+        );
+
+        // a literal `--` is passed to a recipe
+        let args: Vec<String> =
+            ["build", "--", "--", "a", "--", "b", "test", "--", "--", "c"].iter().map(|s| s.to_string()).collect();
+        let (_, tail) = split_at_separator(&args);
+        let chain = split_recipes("build", &tail, |name| eng.has_recipe(name), false);
+        assert_eq!(
+            chain,
+            vec![
+                ("build".to_string(), ["--", "a", "--", "b"].iter().map(|s| s.to_string()).collect()),
+                ("test".to_string(), vec!["--".to_string(), "c".to_string()])
+            ]
+        );
+
+        let args: Vec<String> = vec!["build".to_string()];
+        let (head, tail) = split_at_separator(&args);
+        assert_eq!(head, &["build".to_string()]);
+        assert!(tail.is_empty());
+
+        let to_args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        let conf = parse_args_from(to_args(&["haku", "biuld", "x"])).unwrap();
+        assert!(!conf.recipe_after_sep);
+        let conf = parse_args_from(to_args(&["haku", "-v", "--", "biuld", "x"])).unwrap();
+        assert!(conf.recipe_after_sep);
+        assert_eq!(conf.recipe, "biuld");
+        let conf = parse_args_from(to_args(&["haku", "build", "--", "x"])).unwrap();
+        assert!(!conf.recipe_after_sep);
+    }
+}
@@ -1,9 +1,8 @@
 mod config;
-use std::collections::HashSet;
 use std::path::Path;
 use std::process::exit;
 
-use config::{parse_args, Config};
+use config::{parse_args, split_recipes, Config};
 
 use haku::errors::HakuError;
 use haku::vm::{Engine, RunOpts};
@@ -17,6 +16,13 @@ fn nice_vec_print(lst: &[String]) {
     }
 }
 
+/// Exits with `code` after destroying the engine. `exit` does not run destructors, so
+/// otherwise background processes would keep running and temporary files would stay
+fn finish(eng: Engine, code: i32) -> ! {
+    drop(eng);
+    exit(code)
+}
+
 fn display_recipes(eng: Engine, conf: &Config) {
     if conf.show_features {
         let feats = eng.user_features();
@@ -40,29 +46,17 @@ fn display_recipes(eng: Engine, conf: &Config) {
     if !recipes.is_empty() {
         println!("Available:");
     }
-    let mut sec_names = HashSet::new();
-    for s in recipes {
-        if sec_names.contains(&s.name) {
-            continue;
-        }
-        sec_names.insert(s.name.clone());
-        if s.system {
-            continue;
-        }
-        print!("    {}", s.name);
-        if !s.vars.is_empty() {
-            print!(" (");
-            nice_vec_print(&s.vars);
-            print!(")");
-        }
-        if !s.depends.is_empty() {
-            print!(": ");
-            nice_vec_print(&s.depends);
+    if conf.list_by_file {
+        for (file, lines) in eng.recipe_list_by_file(conf.list_order) {
+            println!("  {}:", file);
+            for line in lines {
+                println!("    {}", line);
+            }
         }
-        if !s.desc.is_empty() {
-            print!(" #{}", s.desc);
+    } else {
+        for line in eng.recipe_list_by(conf.list_order) {
+            println!("    {}", line);
         }
-        println!();
     }
 
     if disabled.is_empty() || !conf.show_all {
@@ -103,7 +97,7 @@ fn detect_taskfile() -> String {
 }
 
 fn main() -> Result<(), HakuError> {
-    let conf = parse_args()?;
+    let mut conf = parse_args()?;
 
     if conf.version {
         let version = env!("CARGO_PKG_VERSION");
@@ -111,20 +105,105 @@ fn main() -> Result<(), HakuError> {
         exit(0);
     }
 
-    let filename = if conf.filename.is_empty() { detect_taskfile() } else { conf.filename.clone() };
+    if conf.list_functions {
+        for (category, funcs) in Engine::function_list() {
+            println!("{}:", category);
+            for f in funcs {
+                println!("    {}", f);
+            }
+        }
+        exit(0);
+    }
+
+    if conf.filename.is_empty() {
+        conf.filename = detect_taskfile();
+    }
+    let filename = conf.filename.clone();
 
     let opts = RunOpts::new()
         .with_dry_run(conf.dry_run)
+        .with_dry_run_smart(conf.dry_run_smart)
         .with_features(conf.features.clone())
+        .with_all_features(conf.all_features)
         .with_verbosity(conf.verbose)
-        .with_time(conf.show_time);
+        .with_time(conf.show_time)
+        .with_progress(conf.progress)
+        .with_no_shell(conf.no_shell)
+        .with_clean_temp(conf.clean_temp)
+        .with_continue_from(&conf.continue_from)
+        .with_only(&conf.only)
+        .with_ignore_case(conf.ignore_case)
+        .with_allow_missing_default(conf.allow_missing_default)
+        .with_comments(conf.comments.clone())
+        .with_trace(conf.trace)
+        .with_debug_vars(conf.debug_vars)
+        .with_strict(conf.strict)
+        .with_warn_shadow(conf.warn_shadow)
+        .with_env_vars(conf.env_vars.clone())
+        .with_max_include_depth(conf.max_include_depth);
     let mut eng = Engine::new(opts);
-    eng.set_free_args(&conf.args);
     if let Err(e) = eng.load_from_file(&filename) {
         eprintln!("{}", e);
         exit(1);
     }
 
+    if conf.warn_unused {
+        for w in eng.unused_arg_warnings() {
+            eprintln!("Warning: {}", w);
+        }
+    }
+
+    if conf.dump_ops {
+        for line in eng.dump_ops() {
+            println!("{}", line);
+        }
+        exit(0);
+    }
+
+    if let Some((file, line)) = &conf.at {
+        println!("{}", eng.recipe_at(file, *line));
+        exit(0);
+    }
+
+    if conf.dump_config {
+        for line in conf.dump() {
+            println!("{}", line);
+        }
+        match eng.resolved_shell() {
+            Ok(shell) => println!("shell={}", shell.join(" ")),
+            Err(e) => {
+                eprintln!("{}", e);
+                finish(eng, 1);
+            }
+        }
+        finish(eng, 0);
+    }
+
+    if conf.print_shell {
+        match eng.resolved_shell() {
+            Ok(shell) => println!("{}", shell.join(" ")),
+            Err(e) => {
+                eprintln!("{}", e);
+                finish(eng, 1);
+            }
+        }
+        finish(eng, 0);
+    }
+
+    if conf.dump_recipes {
+        for line in eng.dump_recipes() {
+            println!("{}", line);
+        }
+        exit(0);
+    }
+
+    if conf.dump_disabled {
+        for line in eng.dump_disabled() {
+            println!("{}", line);
+        }
+        exit(0);
+    }
+
     if !conf.show_recipe.is_empty() {
         match eng.recipe_content(&conf.show_recipe) {
             Err(e) => {
@@ -148,21 +227,47 @@ fn main() -> Result<(), HakuError> {
         exit(0);
     }
 
+    if !conf.describe_recipe.is_empty() {
+        match eng.describe_recipe(&conf.describe_recipe) {
+            Err(e) => {
+                eprintln!("{}", e);
+                exit(1);
+            }
+            Ok(s) => println!("{}", s),
+        }
+        exit(0);
+    }
+
     if conf.list || conf.show_features {
         display_recipes(eng, &conf);
         exit(0);
     }
 
-    if let Err(e) = eng.run_recipe(&conf.recipe) {
-        match e {
-            HakuError::DefaultRecipeError => {
-                println!("Default recipe is not found. Consider creating recipe '_default'");
-            }
-            _ => {
-                eprintln!("{}", e);
-                exit(1);
+    // a mistyped recipe name must not silently run the default recipe
+    let default_args = eng.default_has_args() && (conf.recipe_after_sep || !eng.has_similar_recipe(&conf.recipe));
+    let chain = split_recipes(&conf.recipe, &conf.args, |name| eng.has_recipe(name), default_args);
+    let mut failed = false;
+    for (name, args) in chain {
+        eng.set_free_args(&args);
+        if let Err(e) = eng.run_recipe(&name) {
+            match e {
+                HakuError::DefaultRecipeError => {
+                    println!("Default recipe is not found. Consider creating recipe '_default'");
+                }
+                // a failure of a previous recipe with `--keep-going` is not hidden by `exit(0)`
+                HakuError::Exit(code) => finish(eng, if failed && code == 0 { 1 } else { code }),
+                _ => {
+                    eprintln!("{}", e);
+                    if !conf.keep_going {
+                        finish(eng, 1);
+                    }
+                    failed = true;
+                }
             }
-        }
-    };
+        };
+    }
+    if failed {
+        finish(eng, 1);
+    }
     Ok(())
 }
@@ -1,4 +1,3 @@
-use std::usize;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -13,6 +12,8 @@ pub enum HakuError {
     FileOpenFailure(String, String),
     #[error("Failed to read file '{0}'")]
     FileReadFailure(String),
+    #[error("Invalid line in environment file '{0}': {1}")]
+    EnvFileError(String, String),
     #[error("File '{0}' does not exists")]
     FileNotLoaded(usize),
     #[error("Fail to parse '{0}'{1}")]
@@ -25,16 +26,27 @@ pub enum HakuError {
     SeqVarNameError(String),
     #[error("Include recursion detected: '{0}'")]
     IncludeRecursionError(String),
+    #[error("Include depth limit {1} exceeded while loading '{0}'")]
+    IncludeDepthError(String, usize),
     #[error("Recipe recursive call detected: '{0}'{1}")]
     RecipeRecursionError(String, String),
-    #[error("Recipe '{0}' not found")]
-    RecipeNotFoundError(String),
+    #[error("Recipe '{0}' not found{1}")]
+    RecipeNotFoundError(String, String),
+    #[error("Recipe name '{0}' is ambiguous, it matches: {1}")]
+    RecipeAmbiguousError(String, String),
     #[error("Recipe '{0}' is disabled")]
     RecipeDisabledError(String),
+    #[error("Recipe '{0}' is not in the list of recipes to run: {1}")]
+    RecipeNotInRunError(String, String),
     #[error("Failed to execute '{0}': {1}{2}")]
     ExecFailureError(String, String, String),
-    #[error("Function call error: '{0}'")]
-    FunctionError(String),
+    #[error("Command '{0}' cannot run without a shell: {1}{2}")]
+    NoShellError(String, String, String),
+    /// A built-in function failed. Besides the text for humans, it keeps the details for
+    /// tools: the function name, the error message, the script path(`<string>` for scripts
+    /// loaded from memory), and 1-based line number(`0` - unknown line)
+    #[error("Function call error: '{msg}: {extra}'")]
+    FunctionError { name: String, msg: String, file: String, line: usize, extra: String },
     #[error("Include inside a recipe is not supported{0}")]
     IncludeInRecipeError(String),
     #[error("'END' without corresponding IF/WHILE/FOR{0}")]
@@ -49,17 +61,38 @@ pub enum HakuError {
     StrayElseError(String),
     #[error("'ELSEIF' without corresponding IF{0}")]
     StrayElseIfError(String),
+    #[error("Recipe '{0}' requires argument '{1}' but no value is given{2}")]
+    RecipeArgMissingError(String, String, String),
+    #[error("Conditional dependency '{0}' is not in the dependency list of recipe '{1}'{2}")]
+    DepConditionError(String, String, String),
+    #[error("Division by zero while changing variable '{0}'{1}")]
+    DivisionByZeroError(String, String),
+    #[error("Invalid value '{2}' of argument '{1}' of recipe '{0}': {3}{4}")]
+    RecipeArgTypeError(String, String, String, String, String),
     #[error("Only the last recipe argument can be a list: '{0}'")]
     RecipeListArgError(String),
-    #[error("Execution interrupted with message: {0}")]
-    UserError(String),
+    #[error("Execution interrupted with message: {0}{1}")]
+    UserError(String, String),
     #[error("Invalid directory {0}: {1}")]
     CdError(String, String),
+    #[error("Variable '{0}' is not defined{1}")]
+    UndefinedVarError(String, String),
+    #[error("FOR: {0} iteration(s) failed{1}")]
+    ForFailedError(usize, String),
+    #[error("Attribute #[no-fail-fast] must be followed by FOR statement{0}")]
+    LoopAttrError(String),
+    #[error("Feature implication cycle detected: {0}{1}")]
+    FeatureCycleError(String, String),
+    /// A script has called `exit`: the run stops and the process exits with the code
+    #[error("Execution stopped with exit code {0}")]
+    Exit(i32),
 }
 
 impl HakuError {
     /// Generates detailed information about a place where the error happenned.
+    /// `line_no` is the index of the line in the script, it is displayed starting from 1.
     pub(crate) fn error_extra(filename: &str, line: &str, line_no: usize) -> String {
+        let line_no = line_no.saturating_add(1);
         if !filename.is_empty() && !line.is_empty() {
             format!(" in '{}' at line {}:\n--> {}", filename, line_no, line)
         } else if !filename.is_empty() {
@@ -70,4 +103,11 @@ impl HakuError {
             String::new()
         }
     }
+
+    /// Generates information about a place where a script line failed to parse: the line
+    /// and the column are displayed starting from 1, and the column is marked with `^`.
+    pub(crate) fn error_pointer(line: &str, line_no: usize, col: usize) -> String {
+        let pad = " ".repeat(col.saturating_sub(1));
+        format!(" at line {}, column {}:\n--> {}\n    {}^", line_no + 1, col, line, pad)
+    }
 }
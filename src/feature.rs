@@ -1,4 +1,6 @@
-use pest::iterators::Pairs;
+use std::collections::HashSet;
+
+use pest::iterators::{Pair, Pairs};
 use target::{arch, endian, os, os_family, pointer_width};
 
 use crate::parse::Rule;
@@ -12,7 +14,21 @@ use crate::vm::RunOpts;
 // pointer_width: 32, 64
 // endian: big, little
 
-/// Checks if a feature is in a list of enabled features.
+/// Extracts a single feature value and its negation mark: `!debug` -> `(true, "debug")`
+fn feature_item(p: Pair<Rule>) -> (bool, String) {
+    let mut neg = false;
+    let mut val = String::new();
+    for it in p.into_inner() {
+        match it.as_rule() {
+            Rule::feature_not => neg = true,
+            _ => val = it.as_str().to_lowercase(),
+        }
+    }
+    (neg, val)
+}
+
+/// Checks if a feature is in a list of enabled features. Values of the list are joined with
+/// logical OR. A value with leading `!` matches when the feature does not equal the value.
 ///
 /// Arguments:
 ///
@@ -22,8 +38,8 @@ use crate::vm::RunOpts;
 fn check_feature_val(val: &str, p: Pairs<Rule>, neg: bool) -> bool {
     let mut found = false;
     for fv in p {
-        let val_low = fv.as_str().to_lowercase();
-        if val == val_low.as_str() {
+        let (inv, val_low) = feature_item(fv);
+        if (val == val_low.as_str()) != inv {
             found = true;
             break;
         }
@@ -37,34 +53,24 @@ fn check_feature_val(val: &str, p: Pairs<Rule>, neg: bool) -> bool {
 
 /// Checks if any feature is in a list of enabled features. The function does not use short
 /// way to evaluate feature usage because it has to fill the list of mentioned user-defined
-/// ones that later a caller may print out.
+/// ones that later a caller may print out. A value with leading `!` matches when the feature
+/// is not enabled.
 ///
 /// Arguments:
 ///
 /// * `vals` - feature list to check
+/// * `all` - every user-defined feature is enabled regardless of `vals`
 /// * `p` - list of enabled features
 /// * `neg` - invert the result
 /// * `feats` - vector to collect all user-defined features
-fn check_feature_list(vals: &[String], p: Pairs<Rule>, neg: bool, feats: &mut Vec<String>) -> bool {
-    for fv in p.clone() {
-        let val_low = fv.as_str().to_lowercase();
-        feats.push(val_low);
-    }
-    if vals.is_empty() {
-        return false;
-    }
+fn check_feature_list(vals: &[String], all: bool, p: Pairs<Rule>, neg: bool, feats: &mut Vec<String>) -> bool {
     let mut found = false;
     for fv in p {
-        let val_low = fv.as_str().to_lowercase();
-        for val in vals {
-            let val = val.to_lowercase();
-            if val == val_low {
-                found = true;
-                break;
-            }
-        }
-        if found {
-            break;
+        let (inv, val_low) = feature_item(fv);
+        let enabled = all || vals.iter().any(|v| v.to_lowercase() == val_low);
+        feats.push(val_low);
+        if enabled != inv {
+            found = true;
         }
     }
     if neg {
@@ -74,41 +80,141 @@ fn check_feature_list(vals: &[String], p: Pairs<Rule>, neg: bool, feats: &mut Ve
     found
 }
 
+/// Evaluates a single feature rule, e.g. `os(linux,windows)` or `!feature(debug)`.
+///
+/// * `feats` - vector to collect all user-defined features
+fn check_feature(p: Pairs<Rule>, opts: &RunOpts, feats: &mut Vec<String>) -> Result<bool, String> {
+    let mut inverse = false;
+    let mut f_name: String = String::new();
+    let mut pass = false;
+    for sss in p {
+        match sss.as_rule() {
+            Rule::not_op => {
+                inverse = true;
+            }
+            Rule::feature_name => {
+                f_name = sss.as_str().to_lowercase();
+            }
+            Rule::feature_val => {
+                pass = match f_name.as_str() {
+                    "os" => check_feature_val(os(), sss.into_inner(), inverse),
+                    "bit" => check_feature_val(pointer_width(), sss.into_inner(), inverse),
+                    "family" | "platform" => check_feature_val(os_family(), sss.into_inner(), inverse),
+                    "arch" => check_feature_val(arch(), sss.into_inner(), inverse),
+                    "endian" => check_feature_val(endian(), sss.into_inner(), inverse),
+                    "feature" | "feat" => {
+                        check_feature_list(&opts.feats, opts.all_features, sss.into_inner(), inverse, feats)
+                    }
+                    _ => return Err(f_name),
+                };
+            }
+            _ => unreachable!(),
+        }
+    }
+    Ok(pass)
+}
+
+/// Returns comma-separated groups of a feature directive that do not pass with the current
+/// options: e.g., for `#[os(linux), feature(zip | rar)]` on Windows it returns `["os(linux)"]`.
+pub fn unmet_features(p: Pairs<Rule>, opts: &RunOpts) -> Result<Vec<String>, String> {
+    let mut unmet = Vec::new();
+    let mut feats = Vec::new();
+    for group in p {
+        let text = group.as_str().trim().to_string();
+        let mut pass = false;
+        for ss in group.into_inner() {
+            pass |= check_feature(ss.into_inner(), opts, &mut feats)?;
+        }
+        if !pass {
+            unmet.push(text);
+        }
+    }
+    Ok(unmet)
+}
+
 /// Checks the list of features in a directive against list of enabled features.
-/// Returns `true` if all features are in list of enabled features.
+/// Comma-separated groups are joined with AND, and rules inside a group separated with `|`
+/// are joined with OR. Returns `true` if every group has at least one passed rule.
 ///
 /// * `feats` - vector to collect all user-defined features
 pub fn process_feature(p: Pairs<Rule>, opts: &RunOpts, feats: &mut Vec<String>) -> Result<bool, String> {
     let mut ok = true;
-    for ss in p {
-        let mut inverse = false;
-        let mut f_name: String = String::new();
-        for sss in ss.into_inner() {
-            match sss.as_rule() {
-                Rule::not_op => {
-                    inverse = true;
-                }
-                Rule::feature_name => {
-                    f_name = sss.as_str().to_lowercase();
-                }
-                Rule::feature_val => {
-                    let pass = match f_name.as_str() {
-                        "os" => check_feature_val(os(), sss.into_inner(), inverse),
-                        "bit" => check_feature_val(pointer_width(), sss.into_inner(), inverse),
-                        "family" | "platform" => check_feature_val(os_family(), sss.into_inner(), inverse),
-                        "arch" => check_feature_val(arch(), sss.into_inner(), inverse),
-                        "endian" => check_feature_val(endian(), sss.into_inner(), inverse),
-                        "feature" | "feat" => check_feature_list(&opts.feats, sss.into_inner(), inverse, feats),
-                        _ => return Err(f_name),
-                    };
-                    ok &= pass;
-                    // if !ok {
-                    //     return Ok(ok)
-                    // }
+    for group in p {
+        let mut pass = false;
+        for ss in group.into_inner() {
+            // evaluate all rules to collect all user-defined features
+            pass |= check_feature(ss.into_inner(), opts, feats)?;
+        }
+        ok &= pass;
+    }
+    Ok(ok)
+}
+
+/// Feature implication: enabling the feature `from` enables all features in `to`.
+/// All names are lowercase.
+#[derive(Clone, Debug)]
+pub(crate) struct Implication {
+    pub(crate) from: String,
+    pub(crate) to: Vec<String>,
+    /// the line of the directive in the script
+    pub(crate) line: usize,
+}
+
+/// Returns the list of features passed by a caller extended with all features they imply,
+/// directly or through other features.
+pub(crate) fn expand_features(feats: &[String], rules: &[Implication]) -> Vec<String> {
+    let mut res = feats.to_vec();
+    let mut idx = 0;
+    while idx < res.len() {
+        let name = res[idx].to_lowercase();
+        for rule in rules.iter().filter(|r| r.from == name) {
+            for f in rule.to.iter() {
+                if !res.iter().any(|r| r.to_lowercase() == *f) {
+                    res.push(f.clone());
                 }
-                _ => unreachable!(),
             }
         }
+        idx += 1;
     }
-    Ok(ok)
+    res
+}
+
+fn visit_implied<'a>(
+    name: &'a str,
+    rules: &'a [Implication],
+    path: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+) -> Option<Vec<String>> {
+    if let Some(pos) = path.iter().position(|p| *p == name) {
+        let mut cycle: Vec<String> = path[pos..].iter().map(|s| s.to_string()).collect();
+        cycle.push(name.to_string());
+        return Some(cycle);
+    }
+    if done.contains(name) {
+        return None;
+    }
+    path.push(name);
+    for rule in rules.iter().filter(|r| r.from == name) {
+        for f in rule.to.iter() {
+            if let Some(cycle) = visit_implied(f, rules, path, done) {
+                return Some(cycle);
+            }
+        }
+    }
+    path.pop();
+    done.insert(name);
+    None
+}
+
+/// Looks for a cycle in feature implications(e.g., `a` implies `b` and `b` implies `a`).
+/// Returns the first found cycle as a chain of feature names: `["a", "b", "a"]`.
+pub(crate) fn implication_cycle(rules: &[Implication]) -> Option<Vec<String>> {
+    let mut done = HashSet::new();
+    for rule in rules.iter() {
+        let mut path = Vec::new();
+        if let Some(cycle) = visit_implied(&rule.from, rules, &mut path, &mut done) {
+            return Some(cycle);
+        }
+    }
+    None
 }
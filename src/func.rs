@@ -1,11 +1,13 @@
 use std::env;
 use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use glob::glob;
 use rand::prelude::*;
 use regex::Regex;
 use semver::{Version, VersionReq};
+use sha2::{Digest, Sha256};
 use target::{arch, endian, os, os_family, pointer_width};
 use unicode_width::UnicodeWidthStr;
 
@@ -22,6 +24,10 @@ use crate::vm::Engine;
 
 /// default alphabet to generate random strings
 const LETTERS: &str = "0123456789abcdefghijklmnopqrstuvwxyz";
+/// the length of the random part of a temporary file name
+const TEMP_SUFFIX_LEN: usize = 10;
+/// how many random names to try before giving up creating a temporary file
+const TEMP_ATTEMPTS: usize = 16;
 
 pub(crate) type FuncResult = Result<VarValue, String>;
 /// File path check: object is file, object is directory, object exists
@@ -80,78 +86,645 @@ enum SemverCmp {
     Greater,
     Less,
 }
+/// Which part of a list to return
+enum Slice {
+    /// the first N items
+    Head,
+    /// the last N items
+    Tail,
+    /// all items except the first N ones
+    Drop,
+}
+/// Group of built-in functions for `--list-functions`
+#[derive(Clone, Copy, PartialEq)]
+enum FuncCategory {
+    /// OS and machine information
+    System,
+    /// file paths and directories
+    Path,
+    /// string manipulation
+    String,
+    /// lists
+    List,
+    /// integer arithmetic
+    Numeric,
+    /// environment and script variables
+    Env,
+    /// semantic versions
+    Version,
+    /// output, arguments, and recipe execution
+    Script,
+}
 
-pub(crate) fn run_func(name: &str, eng: &mut Engine, args: &[VarValue]) -> FuncResult {
-    let lowstr = name.to_lowercase();
-    match lowstr.as_str() {
-        "os" => Ok(VarValue::from(os())),
-        "family" | "platform" => Ok(VarValue::from(os_family())),
-        "bit" => Ok(VarValue::from(pointer_width())),
-        "arch" => Ok(VarValue::from(arch())),
-        "endian" => Ok(VarValue::from(endian())),
-        "is_file" | "is-file" | "isfile" => all_are(args, CheckType::IsFile),
-        "is_dir" | "is-dir" | "isdir" => all_are(args, CheckType::IsDir),
-        "exists" => all_are(args, CheckType::Exists),
-        "stem" => extract_part(args, PathPart::Stem),
-        "ext" => extract_part(args, PathPart::Ext),
-        "dir" => extract_part(args, PathPart::Dir),
-        "filename" => extract_part(args, PathPart::Name),
-        "add_ext" | "add-ext" => add_ext(args),
-        "with_ext" | "with-ext" => replace_ext(args),
-        "with_filename" | "with-filename" | "with_name" | "with-name" => replace_name(args),
-        "with_stem" | "with-stem" => replace_stem(args),
-        "join" => join_path(args),
-        "temp" | "temp_dir" | "temp-dir" => system_path(SysPath::Temp),
-        "home" | "home_dir" | "home-dir" | "user_dir" | "user-dir" => system_path(SysPath::Home),
-        "config" | "config_dir" | "config-dir" => system_path(SysPath::Config),
-        "documents" | "docs_dir" | "docs-dir" => system_path(SysPath::Docs),
-        "print" => print_all(args, false),
-        "println" => print_all(args, true),
-        "time" | "format-time" | "format_time" | "time-format" | "time_format" => format_time(args),
-        "trim" => trim_string(args, Where::All),
-        "trim_left" | "trim-left" | "trim_start" | "trim-start" => trim_string(args, Where::Left),
-        "trim_right" | "trim-right" | "trim_end" | "trim-end" => trim_string(args, Where::Right),
-        "starts-with" | "starts_with" => starts_with(args),
-        "ends-with" | "ends_with" => ends_with(args),
-        "lowcase" => change_case(args, StrCase::Low),
-        "upcase" => change_case(args, StrCase::Up),
-        "contains" => contains(args),
-        "replace" => replace(args),
-        "match" => match_regex(args),
-        "substr" => substr_regex(args),
-        "pad-center" | "pad_center" => pad(args, Where::All),
-        "pad-left" | "pad_left" => pad(args, Where::Left),
-        "pad-right" | "pad_right" => pad(args, Where::Right),
-        "field" | "fields" => fields(args),
-        "field-sep" | "fields-sep" | "field_sep" | "fields_sep" => fields_with_sep(args),
-        "rand-str" | "rand_str" => rand_string(args),
-        "inc" => increment(args),
-        "dec" => decrement(args),
-        "shell" => change_shell(eng, args),
-        "invoke-dir" | "invoke_dir" | "invokedir" => {
+impl FuncCategory {
+    const ALL: [FuncCategory; 8] = [
+        FuncCategory::String,
+        FuncCategory::Path,
+        FuncCategory::Env,
+        FuncCategory::List,
+        FuncCategory::Numeric,
+        FuncCategory::Version,
+        FuncCategory::System,
+        FuncCategory::Script,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            FuncCategory::System => "system",
+            FuncCategory::Path => "path",
+            FuncCategory::String => "string",
+            FuncCategory::List => "list",
+            FuncCategory::Numeric => "numeric",
+            FuncCategory::Env => "env",
+            FuncCategory::Version => "version",
+            FuncCategory::Script => "script",
+        }
+    }
+}
+/// Shell family to quote strings for
+#[derive(Debug, PartialEq)]
+enum ShellKind {
+    /// sh, bash, zsh and the like
+    Unix,
+    /// Windows command prompt
+    Cmd,
+    /// Windows powershell or powershell core
+    PowerShell,
+}
+
+/// No upper limit for the number of function arguments
+const MANY: usize = usize::MAX;
+
+/// Built-in function description
+struct FuncDef {
+    /// the function name and its aliases. The first name is the main one
+    names: &'static [&'static str],
+    /// the minimal and maximal number of arguments. `MANY` - no upper limit
+    arity: (usize, usize),
+    category: FuncCategory,
+    func: fn(&mut Engine, &[VarValue]) -> FuncResult,
+}
+
+/// All built-in functions
+static FUNCTIONS: &[FuncDef] = &[
+    FuncDef { names: &["os"], arity: (0, 0), category: FuncCategory::System, func: |_, _| Ok(VarValue::from(os())) },
+    FuncDef {
+        names: &["family", "platform"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::from(os_family())),
+    },
+    FuncDef {
+        names: &["bit"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::from(pointer_width())),
+    },
+    FuncDef {
+        names: &["arch"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::from(arch())),
+    },
+    FuncDef {
+        names: &["endian"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::from(endian())),
+    },
+    FuncDef {
+        names: &["os_release", "os-release", "distro"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::from(distro())),
+    },
+    FuncDef {
+        names: &["hostname", "host_name", "host-name"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::from(host_name())),
+    },
+    FuncDef {
+        names: &["username", "user_name", "user-name"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::from(user_name())),
+    },
+    FuncDef {
+        names: &["cpu_count", "cpu-count", "num_cpus", "num-cpus"],
+        arity: (0, 0),
+        category: FuncCategory::System,
+        func: |_, _| Ok(VarValue::Int(cpu_count())),
+    },
+    FuncDef {
+        names: &["is_file", "is-file", "isfile"],
+        arity: (0, MANY),
+        category: FuncCategory::Path,
+        func: |_, args| all_are(args, CheckType::IsFile),
+    },
+    FuncDef {
+        names: &["is_dir", "is-dir", "isdir"],
+        arity: (0, MANY),
+        category: FuncCategory::Path,
+        func: |_, args| all_are(args, CheckType::IsDir),
+    },
+    FuncDef {
+        names: &["exists"],
+        arity: (0, MANY),
+        category: FuncCategory::Path,
+        func: |_, args| all_are(args, CheckType::Exists),
+    },
+    FuncDef {
+        names: &["stem"],
+        arity: (1, 1),
+        category: FuncCategory::Path,
+        func: |_, args| extract_part(args, PathPart::Stem),
+    },
+    FuncDef {
+        names: &["ext"],
+        arity: (1, 1),
+        category: FuncCategory::Path,
+        func: |_, args| extract_part(args, PathPart::Ext),
+    },
+    FuncDef {
+        names: &["dir"],
+        arity: (1, 1),
+        category: FuncCategory::Path,
+        func: |_, args| extract_part(args, PathPart::Dir),
+    },
+    FuncDef {
+        names: &["filename"],
+        arity: (1, 1),
+        category: FuncCategory::Path,
+        func: |_, args| extract_part(args, PathPart::Name),
+    },
+    FuncDef {
+        names: &["add_ext", "add-ext"],
+        arity: (1, 2),
+        category: FuncCategory::Path,
+        func: |_, args| add_ext(args),
+    },
+    FuncDef {
+        names: &["with_ext", "with-ext"],
+        arity: (1, 2),
+        category: FuncCategory::Path,
+        func: |_, args| replace_ext(args),
+    },
+    FuncDef {
+        names: &["with_filename", "with-filename", "with_name", "with-name"],
+        arity: (1, 2),
+        category: FuncCategory::Path,
+        func: |_, args| replace_name(args),
+    },
+    FuncDef {
+        names: &["with_stem", "with-stem"],
+        arity: (1, 2),
+        category: FuncCategory::Path,
+        func: |_, args| replace_stem(args),
+    },
+    FuncDef { names: &["join"], arity: (0, MANY), category: FuncCategory::Path, func: |_, args| join_path(args) },
+    FuncDef {
+        names: &["temp", "temp_dir", "temp-dir"],
+        arity: (0, 0),
+        category: FuncCategory::Path,
+        func: |_, _| system_path(SysPath::Temp),
+    },
+    FuncDef {
+        names: &["tempfile", "temp_file", "temp-file"],
+        arity: (0, 1),
+        category: FuncCategory::Path,
+        func: |eng, args| make_temp(eng, args, false),
+    },
+    FuncDef {
+        names: &["tempdir"],
+        arity: (0, 1),
+        category: FuncCategory::Path,
+        func: |eng, args| make_temp(eng, args, true),
+    },
+    FuncDef {
+        names: &["home", "home_dir", "home-dir", "user_dir", "user-dir"],
+        arity: (0, 0),
+        category: FuncCategory::Path,
+        func: |_, _| system_path(SysPath::Home),
+    },
+    FuncDef {
+        names: &["config", "config_dir", "config-dir"],
+        arity: (0, 0),
+        category: FuncCategory::Path,
+        func: |_, _| system_path(SysPath::Config),
+    },
+    FuncDef {
+        names: &["documents", "docs_dir", "docs-dir"],
+        arity: (0, 0),
+        category: FuncCategory::Path,
+        func: |_, _| system_path(SysPath::Docs),
+    },
+    FuncDef {
+        names: &["print"],
+        arity: (0, MANY),
+        category: FuncCategory::Script,
+        func: |eng, args| print_all(eng, args, false),
+    },
+    FuncDef {
+        names: &["println"],
+        arity: (0, MANY),
+        category: FuncCategory::Script,
+        func: |eng, args| print_all(eng, args, true),
+    },
+    FuncDef {
+        names: &["print_json", "print-json"],
+        arity: (0, MANY),
+        category: FuncCategory::Script,
+        func: |eng, args| print_json(eng, args),
+    },
+    FuncDef {
+        names: &["set_output", "set-output"],
+        arity: (0, 2),
+        category: FuncCategory::Script,
+        func: |eng, args| set_output(eng, args),
+    },
+    FuncDef { names: &["retry"], arity: (2, 4), category: FuncCategory::Script, func: |eng, args| retry(eng, args) },
+    FuncDef {
+        names: &["spawn"],
+        arity: (1, 1),
+        category: FuncCategory::Script,
+        func: |eng, args| eng.spawn_cmd(&args[0].to_string()),
+    },
+    FuncDef {
+        names: &["wait"],
+        arity: (1, 1),
+        category: FuncCategory::Script,
+        func: |eng, args| eng.wait_child(args[0].to_int()),
+    },
+    FuncDef {
+        names: &["capture_recipe", "capture-recipe"],
+        arity: (1, 1),
+        category: FuncCategory::Script,
+        func: |eng, args| eng.capture_recipe(&args[0].to_string()),
+    },
+    FuncDef { names: &["exit"], arity: (1, 1), category: FuncCategory::Script, func: |_, args| exit_code(args) },
+    FuncDef {
+        names: &["run_args", "run-args"],
+        arity: (1, MANY),
+        category: FuncCategory::Script,
+        func: |eng, args| run_args(eng, args),
+    },
+    FuncDef {
+        names: &["quote", "shell_escape", "shell-escape"],
+        arity: (0, MANY),
+        category: FuncCategory::String,
+        func: |eng, args| quote_args(eng, args),
+    },
+    FuncDef {
+        names: &["lines_to_args", "lines-to-args"],
+        arity: (0, MANY),
+        category: FuncCategory::List,
+        func: |eng, args| lines_to_args(eng, args),
+    },
+    FuncDef {
+        names: &["assert_eq", "assert-eq"],
+        arity: (2, 3),
+        category: FuncCategory::Script,
+        func: |_, args| assert_cmp(args, true),
+    },
+    FuncDef {
+        names: &["assert_ne", "assert-ne"],
+        arity: (2, 3),
+        category: FuncCategory::Script,
+        func: |_, args| assert_cmp(args, false),
+    },
+    FuncDef {
+        names: &["time", "format-time", "format_time", "time-format", "time_format"],
+        arity: (0, 1),
+        category: FuncCategory::String,
+        func: |_, args| format_time(args),
+    },
+    FuncDef {
+        names: &["trim"],
+        arity: (1, 2),
+        category: FuncCategory::String,
+        func: |_, args| trim_string(args, Where::All),
+    },
+    FuncDef {
+        names: &["trim_left", "trim-left", "trim_start", "trim-start"],
+        arity: (1, 2),
+        category: FuncCategory::String,
+        func: |_, args| trim_string(args, Where::Left),
+    },
+    FuncDef {
+        names: &["trim_right", "trim-right", "trim_end", "trim-end"],
+        arity: (1, 2),
+        category: FuncCategory::String,
+        func: |_, args| trim_string(args, Where::Right),
+    },
+    FuncDef { names: &["squeeze"], arity: (1, 2), category: FuncCategory::String, func: |_, args| squeeze(args) },
+    FuncDef {
+        names: &["starts-with", "starts_with"],
+        arity: (1, MANY),
+        category: FuncCategory::String,
+        func: |_, args| starts_with(args),
+    },
+    FuncDef {
+        names: &["ends-with", "ends_with"],
+        arity: (1, MANY),
+        category: FuncCategory::String,
+        func: |_, args| ends_with(args),
+    },
+    FuncDef {
+        names: &["lowcase"],
+        arity: (1, 1),
+        category: FuncCategory::String,
+        func: |_, args| change_case(args, StrCase::Low),
+    },
+    FuncDef {
+        names: &["upcase"],
+        arity: (1, 1),
+        category: FuncCategory::String,
+        func: |_, args| change_case(args, StrCase::Up),
+    },
+    FuncDef { names: &["contains"], arity: (1, MANY), category: FuncCategory::String, func: |_, args| contains(args) },
+    FuncDef { names: &["replace"], arity: (2, 3), category: FuncCategory::String, func: |_, args| replace(args) },
+    FuncDef { names: &["match"], arity: (1, MANY), category: FuncCategory::String, func: |_, args| match_regex(args) },
+    FuncDef { names: &["substr"], arity: (2, 3), category: FuncCategory::String, func: |_, args| substr_regex(args) },
+    FuncDef {
+        names: &["pad-center", "pad_center"],
+        arity: (3, 3),
+        category: FuncCategory::String,
+        func: |_, args| pad(args, Where::All),
+    },
+    FuncDef {
+        names: &["pad-left", "pad_left"],
+        arity: (3, 3),
+        category: FuncCategory::String,
+        func: |_, args| pad(args, Where::Left),
+    },
+    FuncDef {
+        names: &["pad-right", "pad_right"],
+        arity: (3, 3),
+        category: FuncCategory::String,
+        func: |_, args| pad(args, Where::Right),
+    },
+    FuncDef { names: &["wrap"], arity: (2, 2), category: FuncCategory::String, func: |_, args| wrap(args) },
+    FuncDef { names: &["indent"], arity: (2, 3), category: FuncCategory::String, func: |_, args| indent(args) },
+    FuncDef {
+        names: &["dedent", "ltrim-lines", "ltrim_lines"],
+        arity: (1, 1),
+        category: FuncCategory::String,
+        func: |_, args| dedent(args),
+    },
+    FuncDef {
+        names: &["field", "fields"],
+        arity: (2, MANY),
+        category: FuncCategory::String,
+        func: |_, args| fields(args),
+    },
+    FuncDef {
+        names: &["field-sep", "fields-sep", "field_sep", "fields_sep"],
+        arity: (3, MANY),
+        category: FuncCategory::String,
+        func: |_, args| fields_with_sep(args),
+    },
+    FuncDef {
+        names: &["sort_num", "sort-num"],
+        arity: (0, MANY),
+        category: FuncCategory::List,
+        func: |_, args| sort_num(args),
+    },
+    FuncDef {
+        names: &["head", "take"],
+        arity: (2, 2),
+        category: FuncCategory::List,
+        func: |_, args| slice_list(args, Slice::Head),
+    },
+    FuncDef {
+        names: &["tail"],
+        arity: (2, 2),
+        category: FuncCategory::List,
+        func: |_, args| slice_list(args, Slice::Tail),
+    },
+    FuncDef {
+        names: &["drop"],
+        arity: (2, 2),
+        category: FuncCategory::List,
+        func: |_, args| slice_list(args, Slice::Drop),
+    },
+    FuncDef {
+        names: &["rand-str", "rand_str"],
+        arity: (1, 2),
+        category: FuncCategory::String,
+        func: |_, args| rand_string(args),
+    },
+    FuncDef { names: &["inc"], arity: (1, MANY), category: FuncCategory::Numeric, func: |_, args| increment(args) },
+    FuncDef { names: &["dec"], arity: (1, MANY), category: FuncCategory::Numeric, func: |_, args| decrement(args) },
+    FuncDef {
+        names: &["shell"],
+        arity: (0, MANY),
+        category: FuncCategory::Script,
+        func: |eng, args| change_shell(eng, args),
+    },
+    FuncDef {
+        names: &["invoke-dir", "invoke_dir", "invokedir"],
+        arity: (0, 0),
+        category: FuncCategory::Path,
+        func: |eng, _| {
             if eng.cwd_history.is_empty() {
                 return Ok(VarValue::from(eng.cwd.clone().to_string_lossy().to_string()));
             }
             Ok(VarValue::from(eng.cwd_history[0].clone().to_string_lossy().to_string()))
+        },
+    },
+    FuncDef {
+        names: &["argc", "arg_count", "arg-count"],
+        arity: (0, 0),
+        category: FuncCategory::Script,
+        func: |eng, _| Ok(VarValue::Int(eng.free_arg_count() as i64)),
+    },
+    FuncDef {
+        names: &["shift"],
+        arity: (0, 1),
+        category: FuncCategory::Script,
+        func: |eng, args| shift_args(eng, args),
+    },
+    FuncDef {
+        names: &["feature", "feat"],
+        arity: (0, MANY),
+        category: FuncCategory::Script,
+        func: |eng, args| Ok(VarValue::Int(if args.iter().any(|a| eng.is_feature_on(&a.to_string())) { 1 } else { 0 })),
+    },
+    FuncDef {
+        names: &["cwd", "pwd"],
+        arity: (0, 0),
+        category: FuncCategory::Path,
+        func: |eng, _| Ok(VarValue::from(eng.cwd.to_string_lossy().to_string())),
+    },
+    FuncDef {
+        names: &["popd"],
+        arity: (0, 0),
+        category: FuncCategory::Path,
+        func: |eng, _| Ok(VarValue::from(eng.pop_dir().to_string_lossy().to_string())),
+    },
+    FuncDef {
+        names: &["set-env", "set_env", "setenv"],
+        arity: (1, 2),
+        category: FuncCategory::Env,
+        func: |eng, args| set_env_var(eng, args),
+    },
+    FuncDef {
+        names: &["del-env", "del_env", "delenv"],
+        arity: (1, 1),
+        category: FuncCategory::Env,
+        func: |eng, args| del_env_var(eng, args),
+    },
+    FuncDef {
+        names: &["unset"],
+        arity: (1, MANY),
+        category: FuncCategory::Env,
+        func: |eng, args| unset_vars(eng, args),
+    },
+    FuncDef {
+        names: &["defined", "is_set", "is-set"],
+        arity: (1, MANY),
+        category: FuncCategory::Env,
+        func: |eng, args| all_defined(eng, args),
+    },
+    FuncDef {
+        names: &["clear-env", "clear_env", "clearenv"],
+        arity: (0, 0),
+        category: FuncCategory::Env,
+        func: |eng, _| eng.clear_env_vars(),
+    },
+    FuncDef {
+        names: &["help", "list_recipes", "list-recipes"],
+        arity: (0, 0),
+        category: FuncCategory::Script,
+        func: |eng, _| list_recipes(eng),
+    },
+    FuncDef { names: &["glob"], arity: (0, 2), category: FuncCategory::Path, func: |_, args| globfiles(args) },
+    FuncDef {
+        names: &["hash_files", "hash-files"],
+        arity: (1, MANY),
+        category: FuncCategory::Path,
+        func: |_, args| hash_files(args),
+    },
+    FuncDef {
+        names: &["ver-inc", "ver_inc"],
+        arity: (1, 2),
+        category: FuncCategory::Version,
+        func: |_, args| semver_inc(args),
+    },
+    FuncDef {
+        names: &["ver-eq", "ver_eq"],
+        arity: (2, 2),
+        category: FuncCategory::Version,
+        func: |_, args| semver_equal(args),
+    },
+    FuncDef {
+        names: &["ver-gt", "ver_gt"],
+        arity: (2, 2),
+        category: FuncCategory::Version,
+        func: |_, args| semver_greater(args),
+    },
+    FuncDef {
+        names: &["ver-lt", "ver_lt"],
+        arity: (2, 2),
+        category: FuncCategory::Version,
+        func: |_, args| semver_less(args),
+    },
+    FuncDef {
+        names: &["ver-match", "ver_match"],
+        arity: (2, 2),
+        category: FuncCategory::Version,
+        func: |_, args| semver_match(args),
+    },
+];
+
+/// Returns built-in functions grouped by category. Every function is a line with its main
+/// name followed by its aliases in parentheses, e.g. `trim_left (trim-left, trim_start)`
+pub(crate) fn function_list() -> Vec<(String, Vec<String>)> {
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for cat in FuncCategory::ALL.iter() {
+        let mut lines: Vec<String> = FUNCTIONS
+            .iter()
+            .filter(|f| f.category == *cat)
+            .map(|f| match f.names.split_first() {
+                Some((name, aliases)) if !aliases.is_empty() => format!("{} ({})", name, aliases.join(", ")),
+                _ => f.names.join(""),
+            })
+            .collect();
+        lines.sort();
+        groups.push((cat.name().to_string(), lines));
+    }
+    groups
+}
+
+pub(crate) fn run_func(name: &str, eng: &mut Engine, args: &[VarValue]) -> FuncResult {
+    let lowstr = name.to_lowercase();
+    match FUNCTIONS.iter().find(|f| f.names.contains(&lowstr.as_str())) {
+        Some(f) => {
+            check_arity(name, f.arity, args.len())?;
+            (f.func)(eng, args)
         }
-        "set-env" | "set_env" | "setenv" => set_env_var(eng, args),
-        "del-env" | "del_env" | "delenv" => del_env_var(eng, args),
-        "clear-env" | "clear_env" | "clearenv" => eng.clear_env_vars(),
-        "glob" => globfiles(args),
-        "ver-inc" | "ver_inc" => semver_inc(args),
-        "ver-eq" | "ver_eq" => semver_equal(args),
-        "ver-gt" | "ver_gt" => semver_greater(args),
-        "ver-lt" | "ver_lt" => semver_less(args),
-        "ver-match" | "ver_match" => semver_match(args),
-        _ => Err(format!("function {} not found", name)),
+        None => Err(format!("function {} not found", name)),
     }
 }
 
+/// Checks that the number of arguments `cnt` is within function limits `arity`
+fn check_arity(name: &str, arity: (usize, usize), cnt: usize) -> Result<(), String> {
+    let (min, max) = arity;
+    if cnt >= min && cnt <= max {
+        return Ok(());
+    }
+    let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+    let expected = if max == 0 {
+        "no arguments".to_string()
+    } else if min == max {
+        format!("{} {}", min, plural(min))
+    } else if min == 0 {
+        format!("at most {} {}", max, plural(max))
+    } else if max == MANY {
+        format!("at least {} {}", min, plural(min))
+    } else {
+        format!("from {} to {} arguments", min, max)
+    };
+    Err(format!("function '{}' expects {}, got {}", name, expected, cnt))
+}
+
 fn change_shell(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
     let v: Vec<String> = args.iter().map(|v| v.to_string()).filter(|a| !a.is_empty()).collect();
     eng.set_shell(v)
 }
 
+/// Prints the list of available recipes in the same way `haku --list` does. The list goes
+/// where print functions write to(see `set_output`). Returns the printed text.
+fn list_recipes(eng: &mut Engine) -> FuncResult {
+    let lines = eng.recipe_list();
+    let text = if lines.is_empty() {
+        "No recipes found".to_string()
+    } else {
+        let mut text = "Available:".to_string();
+        for line in lines {
+            text += &format!("\n    {}", line);
+        }
+        text
+    };
+    eng.write_output(&format!("{}\n", text))?;
+    Ok(VarValue::Str(text))
+}
+
+/// Removes the first free argument(or the first N ones if the argument is given). Returns the removed
+/// argument as a string when one is removed, and a list of removed arguments otherwise
+fn shift_args(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
+    let cnt = if args.is_empty() { 1 } else { args[0].to_int() };
+    if cnt < 0 {
+        return Err(format!("number of arguments must be non-negative, got {}", cnt));
+    }
+    let mut removed = eng.shift_free_args(cnt as usize);
+    if cnt == 1 {
+        return Ok(VarValue::Str(removed.pop().unwrap_or_default()));
+    }
+    Ok(VarValue::List(removed))
+}
+
 fn set_env_var(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
     let v: Vec<String> = args.iter().map(|v| v.to_string()).filter(|a| !a.is_empty()).collect();
     let name = if v.is_empty() { String::new() } else { v[0].clone() };
@@ -165,6 +738,29 @@ fn del_env_var(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
     eng.del_env_var(name)
 }
 
+/// Removes all script variables listed in arguments. Returns `1` if all of them existed
+fn unset_vars(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
+    if args.is_empty() {
+        return Err("variable name missing".to_string());
+    }
+    let mut res = 1;
+    for arg in args.iter() {
+        if !eng.unset_var(arg.to_string())?.is_true() {
+            res = 0;
+        }
+    }
+    Ok(VarValue::Int(res))
+}
+
+/// Returns `1` if all variables listed in arguments are defined even if their values are empty
+fn all_defined(eng: &Engine, args: &[VarValue]) -> FuncResult {
+    if args.is_empty() {
+        return Err("variable name missing".to_string());
+    }
+    let ok = args.iter().all(|arg| eng.is_var_defined(&arg.to_string()));
+    Ok(VarValue::Int(if ok { 1 } else { 0 }))
+}
+
 /// Checks if all paths are the same: files, directories, existing filesystem objects
 fn all_are(args: &[VarValue], tp: CheckType) -> FuncResult {
     if args.is_empty() {
@@ -195,10 +791,10 @@ fn extract_part(args: &[VarValue], tp: PathPart) -> FuncResult {
     let empty = OsStr::new("");
     let empty_path = Path::new("");
     match tp {
-        PathPart::Stem => Ok(VarValue::from(p.file_stem().unwrap_or(&empty).to_string_lossy().to_string())),
-        PathPart::Ext => Ok(VarValue::from(p.extension().unwrap_or(&empty).to_string_lossy().to_string())),
-        PathPart::Dir => Ok(VarValue::from(p.parent().unwrap_or(&empty_path).to_string_lossy().to_string())),
-        PathPart::Name => Ok(VarValue::from(p.file_name().unwrap_or(&empty).to_string_lossy().to_string())),
+        PathPart::Stem => Ok(VarValue::from(p.file_stem().unwrap_or(empty).to_string_lossy().to_string())),
+        PathPart::Ext => Ok(VarValue::from(p.extension().unwrap_or(empty).to_string_lossy().to_string())),
+        PathPart::Dir => Ok(VarValue::from(p.parent().unwrap_or(empty_path).to_string_lossy().to_string())),
+        PathPart::Name => Ok(VarValue::from(p.file_name().unwrap_or(empty).to_string_lossy().to_string())),
     }
 }
 
@@ -285,8 +881,8 @@ fn replace_stem(args: &[VarValue]) -> FuncResult {
     }
     let empty = OsStr::new("");
     let empty_path = Path::new("");
-    let ext = p.extension().unwrap_or(&empty).to_string_lossy().to_string();
-    let dir = p.parent().unwrap_or(&empty_path);
+    let ext = p.extension().unwrap_or(empty).to_string_lossy().to_string();
+    let dir = p.parent().unwrap_or(empty_path);
     let fname = if ext.is_empty() { new_stem } else { new_stem + "." + &ext };
     Ok(VarValue::Str(dir.join(fname).to_string_lossy().to_string()))
 }
@@ -308,6 +904,70 @@ fn join_path(args: &[VarValue]) -> FuncResult {
     Ok(VarValue::Str(path.to_string_lossy().to_string()))
 }
 
+/// Extracts the distribution ID from the content of `/etc/os-release`.
+/// Returns an empty string if the content does not have the field `ID`.
+#[cfg(target_os = "linux")]
+fn parse_os_release(content: &str) -> String {
+    for line in content.lines() {
+        let line = line.trim();
+        if let Some(val) = line.strip_prefix("ID=") {
+            return val.trim_matches(|c| c == '"' || c == '\'').to_lowercase();
+        }
+    }
+    String::new()
+}
+
+/// Detects the OS distribution: on Linux it is `ID` from `/etc/os-release`, on macOS and
+/// Windows it is the name of the OS. Returns an empty string if the distribution is unknown.
+fn distro() -> String {
+    #[cfg(target_os = "linux")]
+    {
+        match std::fs::read_to_string("/etc/os-release") {
+            Ok(s) => parse_os_release(&s),
+            Err(_) => String::new(),
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        "macos".to_string()
+    }
+    #[cfg(windows)]
+    {
+        "windows".to_string()
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        String::new()
+    }
+}
+
+/// Returns the name of the computer or an empty string if it cannot be detected
+fn host_name() -> String {
+    gethostname::gethostname().to_string_lossy().to_string()
+}
+
+/// Returns the name of the current user. First, it checks environment variables `USER` and
+/// `USERNAME`. If both are empty, it asks the system. Returns an empty string if the name
+/// cannot be detected.
+fn user_name() -> String {
+    for var in &["USER", "USERNAME"] {
+        if let Ok(name) = env::var(var) {
+            if !name.is_empty() {
+                return name;
+            }
+        }
+    }
+    whoami::fallible::username().unwrap_or_default()
+}
+
+/// Returns the number of logical CPUs or `1` if it cannot be detected
+fn cpu_count() -> i64 {
+    match std::thread::available_parallelism() {
+        Ok(n) => n.get() as i64,
+        Err(_) => 1,
+    }
+}
+
 /// Returns a path to a system directory
 fn system_path(pathtype: SysPath) -> FuncResult {
     match pathtype {
@@ -327,18 +987,224 @@ fn system_path(pathtype: SysPath) -> FuncResult {
     }
 }
 
+/// Creates an empty temporary file(`is_dir` is `false`) or directory in the system temporary
+/// directory and returns its full path. The only optional argument is the prefix of the
+/// name(default is `haku-`). The path is removed by the engine when the run finishes.
+fn make_temp(eng: &mut Engine, args: &[VarValue], is_dir: bool) -> FuncResult {
+    let prefix = if args.is_empty() { "haku-".to_string() } else { args[0].to_string() };
+    let letters: Vec<char> = LETTERS.chars().collect();
+    let mut rng = thread_rng();
+    for _ in 0..TEMP_ATTEMPTS {
+        let suffix: String = (0..TEMP_SUFFIX_LEN).map(|_| letters[rng.gen_range(0, letters.len())]).collect();
+        let path = env::temp_dir().join(format!("{}{}", prefix, suffix));
+        let res = if is_dir {
+            std::fs::create_dir(&path)
+        } else {
+            std::fs::OpenOptions::new().write(true).create_new(true).open(&path).map(|_| ())
+        };
+        match res {
+            Ok(()) => {
+                let name = path.to_string_lossy().to_string();
+                eng.track_temp(path);
+                return Ok(VarValue::Str(name));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(format!("failed to create '{}': {}", path.display(), e)),
+        }
+    }
+    Err(format!("failed to create a unique temporary path with prefix '{}'", prefix))
+}
+
 /// Prints all arguments separating them with a space. If `add_new_line` is true,
 /// outputs `\n` at the end.
-fn print_all(args: &[VarValue], add_new_line: bool) -> FuncResult {
+fn print_all(eng: &mut Engine, args: &[VarValue], add_new_line: bool) -> FuncResult {
+    let mut text = String::new();
     for v in args.iter() {
-        print!("{}", v.to_string());
+        text += &v.to_string();
     }
     if add_new_line {
-        println!();
+        text.push('\n');
     }
+    eng.write_output(&text)?;
     Ok(VarValue::Int(1))
 }
 
+/// Redirects the output of print functions to a file, or back to standard output if the
+/// path is empty. The second argument, if it is truthy, makes the function append to the file
+fn set_output(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
+    let path = if args.is_empty() { String::new() } else { args[0].to_string() };
+    let append = args.len() > 1 && args[1].is_true();
+    eng.set_output(&path, append)
+}
+
+/// Checks the argument of `exit` and returns it as the exit code. The engine stops the run with
+/// the code when the function returns
+fn exit_code(args: &[VarValue]) -> FuncResult {
+    let code = args[0].to_int();
+    if code < i64::from(i32::MIN) || code > i64::from(i32::MAX) {
+        return Err(format!("exit code {} is out of range", code));
+    }
+    Ok(VarValue::Int(code))
+}
+
+/// Executes a shell command until it succeeds: `retry(attempts, command[, delay[, multiplier]])`.
+/// The delay between attempts is in seconds, and it is multiplied by `multiplier` after every
+/// failed attempt
+fn retry(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
+    if args.len() < 2 {
+        return Err("requires number of attempts and command".to_string());
+    }
+    let attempts = args[0].to_int();
+    if attempts <= 0 {
+        return Err(format!("number of attempts must be greater than 0, got {}", attempts));
+    }
+    let delay = if args.len() > 2 { args[2].to_int().max(0) as u64 } else { 0 };
+    let multiplier = if args.len() > 3 { args[3].to_int() } else { 1 };
+    if multiplier <= 0 || multiplier > i64::from(u32::MAX) {
+        return Err(format!("invalid delay multiplier {}", multiplier));
+    }
+    eng.retry_cmd(attempts as usize, &args[1].to_string(), Duration::from_secs(delay), multiplier as u32)
+}
+
+/// Executes a program without a shell: `run_args(program, arg1, arg2...)`. A list argument
+/// is passed as a few arguments, one per list item
+fn run_args(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
+    if args.is_empty() {
+        return Err("program name missing".to_string());
+    }
+    let prog = args[0].to_string();
+    let mut cmd_args = Vec::new();
+    for arg in args[1..].iter() {
+        match arg {
+            VarValue::List(lst) => cmd_args.extend(lst.iter().cloned()),
+            _ => cmd_args.push(arg.to_string()),
+        }
+    }
+    eng.exec_args(&prog, &cmd_args)
+}
+
+/// Detects the shell family by the shell executable name
+fn shell_kind(shell: &str) -> ShellKind {
+    let name = Path::new(shell).file_stem().map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match name.as_str() {
+        "cmd" => ShellKind::Cmd,
+        "powershell" | "pwsh" => ShellKind::PowerShell,
+        _ => ShellKind::Unix,
+    }
+}
+
+/// Quotes a string so the shell treats it as a single argument without any substitutions.
+/// A string that contains only safe characters is returned as is
+fn shell_quote(s: &str, kind: &ShellKind) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-./,:=@+%".contains(c);
+    if !s.is_empty() && s.chars().all(safe) {
+        return s.to_string();
+    }
+    match kind {
+        ShellKind::Unix => format!("'{}'", s.replace('\'', "'\\''")),
+        ShellKind::PowerShell => format!("'{}'", s.replace('\'', "''")),
+        ShellKind::Cmd => format!("\"{}\"", s.replace('"', "\"\"")),
+    }
+}
+
+/// Quotes all arguments for the current shell and joins them with spaces. A list argument
+/// is quoted item by item
+fn quote_args(eng: &Engine, args: &[VarValue]) -> FuncResult {
+    let kind = shell_kind(&eng.shell_name());
+    let mut quoted = Vec::new();
+    for arg in args.iter() {
+        match arg {
+            VarValue::List(lst) => quoted.extend(lst.iter().map(|s| shell_quote(s, &kind))),
+            _ => quoted.push(shell_quote(&arg.to_string(), &kind)),
+        }
+    }
+    Ok(VarValue::Str(quoted.join(" ")))
+}
+
+/// Splits all values into lines and makes a one-line string of shell arguments from them:
+/// every non-empty line is quoted for the current shell. It makes a multi-line value(e.g., a
+/// string with new line characters) safe to use in a shell command
+fn lines_to_args(eng: &Engine, args: &[VarValue]) -> FuncResult {
+    let kind = shell_kind(&eng.shell_name());
+    let mut quoted = Vec::new();
+    for arg in args.iter() {
+        let text = arg.to_string();
+        for line in text.lines().map(|l| l.trim_end_matches('\r')).filter(|l| !l.is_empty()) {
+            quoted.push(shell_quote(line, &kind));
+        }
+    }
+    Ok(VarValue::Str(quoted.join(" ")))
+}
+
+/// Compares two values and fails if they are not equal(`equal` is `true`) or if they are
+/// equal(`equal` is `false`). The optional third argument is a message to prepend to the error
+fn assert_cmp(args: &[VarValue], equal: bool) -> FuncResult {
+    if args.len() < 2 {
+        return Err("requires two values to compare".to_string());
+    }
+    let (expected, actual) = (&args[0], &args[1]);
+    let ok = if equal { actual.cmp(expected, "==") } else { actual.cmp(expected, "!=") };
+    if ok {
+        return Ok(VarValue::Int(1));
+    }
+    let msg = if equal {
+        format!("expected '{}', got '{}'", expected, actual)
+    } else {
+        format!("expected value different from '{}'", expected)
+    };
+    if args.len() > 2 {
+        return Err(format!("{}: {}", args[2], msg));
+    }
+    Err(format!("assertion failed: {}", msg))
+}
+
+/// Escapes a string to be a valid JSON string value, including surrounding double quotes
+fn json_escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len() + 2);
+    res.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => res.push_str("\\\""),
+            '\\' => res.push_str("\\\\"),
+            '\n' => res.push_str("\\n"),
+            '\r' => res.push_str("\\r"),
+            '\t' => res.push_str("\\t"),
+            c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+            c => res.push(c),
+        }
+    }
+    res.push('"');
+    res
+}
+
+/// Converts a value to JSON: integers are numbers, lists are arrays of strings, and
+/// all other values are strings
+fn to_json(v: &VarValue) -> String {
+    match v {
+        VarValue::Int(i) => i.to_string(),
+        VarValue::List(lst) => {
+            let items: Vec<String> = lst.iter().map(|s| json_escape(s)).collect();
+            format!("[{}]", items.join(","))
+        }
+        _ => json_escape(&v.to_string()),
+    }
+}
+
+/// Prints a value as JSON and adds a new line. If there are a few arguments, they are
+/// printed as a JSON array. Returns the printed JSON text.
+fn print_json(eng: &mut Engine, args: &[VarValue]) -> FuncResult {
+    let text = match args.len() {
+        0 => json_escape(""),
+        1 => to_json(&args[0]),
+        _ => {
+            let items: Vec<String> = args.iter().map(to_json).collect();
+            format!("[{}]", items.join(","))
+        }
+    };
+    eng.write_output(&format!("{}\n", text))?;
+    Ok(VarValue::Str(text))
+}
+
 /// Formats current time using format specification. If the specification is empty
 /// the format `"%Y%m%d-%H%M%S"` is used.
 fn format_time(args: &[VarValue]) -> FuncResult {
@@ -353,7 +1219,7 @@ fn format_time(args: &[VarValue]) -> FuncResult {
 }
 
 /// Trims characters from a string. Function with one argument trims all whitespaces.
-/// Otherwise, it trims the first character of the second string from the first one.
+/// Otherwise, it trims all characters of the second string from the first one.
 fn trim_string(args: &[VarValue], dir: Where) -> FuncResult {
     if args.is_empty() {
         return Ok(VarValue::Str(String::new()));
@@ -369,19 +1235,48 @@ fn trim_string(args: &[VarValue], dir: Where) -> FuncResult {
         return Ok(VarValue::from(st));
     }
 
-    let what = args[1].to_string().chars().next();
-    let c = match what {
-        None => return Ok(VarValue::Str(s)),
-        Some(cc) => cc,
-    };
+    let set: Vec<char> = args[1].to_string().chars().collect();
+    if set.is_empty() {
+        return Ok(VarValue::Str(s));
+    }
     let st = match dir {
-        Where::All => s.trim_matches(c),
-        Where::Left => s.trim_start_matches(c),
-        Where::Right => s.trim_end_matches(c),
+        Where::All => s.trim_matches(set.as_slice()),
+        Where::Left => s.trim_start_matches(set.as_slice()),
+        Where::Right => s.trim_end_matches(set.as_slice()),
     };
     Ok(VarValue::from(st))
 }
 
+/// Collapses runs of whitespace into a single space and trims the string. If the second
+/// argument is set, only runs of its first character are collapsed into one character,
+/// and the string is not trimmed.
+fn squeeze(args: &[VarValue]) -> FuncResult {
+    if args.is_empty() {
+        return Ok(VarValue::Str(String::new()));
+    }
+
+    let s = args[0].to_string();
+    if args.len() == 1 {
+        let v: Vec<&str> = s.split_whitespace().collect();
+        return Ok(VarValue::from(v.join(" ")));
+    }
+
+    let c = match args[1].to_string().chars().next() {
+        None => return Ok(VarValue::Str(s)),
+        Some(cc) => cc,
+    };
+    let mut res = String::with_capacity(s.len());
+    let mut prev: Option<char> = None;
+    for ch in s.chars() {
+        if ch == c && prev == Some(c) {
+            continue;
+        }
+        res.push(ch);
+        prev = Some(ch);
+    }
+    Ok(VarValue::from(res))
+}
+
 /// Checks if the string starts with a substring. The function accepts unlimited number
 /// of argument. It returns `true` if a string (the first argument) starts with any
 /// substring(the rest arguments).
@@ -537,13 +1432,13 @@ fn pad(args: &[VarValue], loc: Where) -> FuncResult {
     }
 
     let patt = args[1].to_string();
-    let patt_width = patt.width() as usize;
+    let patt_width = patt.width();
     if patt_width == 0 {
         return Err("pad string cannot be empty".to_string());
     }
     let l = args[2].to_int() as usize;
     let s = args[0].to_string();
-    let orig_width = s.width() as usize;
+    let orig_width = s.width();
 
     if orig_width + patt_width >= l {
         return Ok(VarValue::from(s));
@@ -563,6 +1458,96 @@ fn pad(args: &[VarValue], loc: Where) -> FuncResult {
     Ok(VarValue::from(res))
 }
 
+/// Wraps a string(the first argument) at word boundaries, so no line is wider than the given
+/// width(the second argument). Existing line breaks are kept. A word wider than the width is
+/// put on a separate line as is.
+fn wrap(args: &[VarValue]) -> FuncResult {
+    if args.len() < 2 {
+        return Err("requires two arguments".to_string());
+    }
+    let width = args[1].to_int();
+    if width <= 0 {
+        return Err(format!("width must be greater than zero: {}", width));
+    }
+    let width = width as usize;
+
+    let s = args[0].to_string();
+    let mut lines: Vec<String> = Vec::new();
+    for orig in s.lines() {
+        let mut line = String::new();
+        let mut line_width = 0usize;
+        for word in orig.split_whitespace() {
+            let w = word.width();
+            if line_width != 0 && line_width + 1 + w > width {
+                lines.push(line);
+                line = String::new();
+                line_width = 0;
+            }
+            if line_width != 0 {
+                line.push(' ');
+                line_width += 1;
+            }
+            line += word;
+            line_width += w;
+        }
+        lines.push(line);
+    }
+    Ok(VarValue::from(lines.join("\n")))
+}
+
+/// Prefixes every line of a string(the first argument). The second argument is either the
+/// number of spaces or a prefix string. Empty lines are not indented unless the third
+/// argument is `true`.
+fn indent(args: &[VarValue]) -> FuncResult {
+    if args.len() < 2 {
+        return Err("requires two arguments".to_string());
+    }
+    let prefix = match &args[1] {
+        VarValue::Int(n) => {
+            if *n < 0 {
+                return Err(format!("indentation cannot be negative: {}", n));
+            }
+            " ".repeat(*n as usize)
+        }
+        v => v.to_string(),
+    };
+    let indent_empty = args.len() > 2 && args[2].is_true();
+
+    let s = args[0].to_string();
+    let lines: Vec<String> = s
+        .lines()
+        .map(|l| if l.trim().is_empty() && !indent_empty { l.to_string() } else { format!("{}{}", prefix, l) })
+        .collect();
+    Ok(VarValue::from(lines.join("\n")))
+}
+
+/// Removes the longest common leading whitespace from all non-empty lines of a string.
+/// Lines that contain only whitespace become empty ones.
+fn dedent(args: &[VarValue]) -> FuncResult {
+    if args.is_empty() {
+        return Ok(VarValue::Str(String::new()));
+    }
+
+    let s = args[0].to_string();
+    let mut common: Option<&str> = None;
+    for l in s.lines() {
+        if l.trim().is_empty() {
+            continue;
+        }
+        let ws = &l[..l.len() - l.trim_start().len()];
+        common = match common {
+            None => Some(ws),
+            Some(c) => {
+                let n: usize = c.chars().zip(ws.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+                Some(&c[..n])
+            }
+        };
+    }
+    let cut = common.unwrap_or("").len();
+    let lines: Vec<&str> = s.lines().map(|l| if l.trim().is_empty() { "" } else { &l[cut..] }).collect();
+    Ok(VarValue::from(lines.join("\n")))
+}
+
 /// Treats a string(the first argument) as a string with values delimited with whitespaces, and
 /// returns the fields by their indices(the rest arguments) as an array of strings. Field index
 /// starts with 0.  If field index is equal to or greater than the number of values, the empty
@@ -630,6 +1615,56 @@ fn fields_with_sep(args: &[VarValue]) -> FuncResult {
     }
 }
 
+/// Sorts all values(items of list arguments are sorted as separate values) as integer numbers
+/// in ascending order. Values that are not numbers go after all numbers in their original order.
+/// Always returns a list of strings.
+fn sort_num(args: &[VarValue]) -> FuncResult {
+    let mut vals: Vec<(Option<i64>, String)> = Vec::new();
+    for arg in args.iter() {
+        let items = match arg {
+            VarValue::List(v) => v.clone(),
+            _ => vec![arg.to_string()],
+        };
+        for item in items.into_iter() {
+            let n = item.trim().parse::<i64>().ok();
+            vals.push((n, item));
+        }
+    }
+    // stable sort keeps the order of equal numbers and of non-numeric values
+    vals.sort_by(|a, b| match (a.0, b.0) {
+        (Some(x), Some(y)) => x.cmp(&y),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    Ok(VarValue::List(vals.into_iter().map(|(_, s)| s).collect()))
+}
+
+/// Returns a part of a list(the first argument) as a new list. The second argument is the number
+/// of items to take or to skip. A non-list value is treated as a list of one item. If the number
+/// is greater than the list length, the entire list(or an empty one for `Slice::Drop`) is returned.
+fn slice_list(args: &[VarValue], part: Slice) -> FuncResult {
+    if args.len() != 2 {
+        return Err("requires two arguments".to_string());
+    }
+    let cnt = args[1].to_int();
+    if cnt < 0 {
+        return Err(format!("number of items must be non-negative, got {}", cnt));
+    }
+    let lst = match &args[0] {
+        VarValue::List(v) => v.clone(),
+        VarValue::Undefined => Vec::new(),
+        v => vec![v.to_string()],
+    };
+    let cnt = (cnt as usize).min(lst.len());
+    let res = match part {
+        Slice::Head => lst[..cnt].to_vec(),
+        Slice::Tail => lst[lst.len() - cnt..].to_vec(),
+        Slice::Drop => lst[cnt..].to_vec(),
+    };
+    Ok(VarValue::List(res))
+}
+
 /// Generates a random string of a given length. First argument is the length of the string.
 /// The second argument is alphabet to generate a string(it must be longer than 10 characters).
 /// If the seconds argument is missing `LETTERS` is used(digits and lowcase Latin characters).
@@ -704,6 +1739,34 @@ fn decrement(args: &[VarValue]) -> FuncResult {
     Ok(VarValue::Int(val))
 }
 
+/// Calculates a single SHA-256 digest of a set of files: their names and contents. Every
+/// argument is a path or a list of paths(e.g., the result of `glob`). Paths are sorted, so
+/// the order of arguments does not matter. Returns the digest as a lowercase hex string.
+fn hash_files(args: &[VarValue]) -> FuncResult {
+    let mut paths: Vec<String> = Vec::new();
+    for arg in args.iter() {
+        match arg {
+            VarValue::List(lst) => paths.extend(lst.iter().cloned()),
+            _ => paths.push(arg.to_string()),
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    let mut hasher = Sha256::new();
+    for path in paths.iter() {
+        let content = match std::fs::read(path) {
+            Ok(c) => c,
+            Err(e) => return Err(format!("failed to read '{}': {}", path, e)),
+        };
+        // lengths separate names and contents, so moving bytes between them changes the digest
+        hasher.update((path.len() as u64).to_le_bytes());
+        hasher.update(path.as_bytes());
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(&content);
+    }
+    Ok(VarValue::Str(format!("{:x}", hasher.finalize())))
+}
+
 /// Returns a list of files and/or directories that match a pattern.
 /// First argument is a glob pattern.
 /// Second argument is optional:
@@ -720,17 +1783,15 @@ fn globfiles(args: &[VarValue]) -> FuncResult {
         Err(e) => return Err(e.to_string()),
     };
 
-    for entry in entries {
-        if let Ok(p) = entry {
-            if globtype == 1 && !p.is_file() {
-                continue;
-            }
-            if globtype == 2 && !p.is_dir() {
-                continue;
-            }
-            let s = p.to_string_lossy();
-            v.push(s.to_string());
+    for p in entries.flatten() {
+        if globtype == 1 && !p.is_file() {
+            continue;
+        }
+        if globtype == 2 && !p.is_dir() {
+            continue;
         }
+        let s = p.to_string_lossy();
+        v.push(s.to_string());
     }
 
     Ok(VarValue::List(v))
@@ -838,6 +1899,126 @@ fn semver_inc(args: &[VarValue]) -> FuncResult {
 #[cfg(test)]
 mod path_test {
     use super::*;
+    use crate::vm::RunOpts;
+
+    #[test]
+    fn file_hash() {
+        let dir = env::temp_dir().join("haku-hash-files");
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let a = dir.join("a.txt").to_string_lossy().to_string();
+        let b = dir.join("b.txt").to_string_lossy().to_string();
+        std::fs::write(&a, "first").unwrap();
+        std::fs::write(&b, "second").unwrap();
+        let list = [VarValue::List(vec![b.clone(), a.clone()])];
+        let h1 = hash_files(&list).unwrap().to_string();
+        assert_eq!(h1.len(), 64);
+        // the order of paths does not matter
+        assert_eq!(hash_files(&[VarValue::from(a.clone()), VarValue::from(b.clone())]).unwrap().to_string(), h1);
+        std::fs::write(&b, "changed").unwrap();
+        let h2 = hash_files(&list).unwrap().to_string();
+        assert_ne!(h1, h2);
+        // only a name changes
+        let c = dir.join("c.txt").to_string_lossy().to_string();
+        std::fs::rename(&b, &c).unwrap();
+        let h3 = hash_files(&[VarValue::List(vec![a.clone(), c.clone()])]).unwrap().to_string();
+        assert_ne!(h2, h3);
+        let r = hash_files(&list);
+        assert!(r.is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn func_list() {
+        let list = function_list();
+        let cats: Vec<&str> = list.iter().map(|(c, _)| c.as_str()).collect();
+        assert_eq!(cats, vec!["string", "path", "env", "list", "numeric", "version", "system", "script"]);
+        let (_, path) = list.iter().find(|(c, _)| c == "path").unwrap();
+        assert!(path.contains(&"glob".to_string()));
+        assert!(path.contains(&"is_file (is-file, isfile)".to_string()));
+        // every name is unique, so a function cannot shadow another one
+        let mut names: Vec<&str> = FUNCTIONS.iter().flat_map(|f| f.names.iter().copied()).collect();
+        let total = names.len();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), total);
+    }
+
+    #[test]
+    fn host_and_user() {
+        assert!(!host_name().is_empty());
+        assert!(!user_name().is_empty());
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(shell_kind("sh"), ShellKind::Unix);
+        assert_eq!(shell_kind("/usr/bin/bash"), ShellKind::Unix);
+        assert_eq!(shell_kind("cmd.exe"), ShellKind::Cmd);
+        assert_eq!(shell_kind("PowerShell"), ShellKind::PowerShell);
+        assert_eq!(shell_quote("file-1.txt", &ShellKind::Unix), "file-1.txt");
+        assert_eq!(shell_quote("", &ShellKind::Unix), "''");
+        assert_eq!(shell_quote("my file.txt", &ShellKind::Unix), "'my file.txt'");
+        assert_eq!(shell_quote("it's", &ShellKind::Unix), "'it'\\''s'");
+        assert_eq!(shell_quote("$HOME/a", &ShellKind::Unix), "'$HOME/a'");
+        assert_eq!(shell_quote("it's $x", &ShellKind::PowerShell), "'it''s $x'");
+        assert_eq!(shell_quote("say \"hi\"", &ShellKind::Cmd), "\"say \"\"hi\"\"\"");
+        let eng = Engine::new(RunOpts::new());
+        let kind = shell_kind(&eng.shell_name());
+        let res = quote_args(&eng, &[VarValue::from("a b"), VarValue::List(vec!["c".to_string(), "d e".to_string()])]);
+        let expected = format!("{} c {}", shell_quote("a b", &kind), shell_quote("d e", &kind));
+        assert_eq!(res, Ok(VarValue::Str(expected)));
+    }
+
+    #[test]
+    fn lines_args() {
+        let eng = Engine::new(RunOpts::new());
+        let kind = shell_kind(&eng.shell_name());
+        let args =
+            [VarValue::from("a.txt\r\n\nmy file.txt\n"), VarValue::List(vec!["b.txt".to_string(), "c d".to_string()])];
+        let expected = format!("a.txt {} b.txt {}", shell_quote("my file.txt", &kind), shell_quote("c d", &kind));
+        assert_eq!(lines_to_args(&eng, &args), Ok(VarValue::Str(expected)));
+        assert_eq!(lines_to_args(&eng, &[]), Ok(VarValue::from("")));
+    }
+
+    #[test]
+    fn asserts() {
+        let args = [VarValue::from("1"), VarValue::Int(1)];
+        assert_eq!(assert_cmp(&args, true), Ok(VarValue::Int(1)));
+        assert_eq!(assert_cmp(&args, false), Err("assertion failed: expected value different from '1'".to_string()));
+        let args = [VarValue::from("abc"), VarValue::from("abd")];
+        assert_eq!(assert_cmp(&args, true), Err("assertion failed: expected 'abc', got 'abd'".to_string()));
+        assert_eq!(assert_cmp(&args, false), Ok(VarValue::Int(1)));
+        let args = [VarValue::Int(2), VarValue::Int(3), VarValue::from("count")];
+        assert_eq!(assert_cmp(&args, true), Err("count: expected '2', got '3'".to_string()));
+        assert!(assert_cmp(&[VarValue::Int(2)], true).is_err());
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(json_escape("plain"), "\"plain\"");
+        assert_eq!(json_escape("say \"hi\"\nC:\\dir\t\u{1}"), "\"say \\\"hi\\\"\\nC:\\\\dir\\t\\u0001\"");
+        let mut eng = Engine::new(RunOpts::new());
+        let res = print_json(&mut eng, &[VarValue::from("a \"quoted\"\nline")]);
+        assert_eq!(res, Ok(VarValue::from("\"a \\\"quoted\\\"\\nline\"")));
+        let res = print_json(&mut eng, &[VarValue::Int(5), VarValue::List(vec!["x\"".to_string(), "y".to_string()])]);
+        assert_eq!(res, Ok(VarValue::from("[5,[\"x\\\"\",\"y\"]]")));
+        assert_eq!(print_json(&mut eng, &[]), Ok(VarValue::from("\"\"")));
+    }
+
+    #[test]
+    fn cpus() {
+        assert!(cpu_count() >= 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn os_release() {
+        let content = "NAME=\"Ubuntu\"\nVERSION_ID=\"22.04\"\nID=ubuntu\nID_LIKE=debian\n";
+        assert_eq!(parse_os_release(content), "ubuntu");
+        let content = "NAME=\"Fedora Linux\"\nID_LIKE=\"rhel\"\n  ID=\"Fedora\"\n";
+        assert_eq!(parse_os_release(content), "fedora");
+        assert_eq!(parse_os_release("NAME=Unknown\nID_LIKE=debian\n"), "");
+    }
 
     #[test]
     fn extract() {
@@ -935,6 +2116,92 @@ mod path_test {
         assert_eq!(r, Ok(VarValue::from("abc===")));
         let r = trim_string(&v, Where::Right);
         assert_eq!(r, Ok(VarValue::from("++abc===")));
+
+        let v = vec![VarValue::from("+=+abc=+= "), VarValue::from("+=")];
+        let r = trim_string(&v, Where::All);
+        assert_eq!(r, Ok(VarValue::from("abc=+= ")));
+        let v = vec![VarValue::from("+=+abc=+="), VarValue::from("=+")];
+        let r = trim_string(&v, Where::All);
+        assert_eq!(r, Ok(VarValue::from("abc")));
+        let r = trim_string(&v, Where::Left);
+        assert_eq!(r, Ok(VarValue::from("abc=+=")));
+        let r = trim_string(&v, Where::Right);
+        assert_eq!(r, Ok(VarValue::from("+=+abc")));
+        let v = vec![VarValue::from("+=+abc=+="), VarValue::from("")];
+        let r = trim_string(&v, Where::All);
+        assert_eq!(r, Ok(VarValue::from("+=+abc=+=")));
+    }
+
+    #[test]
+    fn squeezes() {
+        let v = vec![VarValue::from("  a \t\tb\n\n  c   d\t ")];
+        let r = squeeze(&v);
+        assert_eq!(r, Ok(VarValue::from("a b c d")));
+        let v = vec![VarValue::from("abc")];
+        let r = squeeze(&v);
+        assert_eq!(r, Ok(VarValue::from("abc")));
+        let v = vec![VarValue::from(" \t\n ")];
+        let r = squeeze(&v);
+        assert_eq!(r, Ok(VarValue::from("")));
+        let v = vec![VarValue::from("//usr///local//bin/"), VarValue::from("/")];
+        let r = squeeze(&v);
+        assert_eq!(r, Ok(VarValue::from("/usr/local/bin/")));
+        let v = vec![VarValue::from("a  b"), VarValue::from("")];
+        let r = squeeze(&v);
+        assert_eq!(r, Ok(VarValue::from("a  b")));
+    }
+
+    #[test]
+    fn wraps() {
+        let v = vec![VarValue::from("the quick brown fox jumps over the lazy dog"), VarValue::from(10)];
+        let r = wrap(&v);
+        assert_eq!(r, Ok(VarValue::from("the quick\nbrown fox\njumps over\nthe lazy\ndog")));
+        let v = vec![VarValue::from("a verylongwordhere b"), VarValue::from(5)];
+        let r = wrap(&v);
+        assert_eq!(r, Ok(VarValue::from("a\nverylongwordhere\nb")));
+        let v = vec![VarValue::from("ab cd\n\nef  gh"), VarValue::from(5)];
+        let r = wrap(&v);
+        assert_eq!(r, Ok(VarValue::from("ab cd\n\nef gh")));
+        let v = vec![VarValue::from("абв где жзи"), VarValue::from(7)];
+        let r = wrap(&v);
+        assert_eq!(r, Ok(VarValue::from("абв где\nжзи")));
+        let v = vec![VarValue::from("abc"), VarValue::from(0)];
+        assert!(wrap(&v).is_err());
+        let v = vec![VarValue::from("abc")];
+        assert!(wrap(&v).is_err());
+    }
+
+    #[test]
+    fn indents() {
+        let v = vec![VarValue::from("a:\n  b: 1\n\nc: 2"), VarValue::from(2)];
+        let r = indent(&v);
+        assert_eq!(r, Ok(VarValue::from("  a:\n    b: 1\n\n  c: 2")));
+        let v = vec![VarValue::from("line 1\n\nline 2"), VarValue::from("> ")];
+        let r = indent(&v);
+        assert_eq!(r, Ok(VarValue::from("> line 1\n\n> line 2")));
+        let v = vec![VarValue::from("line 1\n\nline 2"), VarValue::from("> "), VarValue::from(1)];
+        let r = indent(&v);
+        assert_eq!(r, Ok(VarValue::from("> line 1\n> \n> line 2")));
+        let v = vec![VarValue::from("abc"), VarValue::from(-1)];
+        assert!(indent(&v).is_err());
+        let v = vec![VarValue::from("abc")];
+        assert!(indent(&v).is_err());
+    }
+
+    #[test]
+    fn dedents() {
+        let v = vec![VarValue::from("    a:\n      b: 1\n\n    c: 2")];
+        let r = dedent(&v);
+        assert_eq!(r, Ok(VarValue::from("a:\n  b: 1\n\nc: 2")));
+        let v = vec![VarValue::from("\t  one\n\t two\n   \n\t\tthree")];
+        let r = dedent(&v);
+        assert_eq!(r, Ok(VarValue::from("  one\n two\n\n\tthree")));
+        let v = vec![VarValue::from("  a\nb")];
+        let r = dedent(&v);
+        assert_eq!(r, Ok(VarValue::from("  a\nb")));
+        let v = vec![VarValue::from("")];
+        let r = dedent(&v);
+        assert_eq!(r, Ok(VarValue::from("")));
     }
 
     #[test]
@@ -1121,6 +2388,44 @@ mod path_test {
         assert_eq!(r, Ok(VarValue::List(vec!["bc d".to_string(), String::new()])));
     }
 
+    #[test]
+    fn sort_nums() {
+        let to_list = |v: &[&str]| VarValue::List(v.iter().map(|s| s.to_string()).collect());
+        assert_eq!(sort_num(&[]), Ok(to_list(&[])));
+        let v = vec![to_list(&["10", "abc", "-3", "2", "", "x1", "2"])];
+        assert_eq!(sort_num(&v), Ok(to_list(&["-3", "2", "2", "10", "abc", "", "x1"])));
+        let v = vec![VarValue::from(7), to_list(&["z", "100"]), VarValue::from("a"), VarValue::from(" 5")];
+        assert_eq!(sort_num(&v), Ok(to_list(&[" 5", "7", "100", "z", "a"])));
+        let v = vec![to_list(&["b", "a"])];
+        assert_eq!(sort_num(&v), Ok(to_list(&["b", "a"])));
+    }
+
+    #[test]
+    fn slices() {
+        let to_list = |v: &[&str]| VarValue::List(v.iter().map(|s| s.to_string()).collect());
+        let lst = to_list(&["a", "b", "c"]);
+        assert!(slice_list(&[VarValue::from("a")], Slice::Head).is_err());
+        assert!(slice_list(&[lst.clone(), VarValue::Int(-1)], Slice::Tail).is_err());
+
+        let n = |i: i64| VarValue::Int(i);
+        assert_eq!(slice_list(&[lst.clone(), n(0)], Slice::Head), Ok(to_list(&[])));
+        assert_eq!(slice_list(&[lst.clone(), n(2)], Slice::Head), Ok(to_list(&["a", "b"])));
+        assert_eq!(slice_list(&[lst.clone(), n(3)], Slice::Head), Ok(to_list(&["a", "b", "c"])));
+        assert_eq!(slice_list(&[lst.clone(), n(10)], Slice::Head), Ok(to_list(&["a", "b", "c"])));
+
+        assert_eq!(slice_list(&[lst.clone(), n(0)], Slice::Tail), Ok(to_list(&[])));
+        assert_eq!(slice_list(&[lst.clone(), n(2)], Slice::Tail), Ok(to_list(&["b", "c"])));
+        assert_eq!(slice_list(&[lst.clone(), n(10)], Slice::Tail), Ok(to_list(&["a", "b", "c"])));
+
+        assert_eq!(slice_list(&[lst.clone(), n(0)], Slice::Drop), Ok(to_list(&["a", "b", "c"])));
+        assert_eq!(slice_list(&[lst.clone(), n(2)], Slice::Drop), Ok(to_list(&["c"])));
+        assert_eq!(slice_list(&[lst.clone(), n(3)], Slice::Drop), Ok(to_list(&[])));
+        assert_eq!(slice_list(&[lst, n(10)], Slice::Drop), Ok(to_list(&[])));
+
+        assert_eq!(slice_list(&[VarValue::from("abc"), n(1)], Slice::Head), Ok(to_list(&["abc"])));
+        assert_eq!(slice_list(&[VarValue::Undefined, n(1)], Slice::Tail), Ok(to_list(&[])));
+    }
+
     #[test]
     fn rand() {
         let v = vec![VarValue::from(10)];
@@ -1132,7 +2437,7 @@ mod path_test {
         let v = vec![VarValue::from(10), VarValue::from("0123456789")];
         let s1 = rand_string(&v);
         for chr in s1.unwrap().to_string().chars() {
-            assert!(chr >= '0' && chr <= '9');
+            assert!(chr.is_ascii_digit());
         }
     }
 
@@ -1,4 +1,4 @@
-use std::i64;
+use std::fmt;
 
 use pest::iterators::{Pair, Pairs};
 
@@ -20,12 +20,43 @@ pub enum Seq {
     Var(String),
 }
 
-/// external command and recipe flags. Flags are added as prefixes of a script lines.
+/// Describes a recipe dependency
+#[derive(Debug, Clone)]
+pub struct RecipeDep {
+    /// name of the recipe to execute
+    pub name: String,
+    /// arguments to pass to the recipe instead of free arguments. `None` if the dependency
+    /// is declared without parentheses
+    pub args: Option<Vec<String>>,
+    /// condition to run the dependency(set by `#[dep_if]` attribute). `None` - always run
+    pub cond: Option<Op>,
+}
+
+impl fmt::Display for RecipeDep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.args {
+            None => write!(f, "{}", self.name),
+            Some(args) => write!(f, "{}({})", self.name, args.join(", ")),
+        }
+    }
+}
+
+// external command and recipe flags. Flags are added as prefixes of a script lines.
 
 /// Do not print the command before execution (`@`)
 pub const FLAG_QUIET: u32 = 1;
 /// Do not interrupt the execution if external command has failed(`-`)
 pub const FLAG_PASS: u32 = 2;
+/// Run the recipe at most once per application run(`#[once]` attribute)
+pub const FLAG_ONCE: u32 = 4;
+/// Discard changes of environment variables made by the recipe(`#[isolate-env]` attribute)
+pub const FLAG_ISOLATE_ENV: u32 = 8;
+
+/// Combines recipe-wide flags and flags of a script line: a flag is on if either the recipe
+/// or the line has it, e.g. a redundant `@` in a quiet recipe keeps the line quiet
+pub fn line_flags(recipe_flags: u32, flags: u32) -> u32 {
+    recipe_flags | flags
+}
 
 /// Returns true if a value `flags` has a `flag` on
 pub fn is_flag_on(flags: u32, flag: u32) -> bool {
@@ -35,8 +66,8 @@ pub fn is_flag_on(flags: u32, flag: u32) -> bool {
 /// Operations processed by the engine internally
 #[derive(Debug, Clone)]
 pub enum Op {
-    /// Comment line (ignored) - comment text: starts with `#` or `//`
-    Comment(String),
+    /// Comment line (ignored): starts with `#` or `//`
+    Comment,
     /// Documentation comment - comment text: starts with `##`. Used as a recipe description
     /// when it is right before the recipe, ignored in other cases
     DocComment(String),
@@ -50,7 +81,7 @@ pub enum Op {
     /// List of features which enable a following block of code
     ///
     /// * passed - whether all mentioned features are on (i.e., the block must be executed or
-    /// ignored)
+    ///   ignored)
     /// * string representation of a condition to enable the following code block
     Feature(bool, String),
     /// Execute a function
@@ -72,10 +103,18 @@ pub enum Op {
     ///
     /// Example: `a ?= 10`
     DefAssign(String, Vec<Op>),
+    /// Change a variable value in place using arithmetic operation
+    ///
+    /// * variable name
+    /// * operation: one of `+`, `-`, `*`, `/`
+    /// * expression
+    ///
+    /// Example: `i += 1`
+    CompoundAssign(String, String, Vec<Op>),
     /// Assign the first truthy value from the list of values
     ///
     /// * check - if it is true, the new value is calculated and assigned only if the current
-    /// variable value is undefined or a falsy one
+    ///   variable value is undefined or a falsy one
     /// * variable name
     /// * list of values
     ///
@@ -93,6 +132,12 @@ pub enum Op {
     /// A list of values joined with logical AND. The result of the expression is a logical value
     /// 0 or 1 (1 - if all values are truthy ones)
     AndExpr(Vec<Op>),
+    /// A list of values joined with logical OR. The result of the expression is a logical value
+    /// 0 or 1 (1 - if any value is truthy one)
+    OrExpr(Vec<Op>),
+    /// A list of values joined with `~`. The result is a string: all values converted to
+    /// strings and concatenated
+    Concat(Vec<Op>),
     /// ELSE statement
     Else,
     /// BREAK statement
@@ -103,22 +148,25 @@ pub enum Op {
     Return,
     /// WHILE statement - the loop enter condition
     While(Vec<Op>),
-    /// FOR statement - range of for values
-    For(String, Seq),
+    /// FOR statement - loop variable, range of for values, and whether the loop goes on
+    /// after a failed command(`#[no-fail-fast]`)
+    For(String, Seq, bool),
     /// A recipe declaration
     ///
     /// * name
     /// * flags (e.g., "echo off" or "ignore shell errors")
     /// * list of local recipe variable names
     /// * list of recipes this one depends on (they are executed before this recipe)
+    /// * recipe to execute after this one even if it fails(set by `#[finally]` attribute)
     ///
-    /// Example: `recipe-name loc_var1 +loc_var2: dependency1 dependency2
-    Recipe(String, u32, Vec<String>, Vec<String>),
+    /// Example: `recipe-name loc_var1 +loc_var2: dependency1 dependency2(arg1, arg2)
+    Recipe(String, u32, Vec<String>, Vec<RecipeDep>, Option<String>),
     /// Execute external command using the current shell
     ///
     /// * execution flags (e.g., "echo off" or "ignore shell errors")
+    /// * shell to use for this line only (empty - use the current shell)
     /// * command line to execute
-    Shell(u32, String),
+    Shell(u32, Vec<String>, String),
 
     // here goes a list of basic building blocks of any expression
     /// Integer value(i64)
@@ -131,6 +179,10 @@ pub enum Op {
     Exec(String),
     /// Logical negation of a value
     Not(Vec<Op>),
+    /// A function argument which is expanded into a few arguments if its value is a list
+    ///
+    /// Example: `contains($s, ...$list)`
+    Spread(Vec<Op>),
     /// change working directory: flags, directory
     Cd(u32, String),
     /// PAUSE statement
@@ -175,16 +227,56 @@ pub fn build_recipe(p: Pairs<Rule>) -> Result<Op, HakuError> {
                 }
             }
             Rule::sec_deps => {
-                let inner = s.into_inner();
-                for s_in in inner {
-                    deps.push(s_in.as_str().to_string());
+                for dep in s.into_inner() {
+                    deps.push(build_recipe_dep(dep));
                 }
             }
             _ => { /* skip all other parts like sec_sep */ }
         }
     }
 
-    Ok(Op::Recipe(name, flags, vars, deps))
+    Ok(Op::Recipe(name, flags, vars, deps, None))
+}
+
+/// Parses a recipe dependency: a recipe name with optional list of arguments
+fn build_recipe_dep(p: Pair<Rule>) -> RecipeDep {
+    let mut dep = RecipeDep { name: String::new(), args: None, cond: None };
+    for s in p.into_inner() {
+        match s.as_rule() {
+            Rule::ident => dep.name = s.as_str().to_string(),
+            Rule::dep_args => {
+                let args = s.into_inner().map(|a| strip_quotes(a.as_str()).to_string()).collect();
+                dep.args = Some(args);
+            }
+            _ => unimplemented!(),
+        }
+    }
+    dep
+}
+
+/// Parses a conditional dependency attribute `#[dep_if(condition, dependency)]`.
+/// Returns the dependency name and its condition
+pub fn build_dep_if(p: Pairs<Rule>) -> Result<(String, Op), HakuError> {
+    let mut name = String::new();
+    let mut cond = Op::Int(1);
+    for s in p {
+        match s.as_rule() {
+            Rule::cond => cond = build_condition(s.into_inner())?,
+            Rule::dep_if_name => name = strip_quotes(s.as_str()).to_string(),
+            _ => {}
+        }
+    }
+    Ok((name, cond))
+}
+
+/// Parses a cleanup attribute `#[finally(recipe)]`. Returns the name of the cleanup recipe
+pub fn build_finally(p: Pairs<Rule>) -> String {
+    for s in p {
+        if let Rule::finally_name = s.as_rule() {
+            return strip_quotes(s.as_str()).to_string();
+        }
+    }
+    unreachable!()
 }
 
 /// Parses a script line with cd statement
@@ -229,19 +321,40 @@ pub fn build_error(p: Pairs<Rule>) -> Result<Op, HakuError> {
     Ok(Op::Error(cmd))
 }
 
-/// Parses a script line with external shell execution
+/// Returns the argument that makes an interpreter execute a command passed in the next argument
+fn shell_exec_flag(prog: &str) -> &'static str {
+    let name = prog.rsplit(['/', '\\']).next().unwrap_or(prog).to_lowercase();
+    let name = name.trim_end_matches(".exe");
+    match name {
+        "cmd" => "/C",
+        "node" | "perl" | "ruby" => "-e",
+        _ => "-c",
+    }
+}
+
+/// Parses a script line with external shell execution. A line can start with a shell
+/// that overrides the current one: `!python: print('hi')`. A shell path with spaces must be
+/// in double quotes. If the shell is a single word, the argument to execute a command(e.g., `-c`) is added automatically.
 pub fn build_shell_cmd(p: Pairs<Rule>) -> Result<Op, HakuError> {
     let mut flags: u32 = 0;
     let mut cmd = String::new();
+    let mut shell: Vec<String> = Vec::new();
     for s in p {
         match s.as_rule() {
             Rule::cmd_flags => flags = str_to_flags(s.as_str()),
-            Rule::shell_cmd => cmd = s.as_str().to_string(),
+            Rule::shell_override => {
+                shell = s.into_inner().map(|a| strip_quotes(a.as_str()).to_string()).collect();
+                if shell.len() == 1 {
+                    let flag = shell_exec_flag(&shell[0]);
+                    shell.push(flag.to_string());
+                }
+            }
+            Rule::shell_cmd => cmd = s.as_str().trim_start().to_string(),
             _ => {}
         }
     }
 
-    Ok(Op::Shell(flags, cmd))
+    Ok(Op::Shell(flags, shell, cmd))
 }
 
 /// Removes trailing and leading quotes from a string:
@@ -277,9 +390,9 @@ pub fn strip_var_deco(s: &str) -> &str {
 /// * ident1 ident2
 /// * `\`dir *.txt\``
 /// * `${var-name}` or `$var-name`
-fn build_seq(p: Pairs<Rule>) -> Result<Seq, HakuError> {
+fn build_seq(mut p: Pairs<Rule>) -> Result<Seq, HakuError> {
     let text = p.as_str().to_owned();
-    for pair in p {
+    if let Some(pair) = p.next() {
         match pair.as_rule() {
             Rule::squoted | Rule::dquoted => return Ok(Seq::Str(strip_quotes(pair.as_str()).to_string())),
             Rule::exec => return Ok(Seq::Exec(strip_quotes(pair.as_str()).to_string())),
@@ -345,7 +458,7 @@ fn build_seq(p: Pairs<Rule>) -> Result<Seq, HakuError> {
                 let mut var_name = String::new();
                 for ids in pair.into_inner() {
                     match ids.as_rule() {
-                        Rule::ident => var_name = ids.as_str().to_owned(),
+                        Rule::ident | Rule::all_args => var_name = ids.as_str().to_owned(),
                         _ => unimplemented!(),
                     }
                 }
@@ -371,7 +484,7 @@ pub fn build_for(p: Pairs<Rule>) -> Result<Op, HakuError> {
             _ => {}
         }
     }
-    Ok(Op::For(var, seq))
+    Ok(Op::For(var, seq, false))
 }
 
 /// Parses a single function or expression value
@@ -382,9 +495,10 @@ fn build_arg_value(p: Pair<Rule>) -> Result<Op, HakuError> {
                 return Ok(Op::Int(i));
             }
         }
+        Rule::bool_lit => return Ok(Op::Int(if p.as_str().eq_ignore_ascii_case("true") { 1 } else { 0 })),
         Rule::exec => return Ok(Op::Exec(strip_quotes(p.as_str()).to_string())),
         Rule::string => {
-            for in_p in p.into_inner() {
+            if let Some(in_p) = p.into_inner().next() {
                 match in_p.as_rule() {
                     Rule::squoted | Rule::dquoted => return Ok(Op::Str(strip_quotes(in_p.as_str()).to_string())),
                     _ => unimplemented!(),
@@ -393,6 +507,11 @@ fn build_arg_value(p: Pair<Rule>) -> Result<Op, HakuError> {
         }
         Rule::var => return Ok(Op::Var(strip_var_deco(p.as_str()).to_string())),
         Rule::func => return build_func(p.into_inner()),
+        Rule::group => {
+            if let Some(cond) = p.into_inner().next() {
+                return build_condition(cond.into_inner());
+            }
+        }
         Rule::dquoted | Rule::squoted => return Ok(Op::Str(strip_quotes(p.as_str()).to_string())),
         _ => {
             println!("{:?}", p);
@@ -442,6 +561,14 @@ fn build_arglist(p: Pairs<Rule>) -> Result<Vec<Op>, HakuError> {
     for pair in p {
         match pair.as_rule() {
             Rule::arg => vec.push(build_arg(pair.into_inner())?),
+            Rule::concat => vec.push(build_concat(pair.into_inner())?),
+            Rule::spread => {
+                let val = pair.as_str().to_string();
+                match pair.into_inner().next() {
+                    Some(pp) => vec.push(Op::Spread(vec![build_arg_value(pp)?])),
+                    None => return Err(HakuError::ParseError(val, String::new())),
+                }
+            }
             _ => unimplemented!(),
         }
     }
@@ -473,6 +600,7 @@ fn build_s_expr(p: Pairs<Rule>) -> Result<Op, HakuError> {
     for pair in p {
         match pair.as_rule() {
             Rule::arg => v.push(build_arg(pair.into_inner())?),
+            Rule::concat => v.push(build_concat(pair.into_inner())?),
             Rule::cmp_op => cmp = pair.as_str().to_string(),
             _ => {
                 println!("{:?}", pair);
@@ -487,6 +615,19 @@ fn build_s_expr(p: Pairs<Rule>) -> Result<Op, HakuError> {
     }
 }
 
+/// Parses concatenation: a few values joined with `~`
+fn build_concat(p: Pairs<Rule>) -> Result<Op, HakuError> {
+    let mut v = Vec::new();
+    for pair in p {
+        match pair.as_rule() {
+            Rule::arg => v.push(build_arg(pair.into_inner())?),
+            Rule::concat_op => {} // do nothing
+            _ => unimplemented!(),
+        }
+    }
+    Ok(Op::Concat(v))
+}
+
 /// Parses AND expression: one or few basic expressions joined with AND(&&)
 fn build_and_expr(p: Pairs<Rule>) -> Result<Op, HakuError> {
     let mut v = Vec::new();
@@ -507,7 +648,7 @@ fn build_and_expr(p: Pairs<Rule>) -> Result<Op, HakuError> {
 }
 
 /// Parses OR expression: one or few AND expressions joined with OR(||)
-fn build_condition(p: Pairs<Rule>) -> Result<Vec<Op>, HakuError> {
+fn build_condition(p: Pairs<Rule>) -> Result<Op, HakuError> {
     let mut v = Vec::new();
     for pair in p {
         match pair.as_rule() {
@@ -519,7 +660,7 @@ fn build_condition(p: Pairs<Rule>) -> Result<Vec<Op>, HakuError> {
             }
         }
     }
-    Ok(v)
+    Ok(Op::OrExpr(v))
 }
 
 /// Parses the entire expression
@@ -528,10 +669,7 @@ fn build_expr(p: Pairs<Rule>) -> Result<Vec<Op>, HakuError> {
     for pair in p {
         match pair.as_rule() {
             Rule::andexpr => v.push(build_and_expr(pair.into_inner())?),
-            Rule::cond => {
-                let mut cexpr = build_condition(pair.into_inner())?;
-                v.append(&mut cexpr);
-            }
+            Rule::cond => v.push(build_condition(pair.into_inner())?),
             _ => {
                 println!("{:?}", pair);
                 unimplemented!();
@@ -571,6 +709,24 @@ pub fn build_def_assign(p: Pairs<Rule>) -> Result<Op, HakuError> {
     unreachable!();
 }
 
+/// Parses compound assignment statement: `a += 2` or `$a += 2`
+pub fn build_compound_assign(p: Pairs<Rule>) -> Result<Op, HakuError> {
+    let mut name = String::new();
+    let mut cmp = String::new();
+    for pair in p {
+        match pair.as_rule() {
+            Rule::ident => name = pair.as_str().to_string(),
+            Rule::var => name = strip_var_deco(pair.as_str()).to_string(),
+            Rule::compound_op => cmp = pair.as_str().trim_end_matches('=').to_string(),
+            Rule::assign_expr => {
+                return Ok(Op::CompoundAssign(name, cmp, build_expr(pair.into_inner())?));
+            }
+            _ => {}
+        }
+    }
+    unreachable!();
+}
+
 /// Parses assignment statement with variants: `a = $b ? $c`
 pub fn build_either_assign(p: Pairs<Rule>) -> Result<Op, HakuError> {
     let mut name = String::new();
@@ -609,7 +765,7 @@ pub fn build_either_def_assign(p: Pairs<Rule>) -> Result<Op, HakuError> {
 pub fn build_if(p: Pairs<Rule>) -> Result<Op, HakuError> {
     for pair in p {
         if let Rule::cond = pair.as_rule() {
-            return Ok(Op::If(build_condition(pair.into_inner())?));
+            return Ok(Op::If(vec![build_condition(pair.into_inner())?]));
         }
     }
     unreachable!()
@@ -619,7 +775,7 @@ pub fn build_if(p: Pairs<Rule>) -> Result<Op, HakuError> {
 pub fn build_elseif(p: Pairs<Rule>) -> Result<Op, HakuError> {
     for pair in p {
         if let Rule::cond = pair.as_rule() {
-            return Ok(Op::ElseIf(build_condition(pair.into_inner())?));
+            return Ok(Op::ElseIf(vec![build_condition(pair.into_inner())?]));
         }
     }
     unreachable!()
@@ -629,7 +785,7 @@ pub fn build_elseif(p: Pairs<Rule>) -> Result<Op, HakuError> {
 pub fn build_while(p: Pairs<Rule>) -> Result<Op, HakuError> {
     for pair in p {
         if let Rule::cond = pair.as_rule() {
-            return Ok(Op::While(build_condition(pair.into_inner())?));
+            return Ok(Op::While(vec![build_condition(pair.into_inner())?]));
         }
     }
     unreachable!()
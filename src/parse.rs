@@ -1,13 +1,16 @@
 use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::Read;
+use std::mem;
 
+use pest::error::{Error, LineColLocation};
 use pest::Parser;
 
 use crate::errors::HakuError;
-use crate::feature::process_feature;
+use crate::feature::{expand_features, implication_cycle, process_feature, unmet_features, Implication};
 use crate::ops::{
-    build_assign, build_cd, build_def_assign, build_either_assign, build_either_def_assign, build_elseif, build_error,
-    build_for, build_func, build_if, build_include, build_recipe, build_shell_cmd, build_while, Op,
+    build_assign, build_cd, build_compound_assign, build_def_assign, build_dep_if, build_either_assign,
+    build_either_def_assign, build_elseif, build_error, build_finally, build_for, build_func, build_if, build_include,
+    build_recipe, build_shell_cmd, build_while, Op, FLAG_ISOLATE_ENV, FLAG_ONCE, FLAG_QUIET,
 };
 use crate::vm::RunOpts;
 
@@ -15,6 +18,49 @@ use crate::vm::RunOpts;
 #[grammar = "haku.pest"]
 pub struct TaskParser;
 
+/// Evaluates feature directives of a disabled recipe(e.g., `#[os(linux)]#[feature(zip)]`) and
+/// returns the conditions that are not satisfied with the current options. A directive that
+/// cannot be evaluated is returned as is.
+pub fn disabled_reasons(feat: &str, opts: &RunOpts) -> Vec<String> {
+    let mut reasons = Vec::new();
+    for directive in feat.split_inclusive(']').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+        let unmet = TaskParser::parse(Rule::feature_list, directive)
+            .ok()
+            .and_then(|mut pairs| pairs.next())
+            .and_then(|pair| unmet_features(pair.into_inner(), opts).ok());
+        match unmet {
+            Some(mut v) => reasons.append(&mut v),
+            None => reasons.push(directive.to_string()),
+        }
+    }
+    reasons
+}
+
+/// Collects all `feature NAME implies NAME1, NAME2` directives of a script. Returns an error
+/// if the implications contain a cycle
+fn feature_implications(src: &str) -> Result<Vec<Implication>, HakuError> {
+    let mut rules = Vec::new();
+    for (idx, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if !matches!(line.get(..8), Some(s) if s.eq_ignore_ascii_case("feature ")) {
+            continue;
+        }
+        if let Some(pair) = TaskParser::parse(Rule::feature_implies, line).ok().and_then(|mut p| p.next()) {
+            let mut names: Vec<String> = pair.into_inner().map(|p| p.as_str().to_lowercase()).collect();
+            let from = names.remove(0);
+            rules.push(Implication { from, to: names, line: idx });
+        }
+    }
+    if let Some(cycle) = implication_cycle(&rules) {
+        let (line, idx) = rules
+            .iter()
+            .find(|r| r.from == cycle[0])
+            .map_or((String::new(), 0), |r| (src.lines().nth(r.line).unwrap_or("").trim().to_string(), r.line));
+        return Err(HakuError::FeatureCycleError(cycle.join(" -> "), HakuError::error_extra("", &line, idx)));
+    }
+    Ok(rules)
+}
+
 /// Disabled recipe description
 #[derive(Clone, Debug)]
 pub struct DisabledRecipe {
@@ -39,13 +85,26 @@ pub(crate) struct OpItem {
 
 /// A single script description
 pub(crate) struct HakuFile {
+    /// path to the script file (empty if the script is loaded from a string)
+    pub(crate) path: String,
     /// list of lines that can be executed (all comment and disabled code are removed)
     pub(crate) ops: Vec<OpItem>,
     /// list of disabled recipes (for list command)
     pub(crate) disabled: Vec<DisabledRecipe>,
     /// list of all user-defined features found in the script
     pub(crate) user_feats: Vec<String>,
+    /// features enabled while parsing the script: the ones passed by a caller and all
+    /// features they imply
+    pub(crate) feats: Vec<String>,
     pub(crate) orig_lines: Vec<String>,
+    /// recipe flags set by attributes(e.g., `#[quiet]`) for the next recipe
+    attr_flags: u32,
+    /// dependency conditions set by `#[dep_if]` attributes for the next recipe
+    dep_conds: Vec<(String, Op)>,
+    /// cleanup recipe set by `#[finally]` attribute for the next recipe
+    cleanup: Option<String>,
+    /// `#[no-fail-fast]` attribute is set for the next FOR statement
+    no_fail_fast: bool,
 }
 
 /// What to skip while parsing the script
@@ -99,32 +158,108 @@ impl DeadState {
     }
 }
 
+/// Returns the column where parsing failed(starting from 1)
+fn error_column(e: &Error<Rule>) -> usize {
+    match e.line_col {
+        LineColLocation::Pos((_, col)) => col,
+        LineColLocation::Span((_, col), _) => col,
+    }
+}
+
+/// Returns `true` if a script line continues on the next line: the line ends with `\` that
+/// is neither escaped(`\\` is a backslash) nor inside a quoted string
+fn is_continued(line: &str) -> bool {
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    let mut last_slash = false;
+    for c in line.chars() {
+        last_slash = false;
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => {
+                escaped = true;
+                last_slash = true;
+            }
+            '"' | '\'' | '`' => match quote {
+                None => quote = Some(c),
+                Some(q) if q == c => quote = None,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    last_slash && quote.is_none()
+}
+
 impl HakuFile {
     pub(crate) fn new() -> Self {
-        HakuFile { ops: Vec::new(), disabled: Vec::new(), user_feats: Vec::new(), orig_lines: Vec::new() }
+        HakuFile {
+            path: String::new(),
+            ops: Vec::new(),
+            disabled: Vec::new(),
+            user_feats: Vec::new(),
+            feats: Vec::new(),
+            orig_lines: Vec::new(),
+            attr_flags: 0,
+            dep_conds: Vec::new(),
+            cleanup: None,
+            no_fail_fast: false,
+        }
+    }
+
+    /// Returns `true` if the script ends with `#[no-fail-fast]` attribute that has no FOR
+    /// statement after it
+    pub(crate) fn has_dangling_loop_attr(&self) -> bool {
+        self.no_fail_fast
     }
 
     /// Parses a single script line. Each line must contain only one rule(command/statement)
     fn process_line(&mut self, line: &str, idx: usize, opts: &RunOpts) -> Result<(), HakuError> {
+        if !opts.comments.is_empty() {
+            // `#` is always processed by the grammar: it may be an attribute or a doc comment
+            if opts.comments.iter().any(|c| c != "#" && line.starts_with(c.as_str())) {
+                self.ops.push(OpItem { op: Op::Comment, line: idx });
+                return Ok(());
+            }
+            // `//` comments are disabled: the line is executed by a shell
+            if line.starts_with("//") {
+                let mut pairs = match TaskParser::parse(Rule::shell_stmt, line) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        let col = error_column(&e);
+                        return Err(HakuError::ParseError(line.to_string(), HakuError::error_pointer(line, idx, col)));
+                    }
+                };
+                if let Some(pair) = pairs.next() {
+                    self.ops.push(OpItem { op: build_shell_cmd(pair.into_inner())?, line: idx });
+                }
+                return Ok(());
+            }
+        }
         let res = TaskParser::parse(Rule::expression, line);
 
         let pairs = match res {
             Err(e) => {
-                let msg = format!("'{}': {}", line, e.to_string());
-                return Err(HakuError::ParseError(msg, HakuError::error_extra("", line, idx)));
+                let col = error_column(&e);
+                return Err(HakuError::ParseError(line.to_string(), HakuError::error_pointer(line, idx, col)));
             }
             Ok(p) => p,
         };
         let mut feat_list: Vec<String> = Vec::new();
         for pair in pairs {
+            let attr_target = matches!(pair.as_rule(), Rule::for_stmt | Rule::comment | Rule::doc_comment);
+            if self.no_fail_fast && !attr_target {
+                return Err(HakuError::LoopAttrError(HakuError::error_extra("", line, idx)));
+            }
             match pair.as_rule() {
                 Rule::shell_stmt => {
                     self.ops.push(OpItem { op: build_shell_cmd(pair.into_inner())?, line: idx });
                 }
                 Rule::comment => {
-                    let mut inner = pair.into_inner();
-                    let s = inner.next().unwrap().as_str();
-                    self.ops.push(OpItem { op: Op::Comment(s.to_owned()), line: idx });
+                    self.ops.push(OpItem { op: Op::Comment, line: idx });
                 }
                 Rule::doc_comment => {
                     let mut inner = pair.into_inner();
@@ -164,11 +299,18 @@ impl HakuFile {
                 Rule::assign => {
                     self.ops.push(OpItem { op: build_assign(pair.into_inner())?, line: idx });
                 }
+                Rule::compound_assign => {
+                    self.ops.push(OpItem { op: build_compound_assign(pair.into_inner())?, line: idx });
+                }
                 Rule::while_stmt => {
                     self.ops.push(OpItem { op: build_while(pair.into_inner())?, line: idx });
                 }
                 Rule::for_stmt => {
-                    self.ops.push(OpItem { op: build_for(pair.into_inner())?, line: idx });
+                    let mut op = build_for(pair.into_inner())?;
+                    if let Op::For(_, _, ref mut keep_going) = op {
+                        *keep_going = mem::take(&mut self.no_fail_fast);
+                    }
+                    self.ops.push(OpItem { op, line: idx });
                 }
                 Rule::if_stmt => {
                     self.ops.push(OpItem { op: build_if(pair.into_inner())?, line: idx });
@@ -183,7 +325,48 @@ impl HakuFile {
                     self.ops.push(OpItem { op: Op::Return, line: idx });
                 }
                 Rule::recipe => {
-                    self.ops.push(OpItem { op: build_recipe(pair.into_inner())?, line: idx });
+                    let mut op = build_recipe(pair.into_inner())?;
+                    if let Op::Recipe(ref name, ref mut flags, _, ref mut deps, ref mut cleanup) = op {
+                        *flags |= self.attr_flags;
+                        *cleanup = self.cleanup.take();
+                        for (dep_name, cond) in self.dep_conds.drain(..) {
+                            let mut found = false;
+                            for dep in deps.iter_mut().filter(|d| d.name == dep_name) {
+                                dep.cond = Some(cond.clone());
+                                found = true;
+                            }
+                            if !found {
+                                return Err(HakuError::DepConditionError(
+                                    dep_name,
+                                    name.clone(),
+                                    HakuError::error_extra("", line, idx),
+                                ));
+                            }
+                        }
+                    }
+                    self.attr_flags = 0;
+                    self.ops.push(OpItem { op, line: idx });
+                }
+                Rule::recipe_attr => {
+                    let attr = pair.into_inner().as_str().to_lowercase();
+                    self.attr_flags |= match attr.as_str() {
+                        "once" => FLAG_ONCE,
+                        "isolate-env" | "isolate_env" => FLAG_ISOLATE_ENV,
+                        _ => FLAG_QUIET,
+                    };
+                    self.ops.push(OpItem { op: Op::Comment, line: idx });
+                }
+                Rule::loop_attr => {
+                    self.no_fail_fast = true;
+                    self.ops.push(OpItem { op: Op::Comment, line: idx });
+                }
+                Rule::finally_attr => {
+                    self.cleanup = Some(build_finally(pair.into_inner()));
+                    self.ops.push(OpItem { op: Op::Comment, line: idx });
+                }
+                Rule::dep_if_attr => {
+                    self.dep_conds.push(build_dep_if(pair.into_inner())?);
+                    self.ops.push(OpItem { op: Op::Comment, line: idx });
                 }
                 Rule::feature_list => {
                     let txt = pair.as_str();
@@ -193,6 +376,11 @@ impl HakuFile {
                     };
                     self.ops.push(OpItem { op: Op::Feature(pass, txt.to_string()), line: idx });
                 }
+                Rule::feature_implies => {
+                    // implications are applied before parsing the script
+                    feat_list.extend(pair.into_inner().map(|p| p.as_str().to_lowercase()));
+                    self.ops.push(OpItem { op: Op::Comment, line: idx });
+                }
                 Rule::pause_stmt => {
                     self.ops.push(OpItem { op: Op::Pause, line: idx });
                 }
@@ -222,61 +410,61 @@ impl HakuFile {
     /// Loads and parses a script from a file. If the script contains INCLUDE statements, all
     /// included files are loaded and parsed as well
     pub fn load_from_file(path: &str, opts: &RunOpts) -> Result<HakuFile, HakuError> {
-        const BOM: [u8; 3] = [0xef, 0xbb, 0xbf];
-        let bom = if let Ok(s) = String::from_utf8(BOM.to_vec()) { s } else { "".to_string() };
-        let mut hk = HakuFile::new();
-        let input = match File::open(path) {
+        let mut input = match File::open(path) {
             Ok(f) => f,
             Err(e) => return Err(HakuError::FileOpenFailure(path.to_string(), e.to_string())),
         };
-        let buffered = BufReader::new(input);
-        let mut full_line = String::new();
-        hk.ops.clear();
-        for (idx, line) in buffered.lines().enumerate() {
-            if let Ok(l) = line {
-                let l = l.trim_start_matches(&bom);
-                hk.orig_lines.push(l.trim_end().to_string());
-                let l = l.trim();
-                full_line += l;
-                if full_line == "" {
-                    continue;
-                }
-                if full_line.ends_with('\\') {
-                    let stripped = full_line.trim_end_matches('\\');
-                    full_line = format!("{} ", stripped);
-                    continue;
-                }
-            } else {
-                return Err(HakuError::FileReadFailure(path.to_string()));
-            }
-
-            if full_line != "" {
-                hk.process_line(&full_line, idx, opts)?;
-                full_line.clear();
-            }
+        let mut src = String::new();
+        if input.read_to_string(&mut src).is_err() {
+            return Err(HakuError::FileReadFailure(path.to_string()));
         }
-        hk.remove_dead_code();
+        let mut hk = HakuFile::load_from_str(&src, opts)?;
+        hk.path = path.to_string();
         Ok(hk)
     }
 
     /// Loads and parses a script from memory. If the script contains INCLUDE statements, all
-    /// included files are loaded from files and parsed as well
+    /// included files are loaded from files and parsed as well.
+    /// A leading UTF-8 BOM is skipped, and lines can end with either LF or CRLF
     pub fn load_from_str(src: &str, opts: &RunOpts) -> Result<HakuFile, HakuError> {
+        const BOM: char = '\u{feff}';
+        let src = src.strip_prefix(BOM).unwrap_or(src);
         let mut hk = HakuFile::new();
+        let rules = feature_implications(src)?;
+        let expanded;
+        let opts = if rules.is_empty() {
+            opts
+        } else {
+            expanded = opts.clone().with_features(expand_features(&opts.feats, &rules));
+            &expanded
+        };
+        hk.feats = opts.feats.clone();
         let mut full_line = String::new();
         hk.ops.clear();
         let mut idx: usize = 0;
+        // the first line of a multi-line command: the command is reported at this line
+        let mut start: usize = 0;
         for l in src.lines() {
             hk.orig_lines.push(l.trim_end().to_string());
             let l = l.trim();
+            if full_line.is_empty() {
+                start = idx;
+            }
             full_line += l;
-            if full_line.ends_with('\\') || full_line == "" {
+            if full_line.is_empty() {
+                idx += 1;
+                continue;
+            }
+            if is_continued(&full_line) {
+                full_line.pop();
+                full_line.truncate(full_line.trim_end().len());
+                full_line.push(' ');
                 idx += 1;
                 continue;
             }
 
-            if full_line != "" {
-                hk.process_line(&full_line, idx, opts)?;
+            if !full_line.is_empty() {
+                hk.process_line(&full_line, start, opts)?;
                 full_line.clear();
             }
             idx += 1;
@@ -294,7 +482,7 @@ impl HakuFile {
 
         for o in self.ops.iter().cloned() {
             match o.op {
-                Op::Comment(_) => continue,
+                Op::Comment => continue,
                 Op::DocComment(ref s) => {
                     if skip == Skip::Recipe {
                         ds.next_desc = s.to_string();
@@ -313,7 +501,7 @@ impl HakuFile {
                         ds.fstr += s;
                     }
                 }
-                Op::Recipe(ref name, _, _, _) => {
+                Op::Recipe(ref name, _, _, _, _) => {
                     if skip == Skip::Recipe && !ds.next_pass {
                         self.disabled.push(DisabledRecipe {
                             name: name.to_string(),
@@ -339,7 +527,7 @@ impl HakuFile {
                     }
                     ds.reset();
                 }
-                Op::If(_) | Op::While(_) | Op::For(_, _) => {
+                Op::If(_) | Op::While(_) | Op::For(_, _, _) => {
                     if skip != Skip::None {
                         nesting += 1;
                     } else if ds.pass {
@@ -1,7 +1,10 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::convert::From;
 use std::env;
-use std::usize;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
 
 use crate::output;
 
@@ -10,6 +13,41 @@ struct Escape {
     with: &'static str,
 }
 
+/// Returns names of all variables used inside `${}` in a string or a shell command, including
+/// ones with modifiers(e.g., `${name:-default}`)
+pub(crate) fn interpolated_names(s: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut s_ptr = s;
+    while let Some(pos) = s_ptr.find("${") {
+        let escaped = pos > 0 && s_ptr[..pos].ends_with('$');
+        s_ptr = &s_ptr[pos + "${".len()..];
+        if escaped {
+            continue;
+        }
+        let name: String = s_ptr.chars().take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '-').collect();
+        if !name.is_empty() {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Replaces `old` with `new` in `val`. `rule` is `old/new`(or just `old` to remove it).
+/// Replaces only the first occurrence unless `all` is `true`. An empty `old` changes nothing
+fn substitute(val: &str, rule: &str, all: bool) -> String {
+    let (old, new) = match rule.find('/') {
+        Some(pos) => (&rule[..pos], &rule[pos + 1..]),
+        None => (rule, ""),
+    };
+    if old.is_empty() {
+        val.to_string()
+    } else if all {
+        val.replace(old, new)
+    } else {
+        val.replacen(old, new, 1)
+    }
+}
+
 /// Result of execution of an external command with shell
 #[derive(Clone, Debug, PartialEq)]
 pub struct ExecResult {
@@ -69,27 +107,26 @@ impl From<bool> for VarValue {
     }
 }
 
-impl ToString for VarValue {
-    fn to_string(&self) -> String {
+impl fmt::Display for VarValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            VarValue::Undefined => String::new(),
-            VarValue::Str(s) => s.clone(),
-            VarValue::Int(i) => format!("{}", i),
+            VarValue::Undefined => Ok(()),
+            VarValue::Str(s) => write!(f, "{}", s),
+            VarValue::Int(i) => write!(f, "{}", i),
             VarValue::List(v) => {
-                let mut s = String::new();
-                for it in v.iter() {
-                    if !s.is_empty() {
-                        s += "\n";
+                for (idx, it) in v.iter().enumerate() {
+                    if idx != 0 {
+                        writeln!(f)?;
                     }
-                    s += it;
+                    write!(f, "{}", it)?;
                 }
-                s
+                Ok(())
             }
             VarValue::Exec(ex) => {
                 if ex.code == 0 {
-                    ex.stdout.to_string()
+                    write!(f, "{}", ex.stdout)
                 } else {
-                    String::new()
+                    Ok(())
                 }
             }
         }
@@ -145,7 +182,7 @@ impl VarValue {
     /// * non-zero integer value
     /// * non-empty string
     /// * non-empty list (it must either have more than one item or the first item must be
-    /// non-empty string
+    ///   non-empty string
     /// * result of shell execution with 0 exit code
     pub(crate) fn is_true(&self) -> bool {
         match self {
@@ -196,10 +233,7 @@ impl VarValue {
     /// Returns `true` if both values are equivalent
     fn cmp_eq(&self, val: &VarValue) -> bool {
         match self {
-            VarValue::Undefined => match val {
-                VarValue::Undefined => true,
-                _ => false,
-            },
+            VarValue::Undefined => matches!(val, VarValue::Undefined),
             VarValue::List(lst1) => match val {
                 VarValue::List(lst2) => {
                     if lst1.len() != lst2.len() {
@@ -329,10 +363,7 @@ impl VarValue {
     /// than the successful one (exit code is 0)
     fn cmp_less(&self, val: &VarValue) -> bool {
         match self {
-            VarValue::Undefined => match val {
-                VarValue::Undefined => false,
-                _ => true,
-            },
+            VarValue::Undefined => !matches!(val, VarValue::Undefined),
             VarValue::Exec(ex) => match val {
                 VarValue::Exec(ex_val) => {
                     if ex.code == 0 && ex_val.code != 0 {
@@ -442,6 +473,45 @@ impl Default for Var {
     }
 }
 
+/// Special variable name: the list of all free arguments(`${@}` or `$@`)
+const ALL_ARGS: &str = "@";
+/// Special variable name: the number of free arguments(`${#}` or `$#`)
+const ARG_COUNT: &str = "#";
+
+/// Where the value of a variable comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum VarSource {
+    /// special variable, e.g. `$@`
+    Special,
+    /// recipe local variable
+    RecipeLocal,
+    /// script global variable
+    Global,
+    /// environment variable set by the script
+    ScriptEnv,
+    /// environment variable of the process
+    ProcessEnv,
+    /// the variable does not exist
+    Undefined,
+}
+
+impl fmt::Display for VarSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            VarSource::Special => "special",
+            VarSource::RecipeLocal => "recipe-local",
+            VarSource::Global => "global",
+            VarSource::ScriptEnv => "script-env",
+            VarSource::ProcessEnv => "process-env",
+            VarSource::Undefined => "undefined",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// A destination for messages shared by the engine and the variable manager
+pub(crate) type SharedWriter = Rc<RefCell<Box<dyn Write>>>;
+
 /// Variable manager: adds/removes variables, interpolates strings by substituting variable values
 pub(crate) struct VarMgr {
     /// values from CLI - user defined ones. Used to initialize recipe local variables
@@ -454,11 +524,26 @@ pub(crate) struct VarMgr {
     verbosity: usize,
     /// list of environment variables defined by the running script
     pub(crate) env: HashMap<String, String>,
+    /// `true` - print every variable lookup with the place where the variable is found
+    pub(crate) debug: bool,
+    /// `true` - interpolating an undefined variable is an error
+    pub(crate) strict: bool,
+    /// where warnings and variable lookups are displayed (standard error by default)
+    pub(crate) console_err: SharedWriter,
 }
 
 impl VarMgr {
     pub(crate) fn new(verbosity: usize) -> Self {
-        VarMgr { recipe_vars: Vec::new(), vars: Vec::new(), free: Vec::new(), verbosity, env: HashMap::new() }
+        VarMgr {
+            recipe_vars: Vec::new(),
+            vars: Vec::new(),
+            free: Vec::new(),
+            verbosity,
+            env: HashMap::new(),
+            debug: false,
+            strict: false,
+            console_err: Rc::new(RefCell::new(Box::new(io::stderr()))),
+        }
     }
 
     /// Change or creates a recipe local variable.
@@ -497,35 +582,139 @@ impl VarMgr {
         self.vars.push(Var { name: name.to_string(), value: val });
     }
 
+    /// Returns `true` if a global variable with the name exists
+    pub(crate) fn has_global(&self, name: &str) -> bool {
+        self.vars.iter().any(|v| v.name == name)
+    }
+
+    /// Removes a variable. First, it looks for a recipe local variable. If it does not exist,
+    /// it removes a global variable. Returns `false` if no variable was found.
+    pub(crate) fn unset_var(&mut self, name: &str) -> bool {
+        if let Some(pos) = self.recipe_vars.iter().position(|v| v.name == name) {
+            output!(self.verbosity, 2, "Removing recipe var {}", name);
+            self.recipe_vars.remove(pos);
+            return true;
+        }
+        if let Some(pos) = self.vars.iter().position(|v| v.name == name) {
+            output!(self.verbosity, 2, "Removing var {}", name);
+            self.vars.remove(pos);
+            return true;
+        }
+        false
+    }
+
     /// Returns a value of a variable. First it looks for a recipe local. If it does not exist,
     /// looks for a global variable. The last check is to look for the environment variable.
     /// Returns `Undefined` if no variable exists.
+    /// If variable debugging is on, it prints the name, the value, and the source of the variable.
     pub(crate) fn var(&self, name: &str) -> VarValue {
+        let (val, src) = self.lookup(name);
+        if self.debug {
+            let _ = writeln!(self.console_err.borrow_mut(), "[var] {} -> {}: {:?}", name, src, val);
+        }
+        val
+    }
+
+    /// Looks for a variable in the same way `var` does, and returns its value together with
+    /// the place where it is found.
+    pub(crate) fn lookup(&self, name: &str) -> (VarValue, VarSource) {
+        if name == ALL_ARGS {
+            return (VarValue::List(self.free.clone()), VarSource::Special);
+        }
+        if name == ARG_COUNT {
+            return (VarValue::Int(self.free.len() as i64), VarSource::Special);
+        }
         for v in self.recipe_vars.iter() {
             if v.name == name {
                 output!(self.verbosity, 2, "Local recipe var {} found", name);
-                return v.value.clone();
+                return (v.value.clone(), VarSource::RecipeLocal);
             }
         }
         for v in self.vars.iter() {
             if v.name == name {
                 output!(self.verbosity, 2, "Global var {} found", name);
-                return v.value.clone();
+                return (v.value.clone(), VarSource::Global);
             }
         }
 
         if let Some(s) = self.env.get(name) {
             output!(self.verbosity, 2, "Use environment variable from script {}", name);
-            return VarValue::Str(s.to_string());
+            return (VarValue::Str(s.to_string()), VarSource::ScriptEnv);
         }
 
         if let Ok(s) = env::var(name) {
             output!(self.verbosity, 2, "Use environment variable {}", name);
-            return VarValue::Str(s);
+            return (VarValue::Str(s), VarSource::ProcessEnv);
         }
 
         output!(self.verbosity, 2, "Variable {} not found", name);
-        VarValue::Undefined
+        (VarValue::Undefined, VarSource::Undefined)
+    }
+
+    /// Returns the value of a variable inside `${}` as a string. The variable name can be
+    /// followed by a modifier:
+    ///
+    /// * `${name:-fallback}` - `fallback` if the variable is undefined or empty
+    /// * `${name:+alt}` - `alt` if the variable is not empty, and an empty string otherwise
+    /// * `${name/old/new}` - the value with the first occurrence of `old` replaced with `new`
+    /// * `${name//old/new}` - the value with all occurrences of `old` replaced with `new`
+    /// * `${name^^}` and `${name,,}` - the value in upper or lower case. These operators go last
+    ///   and change the result of other modifiers: `${name:-def^^}`
+    ///
+    /// If `strict` is `true`, an undefined variable is an error(the name of the variable is
+    /// returned), except when it has a fallback or an alternative value.
+    fn expand_var(&self, var_expr: &str, flat: bool, strict: bool) -> Result<String, String> {
+        if let Some(rest) = var_expr.strip_suffix("^^") {
+            return Ok(self.expand_var(rest, flat, strict)?.to_uppercase());
+        }
+        if let Some(rest) = var_expr.strip_suffix(",,") {
+            return Ok(self.expand_var(rest, flat, strict)?.to_lowercase());
+        }
+        if let Some(pos) = var_expr.find([':', '/']) {
+            let name = &var_expr[..pos];
+            let modifier = &var_expr[pos..];
+            if let Some(fallback) = modifier.strip_prefix(":-") {
+                let val = self.var_to_str(name, flat, false)?;
+                return Ok(if val.is_empty() { fallback.to_string() } else { val });
+            }
+            if let Some(alt) = modifier.strip_prefix(":+") {
+                let val = self.var_to_str(name, flat, false)?;
+                return Ok(if val.is_empty() { String::new() } else { alt.to_string() });
+            }
+            if let Some(rule) = modifier.strip_prefix("//") {
+                return Ok(substitute(&self.var_to_str(name, flat, strict)?, rule, true));
+            }
+            if let Some(rule) = modifier.strip_prefix('/') {
+                return Ok(substitute(&self.var_to_str(name, flat, strict)?, rule, false));
+            }
+        }
+        self.var_to_str(var_expr, flat, strict)
+    }
+
+    /// Converts the value of a variable to a string for interpolation. `flat` joins list
+    /// items with spaces instead of new lines. If `strict` is `true`, an undefined variable
+    /// is an error
+    fn var_to_str(&self, var_name: &str, flat: bool, strict: bool) -> Result<String, String> {
+        let val = self.var(var_name);
+        if strict && val == VarValue::Undefined {
+            return Err(var_name.to_string());
+        }
+        if var_name == ALL_ARGS {
+            // all free arguments are always joined with spaces
+            Ok(val.to_flat_string())
+        } else if flat {
+            let val = val.to_flat_string();
+            if val.contains('\n') && self.verbosity >= 1 {
+                let _ = writeln!(
+                    self.console_err.borrow_mut(),
+                    "Warning: multi-line value of '{}' is used in a shell command",
+                    var_name
+                );
+            }
+            Ok(val)
+        } else {
+            Ok(val.to_string())
+        }
     }
 
     /// Replaces variable names with its values in strings and shell command lines. A variable
@@ -546,13 +735,30 @@ impl VarMgr {
     ///
     /// Both echoes print out `Message: ${msg}".
     ///
+    /// `${` without closing `}` and empty `${}` are not variables, they are kept as is.
+    ///
     /// Argument `flat` determines how to interpolate multi-line/-item values:
     ///
-    /// * `true` - join all lines with a space (for shell execution)
+    /// * `true` - join all lines with a space (for shell execution). Lists and the output of
+    ///   external commands become one line, but a string value is substituted as is, so a
+    ///   string with new line characters may break a shell command. In verbose mode the engine
+    ///   warns about it. Use `lines_to_args` to turn such value into shell arguments
     /// * `false` - join all lines with new line character (for `print`)
     ///
     /// Besides replacing variable names it replaces a few escape sequences: `\n`, `\\`, and `\t`.
+    /// A variable name can be followed by a modifier(see `expand_var`).
     pub(crate) fn interpolate(&self, in_str: &str, flat: bool) -> String {
+        // non-strict interpolation never fails
+        self.expand(in_str, flat, false).unwrap_or_default()
+    }
+
+    /// Works the same way as `interpolate`, but in strict mode it returns the name of the first
+    /// undefined variable as an error
+    pub(crate) fn checked_interpolate(&self, in_str: &str, flat: bool) -> Result<String, String> {
+        self.expand(in_str, flat, self.strict)
+    }
+
+    fn expand(&self, in_str: &str, flat: bool, strict: bool) -> Result<String, String> {
         let mut start_s: usize;
         let mut start_d: usize;
         let mut res = String::new();
@@ -571,12 +777,18 @@ impl VarMgr {
             start_s = s_ptr.find('\\').unwrap_or(usize::MAX);
 
             if start_s == usize::MAX && start_d == usize::MAX {
-                return res + s_ptr;
+                return Ok(res + s_ptr);
             }
 
             if start_s == usize::MAX || start_d < start_s {
                 res += &s_ptr[..start_d];
                 s_ptr = &s_ptr[start_d..];
+                // escaped '${' - literal '${'
+                if s_ptr.starts_with("$${") {
+                    res += "${";
+                    s_ptr = &s_ptr["$${".len()..];
+                    continue;
+                }
                 // escaped '$'
                 if s_ptr.starts_with("$$") {
                     res += "$";
@@ -594,13 +806,17 @@ impl VarMgr {
                 // we have "${" - variable substitution starts
                 s_ptr = &s_ptr["${".len()..];
                 match s_ptr.find('}') {
-                    None => return res + "${" + s_ptr,
+                    None => {
+                        // unterminated '${' - keep it and process the rest of the string
+                        res += "${";
+                    }
                     Some(bp) => {
-                        let var_name = &s_ptr[..bp];
-                        if flat {
-                            res += self.var(var_name).to_flat_string().as_str();
+                        let var_expr = &s_ptr[..bp];
+                        if var_expr.is_empty() {
+                            // '${}' is not a variable, keep it as is
+                            res += "${}";
                         } else {
-                            res += self.var(var_name).to_string().as_str();
+                            res += self.expand_var(var_expr, flat, strict)?.as_str();
                         }
                         s_ptr = &s_ptr[(bp + "}".len())..];
                     }
@@ -624,7 +840,7 @@ impl VarMgr {
                 s_ptr = &s_ptr["\\".len()..];
             }
         }
-        res
+        Ok(res)
     }
 }
 
@@ -651,6 +867,55 @@ mod var_test {
         assert_eq!(v1, VarValue::Int(123));
     }
 
+    #[test]
+    fn all_args() {
+        let mut v = VarMgr::new(0);
+        v.free = vec!["first".to_string(), "second arg".to_string()];
+        assert_eq!(v.interpolate("run ${@}", true), "run first second arg");
+        assert_eq!(v.interpolate("run ${@}", false), "run first second arg");
+        assert_eq!(v.var("@"), VarValue::List(vec!["first".to_string(), "second arg".to_string()]));
+        assert_eq!(v.interpolate("total ${#}", false), "total 2");
+        v.set_var("lst", VarValue::List(vec!["a.txt".to_string(), "b.txt".to_string()]));
+        assert_eq!(v.interpolate("rm ${lst}", true), "rm a.txt b.txt");
+        assert_eq!(v.interpolate("rm ${lst}", false), "rm a.txt\nb.txt");
+        v.set_var("multi", VarValue::from("a.txt\nb.txt"));
+        assert_eq!(v.interpolate("rm ${multi}", true), "rm a.txt\nb.txt");
+        v.free.clear();
+        assert_eq!(v.interpolate("run ${@}!", false), "run !");
+        assert_eq!(v.interpolate("total ${#}", false), "total 0");
+    }
+
+    #[test]
+    fn var_sources() {
+        let mut v = VarMgr::new(0);
+        v.set_var("name", VarValue::from("global"));
+        assert_eq!(v.lookup("name"), (VarValue::from("global"), VarSource::Global));
+        v.set_recipe_var("name", VarValue::from("local"));
+        assert_eq!(v.lookup("name"), (VarValue::from("local"), VarSource::RecipeLocal));
+        v.env.insert("haku_test_var".to_string(), "env".to_string());
+        assert_eq!(v.lookup("haku_test_var"), (VarValue::from("env"), VarSource::ScriptEnv));
+        assert_eq!(v.lookup("@"), (VarValue::List(Vec::new()), VarSource::Special));
+        assert_eq!(v.lookup("haku_no_such_var"), (VarValue::Undefined, VarSource::Undefined));
+        assert_eq!(VarSource::RecipeLocal.to_string(), "recipe-local");
+    }
+
+    #[test]
+    fn unset_var() {
+        let mut v = VarMgr::new(0);
+        v.set_var("abc", VarValue::Int(123));
+        v.set_recipe_var("abc", VarValue::Int(50));
+        assert!(v.unset_var("abc"));
+        assert_eq!(v.var("abc"), VarValue::Int(123));
+        assert!(v.unset_var("abc"));
+        assert_eq!(v.var("abc"), VarValue::Undefined);
+        assert!(!v.unset_var("abc"));
+
+        v.env.insert("HAKU_TEST_UNSET".to_string(), "env".to_string());
+        v.set_var("HAKU_TEST_UNSET", VarValue::from("var"));
+        assert!(v.unset_var("HAKU_TEST_UNSET"));
+        assert_eq!(v.var("HAKU_TEST_UNSET"), VarValue::from("env"));
+    }
+
     #[test]
     fn interpolate_no_matches() {
         let mut v = VarMgr::new(0);
@@ -673,6 +938,33 @@ mod var_test {
         assert_eq!(instr, &outstr);
     }
 
+    #[test]
+    fn interpolate_literal_braces() {
+        let mut v = VarMgr::new(0);
+        v.set_var("x", VarValue::from("123"));
+        let outstr = v.interpolate("$${x}", false);
+        assert_eq!("${x}", &outstr);
+        let outstr = v.interpolate("${x}: $${x}", false);
+        assert_eq!("123: ${x}", &outstr);
+        // unterminated '${' is kept, escapes after it are processed
+        let outstr = v.interpolate("text ${x end", false);
+        assert_eq!("text ${x end", &outstr);
+        let outstr = v.interpolate("${x}\\t${not_a_var", false);
+        assert_eq!("123\t${not_a_var", &outstr);
+        let outstr = v.interpolate("${\\t", false);
+        assert_eq!("${\t", &outstr);
+    }
+
+    #[test]
+    fn interpolate_empty_name() {
+        let mut v = VarMgr::new(0);
+        v.set_var("x", VarValue::from("123"));
+        let outstr = v.interpolate("${}", false);
+        assert_eq!("${}", &outstr);
+        let outstr = v.interpolate("a ${} b ${x} ${}", true);
+        assert_eq!("a ${} b 123 ${}", &outstr);
+    }
+
     #[test]
     fn interpolate_one_match() {
         let mut v = VarMgr::new(0);
@@ -737,6 +1029,101 @@ mod var_test {
         assert_eq!("text $abc end test", &outstr);
     }
 
+    #[test]
+    fn interpolate_list() {
+        let mut v = VarMgr::new(0);
+        v.set_var("args", VarValue::List(vec!["a".to_string(), "b".to_string(), "c".to_string()]));
+        let outstr = v.interpolate("echo ${args}", true);
+        assert_eq!("echo a b c", &outstr);
+        let outstr = v.interpolate("echo ${args}", false);
+        assert_eq!("echo a\nb\nc", &outstr);
+    }
+
+    #[test]
+    fn interpolate_defaults() {
+        let mut v = VarMgr::new(0);
+        v.set_var("set", VarValue::from("abc"));
+        v.set_var("empty", VarValue::from(""));
+        v.set_var("zero", VarValue::Int(0));
+        assert_eq!(v.interpolate("${set:-def}", false), "abc");
+        assert_eq!(v.interpolate("${empty:-def}", false), "def");
+        assert_eq!(v.interpolate("${undef:-def}", false), "def");
+        assert_eq!(v.interpolate("[${undef:-}]", false), "[]");
+        assert_eq!(v.interpolate("${undef:-a b-c}", true), "a b-c");
+        // zero is not an empty value
+        assert_eq!(v.interpolate("${zero:-def}", false), "0");
+
+        assert_eq!(v.interpolate("${set:+alt}", false), "alt");
+        assert_eq!(v.interpolate("[${empty:+alt}]", false), "[]");
+        assert_eq!(v.interpolate("[${undef:+alt}]", false), "[]");
+        assert_eq!(v.interpolate("--opt${set:+=}${set}", false), "--opt=abc");
+        assert_eq!(v.interpolate("${set:+:-}", false), ":-");
+
+        // unknown modifier is a part of the name
+        assert_eq!(v.interpolate("[${set:=def}]", false), "[]");
+    }
+
+    #[test]
+    fn interpolate_substitution() {
+        let mut v = VarMgr::new(0);
+        v.set_var("path", VarValue::from("src/a.rs src/b.rs"));
+        assert_eq!(v.interpolate("${path/src/dst}", false), "dst/a.rs src/b.rs");
+        assert_eq!(v.interpolate("${path//src/dst}", false), "dst/a.rs dst/b.rs");
+        assert_eq!(v.interpolate("${path//.rs/.o}", false), "src/a.o src/b.o");
+        // missing `new` removes the text
+        assert_eq!(v.interpolate("${path//src/}", false), "/a.rs /b.rs");
+        assert_eq!(v.interpolate("${path/src}", false), "/a.rs src/b.rs");
+        // `new` may contain slashes
+        assert_eq!(v.interpolate("${path/src/x/y}", false), "x/y/a.rs src/b.rs");
+        assert_eq!(v.interpolate("${path/none/x}", false), "src/a.rs src/b.rs");
+        assert_eq!(v.interpolate("${path//}", false), "src/a.rs src/b.rs");
+        assert_eq!(v.interpolate("[${undef/a/b}]", false), "[]");
+        // the variable itself is unchanged
+        assert_eq!(v.interpolate("${path}", false), "src/a.rs src/b.rs");
+    }
+
+    #[test]
+    fn interpolate_case() {
+        let mut v = VarMgr::new(0);
+        v.set_var("name", VarValue::from("Haku Runner"));
+        assert_eq!(v.interpolate("${name^^}", false), "HAKU RUNNER");
+        assert_eq!(v.interpolate("${name,,}", false), "haku runner");
+        assert_eq!(v.interpolate("[${undef^^}]", false), "[]");
+        // case operators are applied to the result of other modifiers
+        assert_eq!(v.interpolate("${undef:-Default^^}", false), "DEFAULT");
+        assert_eq!(v.interpolate("${name:+Yes,,}", false), "yes");
+        assert_eq!(v.interpolate("${name/Runner/Tool^^}", false), "HAKU TOOL");
+        assert_eq!(v.interpolate("${name// /_,,}", false), "haku_runner");
+        assert_eq!(v.interpolate("${name^^,,}", false), "haku runner");
+        // the variable itself is unchanged
+        assert_eq!(v.interpolate("${name}", false), "Haku Runner");
+    }
+
+    #[test]
+    fn interpolate_strict() {
+        let mut v = VarMgr::new(0);
+        v.set_var("abc", VarValue::from("123"));
+        v.set_var("empty", VarValue::from(""));
+        assert_eq!(v.checked_interpolate("${abd}", false), Ok(String::new()));
+        v.strict = true;
+        assert_eq!(v.checked_interpolate("${abc} ${empty}", false), Ok("123 ".to_string()));
+        assert_eq!(v.checked_interpolate("${abc} ${abd}", false), Err("abd".to_string()));
+        assert_eq!(v.checked_interpolate("${abd^^}", false), Err("abd".to_string()));
+        assert_eq!(v.checked_interpolate("${abd/a/b}", false), Err("abd".to_string()));
+        // escaped variables and variables with fallbacks are fine
+        assert_eq!(v.checked_interpolate("$${abd} \\${abd}", false), Ok("${abd} ${abd}".to_string()));
+        assert_eq!(v.checked_interpolate("${abd:-x}${abd:+y}", false), Ok("x".to_string()));
+        assert_eq!(v.checked_interpolate("${@}${#}", true), Ok("0".to_string()));
+        // non-checked interpolation is never strict
+        assert_eq!(v.interpolate("[${abd}]", false), "[]");
+    }
+
+    #[test]
+    fn used_names() {
+        assert!(interpolated_names("echo $a ${} text").is_empty());
+        assert_eq!(interpolated_names("${a} ${b-c:-x} $${d} ${e^^}${f/x/y}"), vec!["a", "b-c", "e", "f"]);
+    }
+
     #[test]
     fn unescaped() {
         let v = VarMgr::new(0);
@@ -1,22 +1,26 @@
+use std::collections::HashSet;
 use std::convert::From;
 use std::env;
 use std::fmt;
-use std::io::{self, Write};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
 use std::iter::FromIterator;
 use std::mem;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
 use std::time::{Duration, Instant};
-use std::usize;
 
 use crate::errors::HakuError;
-use crate::func::{run_func, FuncResult};
-use crate::ops::{is_flag_on, Op, Seq, FLAG_PASS, FLAG_QUIET};
-use crate::parse::{DisabledRecipe, HakuFile};
-use crate::var::{ExecResult, VarMgr, VarValue};
+use crate::func::{function_list, run_func, FuncResult};
+use crate::ops::{is_flag_on, line_flags, Op, RecipeDep, Seq, FLAG_ISOLATE_ENV, FLAG_ONCE, FLAG_PASS, FLAG_QUIET};
+use crate::parse::{disabled_reasons, DisabledRecipe, HakuFile};
+use crate::var::{interpolated_names, ExecResult, SharedWriter, VarMgr, VarValue};
 
 /// Name of a recipe that is executed if no recipe is set by a caller
 const DEFAULT_RECIPE: &str = "_default";
+/// The longest delay between attempts of `retry`
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
 
 #[macro_export]
 macro_rules! output {
@@ -38,10 +42,11 @@ macro_rules! output {
 }
 
 /// Human-readable formatting of a time duration:
-///   == 0    => 0ms
-///    > 60s  => 10m30s
-///    > 0s   => 30s234ms
-///    < 0s   => 234ms
+///
+/// * == 0    => 0ms
+/// * > 60s  => 10m30s
+/// * > 0s   => 30s234ms
+/// * < 0s   => 234ms
 fn human_duration(dur: Duration) -> String {
     let sec = dur.as_secs();
     if sec >= 60 {
@@ -59,23 +64,231 @@ fn human_duration(dur: Duration) -> String {
     format!("{}ms", milli)
 }
 
+/// Calculates Levenshtein distance between two strings(in characters, not bytes)
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr: Vec<usize> = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+/// Splits a recipe argument declaration `[+]name[!][:type]` into the name(with leading `+`
+/// for a list argument), the flag whether the argument is required, and the type(empty if
+/// the type is not set).
+fn arg_spec(v: &str) -> (&str, bool, &str) {
+    let (v, tp) = match v.find(':') {
+        Some(pos) => (&v[..pos], &v[pos + 1..]),
+        None => (v, ""),
+    };
+    let required = v.ends_with('!');
+    (v.trim_end_matches('!'), required, tp)
+}
+
+/// Splits a command line into a program name and its arguments without a shell. Arguments are
+/// separated with whitespaces. Single quotes keep the text as is, double quotes allow escaping
+/// `"` and `\\` with a backslash. Outside quotes, a backslash escapes only a quote, a backslash,
+/// or a whitespace, otherwise it is kept as is(e.g., in Windows paths like `C:\dir\file`).
+/// Returns an error if the command uses shell features: pipes, redirections, command chains,
+/// background jobs, or command substitution.
+fn split_cmdline(cmdline: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote: Option<char> = None;
+    let mut chars = cmdline.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+            (Some('"'), '\\') if matches!(chars.peek(), Some('"') | Some('\\')) => arg.extend(chars.next()),
+            (Some('"'), '`') => return Err("command substitution is not supported".to_string()),
+            (Some('"'), '$') if chars.peek() == Some(&'(') => {
+                return Err("command substitution is not supported".to_string())
+            }
+            (Some(_), _) => arg.push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, '\\') => {
+                match chars.peek() {
+                    Some(&n) if n == '\'' || n == '"' || n == '\\' || n.is_whitespace() => arg.extend(chars.next()),
+                    _ => arg.push(c),
+                }
+                in_arg = true;
+            }
+            (None, '|') | (None, '&') | (None, ';') | (None, '<') | (None, '>') => {
+                return Err(format!("shell operator '{}' is not supported", c))
+            }
+            (None, '`') => return Err("command substitution is not supported".to_string()),
+            (None, '$') if chars.peek() == Some(&'(') => {
+                return Err("command substitution is not supported".to_string())
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            (None, _) => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if quote.is_some() {
+        return Err("unterminated quoted string".to_string());
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    if args.is_empty() {
+        return Err("empty command".to_string());
+    }
+    Ok(args)
+}
+
+/// Collects names of all variables which values are read by the operation: variables in
+/// expressions, strings, and shell commands
+fn used_var_names(op: &Op, names: &mut HashSet<String>) {
+    let add_str = |s: &str, names: &mut HashSet<String>| names.extend(interpolated_names(s));
+    match op {
+        Op::Var(name) => {
+            names.insert(name.clone());
+        }
+        Op::Str(s) | Op::Exec(s) | Op::Shell(_, _, s) | Op::Cd(_, s) | Op::Include(_, s) => add_str(s, names),
+        Op::Func(_, ops)
+        | Op::Assign(_, ops)
+        | Op::DefAssign(_, ops)
+        | Op::EitherAssign(_, _, ops)
+        | Op::Compare(_, ops)
+        | Op::If(ops)
+        | Op::ElseIf(ops)
+        | Op::AndExpr(ops)
+        | Op::OrExpr(ops)
+        | Op::Concat(ops)
+        | Op::While(ops)
+        | Op::Not(ops)
+        | Op::Spread(ops) => {
+            for o in ops.iter() {
+                used_var_names(o, names);
+            }
+        }
+        Op::CompoundAssign(name, _, ops) => {
+            names.insert(name.clone());
+            for o in ops.iter() {
+                used_var_names(o, names);
+            }
+        }
+        Op::For(_, seq, _) => match seq {
+            Seq::Var(name) => {
+                names.insert(name.clone());
+            }
+            Seq::Str(s) | Seq::Exec(s) => add_str(s, names),
+            Seq::Idents(ids) => {
+                for id in ids.iter() {
+                    add_str(id, names);
+                }
+            }
+            Seq::Int(..) => {}
+        },
+        Op::Recipe(_, _, _, deps, _) => {
+            // dependency arguments and conditions are evaluated in the recipe context
+            for dep in deps.iter() {
+                for arg in dep.args.iter().flatten() {
+                    add_str(arg, names);
+                }
+                if let Some(cond) = &dep.cond {
+                    used_var_names(cond, names);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Checks if an argument value matches its type: `int`, `path`, or `enum(val1|val2...)`.
+/// Returns the description of the mismatch.
+fn check_arg_type(val: &str, tp: &str) -> Result<(), String> {
+    let low = tp.to_lowercase();
+    if low == "int" {
+        let val = val.replace('_', "");
+        let ok = if val.starts_with("0x") || val.starts_with("0X") {
+            i64::from_str_radix(&val[2..], 16).is_ok()
+        } else {
+            val.parse::<i64>().is_ok()
+        };
+        if !ok {
+            return Err("must be an integer".to_string());
+        }
+    } else if low == "path" {
+        if !Path::new(val).exists() {
+            return Err("path does not exist".to_string());
+        }
+    } else if low.starts_with("enum(") {
+        let items: Vec<&str> = tp["enum(".len()..tp.len() - 1].split('|').collect();
+        if !items.contains(&val) {
+            return Err(format!("must be one of: {}", items.join(", ")));
+        }
+    }
+    Ok(())
+}
+
 /// Runtime engine options
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct RunOpts {
     /// the list of user-defined features passed from a caller
     pub(crate) feats: Vec<String>,
+    /// `true` - every user-defined feature is enabled regardless of `feats`
+    pub(crate) all_features: bool,
     /// verbosity level - affects amount of info print while executing a recipe
     verbosity: usize,
-    /// `true` - do not run any shell commands (except ones in assignments and for's)
+    /// `true` - do not run external commands, only print them
     dry_run: bool,
+    /// `true` - in dry-run mode, commands in backticks are still executed, so conditions
+    /// and assignments get the same values as in a real run
+    dry_run_smart: bool,
     /// `true` - show time taken by a recipe
     show_time: bool,
-}
-
-impl Default for RunOpts {
-    fn default() -> Self {
-        RunOpts { dry_run: false, feats: Vec::new(), verbosity: 0, show_time: false }
-    }
+    /// `true` - shell commands are split into arguments and run directly, without a shell
+    no_shell: bool,
+    /// `true` - print the number of the current iteration before every `for` loop iteration
+    progress: bool,
+    /// `true` - recipe names are compared case-insensitively
+    ignore_case: bool,
+    /// `true` - print every shell command and assignment before executing it
+    trace: bool,
+    /// `true` - print every variable lookup and where the variable is found
+    debug_vars: bool,
+    /// `true` - using an undefined variable in a string or a shell command is an error
+    strict: bool,
+    /// `true` - warn when a recipe argument hides a global variable with the same name
+    warn_shadow: bool,
+    /// `true` - running a script without recipe name and without `_default` recipe is not an error
+    allow_missing_default: bool,
+    /// custom line comment prefixes. Empty - use default ones: `#` and `//`
+    pub(crate) comments: Vec<String>,
+    /// environment variables passed from a caller(e.g., loaded from an env file). They are
+    /// visible to a script and to all external commands it runs
+    env_vars: Vec<(String, String)>,
+    /// maximum depth of nested includes(the main script is at depth 0). `0` - no limit
+    max_include_depth: usize,
+    /// recipe to resume a failed run from: all recipes before it in the list of recipes to
+    /// run(dependencies included) are skipped. Empty - run all recipes
+    continue_from: String,
+    /// the only recipe to run from the list of recipes to run(dependencies included).
+    /// Empty - run all recipes
+    only: String,
+    /// `true` - temporary files and directories created by `tempfile` and `tempdir` are
+    /// not removed when the run finishes
+    keep_temp: bool,
 }
 
 impl RunOpts {
@@ -88,11 +301,21 @@ impl RunOpts {
         self
     }
 
+    pub fn with_dry_run_smart(mut self, smart: bool) -> Self {
+        self.dry_run_smart = smart;
+        self
+    }
+
     pub fn with_features(mut self, feats: Vec<String>) -> Self {
         self.feats = feats;
         self
     }
 
+    pub fn with_all_features(mut self, all: bool) -> Self {
+        self.all_features = all;
+        self
+    }
+
     pub fn with_verbosity(mut self, verbosity: usize) -> Self {
         self.verbosity = verbosity;
         self
@@ -102,6 +325,76 @@ impl RunOpts {
         self.show_time = show;
         self
     }
+
+    pub fn with_no_shell(mut self, no_shell: bool) -> Self {
+        self.no_shell = no_shell;
+        self
+    }
+
+    pub fn with_continue_from(mut self, name: &str) -> Self {
+        self.continue_from = name.to_string();
+        self
+    }
+
+    pub fn with_only(mut self, name: &str) -> Self {
+        self.only = name.to_string();
+        self
+    }
+
+    pub fn with_clean_temp(mut self, clean: bool) -> Self {
+        self.keep_temp = !clean;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: bool) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub fn with_debug_vars(mut self, debug: bool) -> Self {
+        self.debug_vars = debug;
+        self
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_warn_shadow(mut self, warn: bool) -> Self {
+        self.warn_shadow = warn;
+        self
+    }
+
+    pub fn with_ignore_case(mut self, ignore: bool) -> Self {
+        self.ignore_case = ignore;
+        self
+    }
+
+    pub fn with_allow_missing_default(mut self, allow: bool) -> Self {
+        self.allow_missing_default = allow;
+        self
+    }
+
+    pub fn with_comments(mut self, comments: Vec<String>) -> Self {
+        self.comments = comments.into_iter().filter(|c| !c.is_empty()).collect();
+        self
+    }
+
+    pub fn with_env_vars(mut self, vars: Vec<(String, String)>) -> Self {
+        self.env_vars = vars;
+        self
+    }
+
+    pub fn with_max_include_depth(mut self, depth: usize) -> Self {
+        self.max_include_depth = depth;
+        self
+    }
 }
 
 /// Recipe detailed information
@@ -179,6 +472,12 @@ struct CondItem {
     line: usize,
     /// detailed info about condition state
     cond: Condition,
+    /// for `for` loops marked with `#[no-fail-fast]` - the number of failed iterations.
+    /// `None` - the first failed command stops the loop
+    failures: Option<usize>,
+    /// for `for` loops when `--progress` is on - the number of the current iteration and
+    /// the total number of iterations
+    progress: Option<(usize, usize)>,
 }
 
 /// Engine that runs the recipes
@@ -209,6 +508,37 @@ pub struct Engine {
     pub(crate) cwd: PathBuf,
     /// directory change stack (for "cd -" command)
     pub(crate) cwd_history: Vec<PathBuf>,
+    /// file to write the output of print functions to(`None` - standard output)
+    output: Option<BufWriter<File>>,
+    /// buffers that collect the output of recipes run by `capture_recipe`(the last one is
+    /// the innermost recipe). While the list is not empty, nothing is printed
+    captures: Vec<String>,
+    /// depth of the script that is being loaded(the main script is at depth 0)
+    include_depth: usize,
+    /// names of `#[once]` recipes that have already been started
+    once_done: HashSet<String>,
+    /// names of cleanup recipes(`#[finally]`) that are running now
+    cleanups: HashSet<String>,
+    /// temporary files and directories created by a script(removed when the engine is destroyed)
+    temp_paths: Vec<PathBuf>,
+    /// processes started in background by `spawn`. A handle returned to a script is an index
+    /// in the list plus one. `None` - the process has been waited for
+    children: Vec<Option<Child>>,
+    /// where the engine displays executed commands, progress of loops, and trace lines
+    /// (standard output by default)
+    console: Box<dyn Write>,
+    /// where the engine displays its warnings (standard error by default). The variable
+    /// manager writes to the same destination
+    console_err: SharedWriter,
+}
+
+/// Order of recipes in the recipe list
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ListOrder {
+    /// alphabetically by recipe name
+    Name,
+    /// in order of definition: by file(the main script goes first), then by line
+    File,
 }
 
 /// Describes a recipe location
@@ -233,6 +563,20 @@ struct RecipeItem {
     vars: Vec<String>,
     /// global recipe flags (i.e., echo off)
     flags: u32,
+    /// arguments passed by a parent recipe(`None` - the recipe uses free arguments)
+    args: Option<Vec<String>>,
+    /// recipe to run after this one finishes, even if this one fails
+    cleanup: Option<String>,
+}
+
+/// Which recipes from the list of recipes to run(a recipe and all its dependencies) are executed
+enum RecipeSteps {
+    /// all recipes
+    All,
+    /// the recipe with the given name and all recipes after it
+    From(String),
+    /// only the recipe with the given name
+    Only(String),
 }
 
 /// Recipe content
@@ -259,11 +603,18 @@ impl Engine {
                 PathBuf::new()
             }
         };
+        let mut varmgr = VarMgr::new(opts.verbosity);
+        varmgr.debug = opts.debug_vars;
+        varmgr.strict = opts.strict;
+        for (name, val) in opts.env_vars.iter() {
+            varmgr.env.insert(name.clone(), val.clone());
+        }
+        let console_err = varmgr.console_err.clone();
         Engine {
             files: Vec::new(),
             included: Vec::new(),
             recipes: Vec::new(),
-            varmgr: VarMgr::new(opts.verbosity),
+            varmgr,
             cond_stack: Vec::new(),
             real_line: usize::MAX,
             file_idx: usize::MAX,
@@ -271,6 +622,15 @@ impl Engine {
             shell,
             cwd,
             cwd_history: Vec::new(),
+            output: None,
+            captures: Vec::new(),
+            include_depth: 0,
+            once_done: HashSet::new(),
+            cleanups: HashSet::new(),
+            temp_paths: Vec::new(),
+            children: Vec::new(),
+            console: Box::new(io::stdout()),
+            console_err,
         }
     }
 
@@ -279,7 +639,7 @@ impl Engine {
         if fidx == usize::MAX || fidx >= self.files.len() {
             return (String::new(), String::new());
         }
-        let fname = if self.files.len() == 1 { String::new() } else { self.included[fidx].clone() };
+        let fname = if self.files.len() == 1 { String::new() } else { self.files[fidx].path.clone() };
         if lidx == usize::MAX || lidx >= self.files[fidx].orig_lines.len() {
             return (fname, String::new());
         }
@@ -301,7 +661,13 @@ impl Engine {
                 return Err(HakuError::IncludeRecursionError(filepath.to_string()));
             }
         }
+        if self.opts.max_include_depth != 0 && self.include_depth > self.opts.max_include_depth {
+            return Err(HakuError::IncludeDepthError(filepath.to_string(), self.opts.max_include_depth));
+        }
         let hk = HakuFile::load_from_file(filepath, &self.opts)?;
+        self.warn_dangling_attr(&hk, filepath);
+        // implied features are visible to included scripts and to `feature` function
+        self.opts.feats = hk.feats.clone();
         self.files.push(hk);
         self.included.push(filepath.to_string());
         self.run_header(self.files.len() - 1)?;
@@ -314,12 +680,23 @@ impl Engine {
     pub fn load_from_str(&mut self, src: &str) -> Result<(), HakuError> {
         output!(self.opts.verbosity, 2, "Executing string: {}", src);
         let hk = HakuFile::load_from_str(src, &self.opts)?;
+        self.warn_dangling_attr(&hk, "");
+        self.opts.feats = hk.feats.clone();
         self.files.push(hk);
         self.run_header(self.files.len() - 1)?;
         self.detect_recipes();
         Ok(())
     }
 
+    /// Prints a warning if a loaded script ends with an attribute that is not applied to anything
+    fn warn_dangling_attr(&mut self, hk: &HakuFile, filepath: &str) {
+        if !hk.has_dangling_loop_attr() {
+            return;
+        }
+        let place = if filepath.is_empty() { String::new() } else { format!(" of '{}'", filepath) };
+        self.echo_err(&format!("Warning: attribute #[no-fail-fast] at the end{} is ignored", place));
+    }
+
     /// Looks for all `import` statements between the first line and the first recipe(or the end
     /// of the script if it does not contain any recipe) and recursively loads imported scripts
     fn run_header(&mut self, idx: usize) -> Result<(), HakuError> {
@@ -331,10 +708,10 @@ impl Engine {
             self.file_idx = idx;
             match &op.op {
                 Op::Feature(_, _) => { /* Since dead code is removed, it can be skipped */ }
-                Op::Recipe(_, _, _, _) => break,
-                Op::Comment(_) | Op::DocComment(_) => { /* just continue */ }
+                Op::Recipe(_, _, _, _, _) => break,
+                Op::Comment | Op::DocComment(_) => { /* just continue */ }
                 Op::Include(flags, path) => {
-                    let inc_path = self.varmgr.interpolate(&path, true);
+                    let inc_path = self.interpolate(path, true)?;
                     output!(self.opts.verbosity, 3, "        !!INCLUDE - {}", inc_path);
                     to_include.push(inc_path);
                     to_include_flags.push(*flags);
@@ -343,16 +720,53 @@ impl Engine {
             }
         }
         output!(self.opts.verbosity, 3, "TO INCLUDE: {}", to_include.len());
-        for (i, path) in to_include.iter().enumerate() {
-            let f = to_include_flags[i];
-            let res = self.load_from_file(path);
+        self.include_depth += 1;
+        let res = self.include_files(&to_include, &to_include_flags);
+        self.include_depth -= 1;
+        res
+    }
+
+    /// Loads scripts included by another script. A script that fails to load is skipped if
+    /// its include has flag `-`. Exceeding the maximum include depth is always an error
+    fn include_files(&mut self, paths: &[String], flags: &[u32]) -> Result<(), HakuError> {
+        for (i, path) in paths.iter().enumerate() {
+            let f = flags[i];
+            let res = self.include_path(path);
             if res.is_err() {
                 output!(self.opts.verbosity, 2, "ERROR: {:?}", res);
             }
+            if let Err(HakuError::IncludeDepthError(..)) = res {
+                return res;
+            }
             if res.is_err() && !is_flag_on(f, FLAG_PASS) {
                 return res;
             }
-            eprintln!("Skipping included file: {:?}", res);
+            if let Err(e) = res {
+                eprintln!("Skipping included file: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads a script by its path. If the path is a glob pattern(contains `*`, `?`, or `[`),
+    /// loads all matching files in alphabetical order. A pattern that matches nothing is an error
+    fn include_path(&mut self, path: &str) -> Result<(), HakuError> {
+        if !path.contains(['*', '?', '[']) {
+            return self.load_from_file(path);
+        }
+        let entries = match glob::glob(path) {
+            Ok(entries) => entries,
+            Err(e) => return Err(HakuError::FileOpenFailure(path.to_string(), e.to_string())),
+        };
+        let mut files: Vec<String> =
+            entries.filter_map(|e| e.ok()).filter(|p| p.is_file()).map(|p| p.to_string_lossy().to_string()).collect();
+        if files.is_empty() {
+            return Err(HakuError::FileOpenFailure(path.to_string(), "no file matches the pattern".to_string()));
+        }
+        files.sort();
+        output!(self.opts.verbosity, 2, "Pattern {} matches {:?}", path, files);
+        for file in files.iter() {
+            self.load_from_file(file)?;
         }
         Ok(())
     }
@@ -376,13 +790,13 @@ impl Engine {
                 match op.op {
                     Op::Feature(_, _) => {}
                     Op::DocComment(ref s) => desc = self.varmgr.interpolate(s, true),
-                    Op::Recipe(ref nm, flags, ref vars, ref deps) => {
+                    Op::Recipe(ref nm, flags, ref vars, ref deps, _) => {
                         let mut recipe = RecipeDesc {
                             name: nm.clone(),
                             desc: desc.clone(),
                             loc: RecipeLoc { line: line_idx, file: file_idx, script_line: op.line },
                             depends: Vec::new(),
-                            system: Engine::is_system_recipe(&nm),
+                            system: Engine::is_system_recipe(nm),
                             vars: vars.clone(),
                             flags,
                         };
@@ -395,7 +809,7 @@ impl Engine {
                         self.recipes.push(recipe);
                         desc.clear();
                     }
-                    Op::Comment(_) => { /* do not change anything */ }
+                    Op::Comment => { /* do not change anything */ }
                     _ => {
                         desc.clear();
                     }
@@ -411,7 +825,16 @@ impl Engine {
         if file_idx >= self.files.len() {
             return Err(HakuError::FileNotLoaded(file_idx));
         }
-        Ok(&self.included[file_idx])
+        Ok(&self.files[file_idx].path)
+    }
+
+    /// Returns the name of a loaded script to display: its path, or `<string>` if the script
+    /// is loaded from a string
+    fn script_name(&self, file_idx: usize) -> &str {
+        match self.files.get(file_idx) {
+            Some(f) if !f.path.is_empty() => &f.path,
+            _ => "<string>",
+        }
     }
 
     /// Returns info about all loaded available recipes
@@ -419,6 +842,61 @@ impl Engine {
         &self.recipes
     }
 
+    /// Returns descriptions of all available non-system recipes in the same format
+    /// `haku --list` shows them: `name (args): deps #description`, one line per recipe
+    pub fn recipe_list(&self) -> Vec<String> {
+        self.recipe_list_by(ListOrder::Name)
+    }
+
+    /// Returns descriptions of all available non-system recipes in the same way `recipe_list`
+    /// does, but in a given order
+    pub fn recipe_list_by(&self, order: ListOrder) -> Vec<String> {
+        self.listed_recipes(order).into_iter().map(Engine::recipe_line).collect()
+    }
+
+    /// Returns descriptions of all available non-system recipes grouped by scripts where the
+    /// recipes are defined: a script file name and its recipes in a given order. The main
+    /// script goes first, scripts without available recipes are skipped.
+    pub fn recipe_list_by_file(&self, order: ListOrder) -> Vec<(String, Vec<String>)> {
+        let listed = self.listed_recipes(order);
+        let mut groups = Vec::new();
+        for file_idx in 0..self.files.len() {
+            let lines: Vec<String> =
+                listed.iter().filter(|s| s.loc.file == file_idx).map(|s| Engine::recipe_line(s)).collect();
+            if !lines.is_empty() {
+                groups.push((self.script_name(file_idx).to_string(), lines));
+            }
+        }
+        groups
+    }
+
+    /// Returns non-system recipes to display in the recipe list in a given order
+    fn listed_recipes(&self, order: ListOrder) -> Vec<&RecipeDesc> {
+        let mut sec_names = HashSet::new();
+        // when a few recipes have the same name, the listed one is the same for any order
+        let mut listed: Vec<&RecipeDesc> =
+            self.recipes.iter().filter(|s| sec_names.insert(s.name.clone()) && !s.system).collect();
+        if order == ListOrder::File {
+            listed.sort_by_key(|s| (s.loc.file, s.loc.line));
+        }
+        listed
+    }
+
+    /// Formats a recipe for the recipe list: `name (args): deps #description`
+    fn recipe_line(s: &RecipeDesc) -> String {
+        let mut line = s.name.clone();
+        if !s.vars.is_empty() {
+            line += &format!(" ({})", s.vars.join(","));
+        }
+        if !s.depends.is_empty() {
+            line += &format!(": {}", s.depends.join(","));
+        }
+        if !s.desc.is_empty() {
+            line += &format!(" #{}", s.desc);
+        }
+        line
+    }
+
     /// Returns info about all loaded disabled recipes
     pub fn disabled_recipes(&self) -> Vec<DisabledRecipe> {
         let mut v = Vec::new();
@@ -453,23 +931,72 @@ impl Engine {
         min
     }
 
+    /// Returns the line after the last line of a recipe that starts at line `line`. All doc
+    /// comments and feature lists before the next recipe belong to the next recipe.
+    fn recipe_end(&self, file: usize, line: usize) -> usize {
+        let mut eidx = self.next_recipe(file, line);
+        let lines = &self.files[file].orig_lines;
+        while eidx > line && {
+            let l = lines[eidx - 1].trim_start();
+            l.starts_with("#[") || l.starts_with("##") || l.starts_with("//")
+        } {
+            eidx -= 1;
+        }
+        eidx
+    }
+
+    /// Returns the name of a recipe that contains the line `line`(1-based) of the script
+    /// `file`. Returns `header` if the line is before the first recipe, and `none` if the line
+    /// is outside of any recipe or the file is not loaded. A script loaded from a string can
+    /// be referred to as `<string>`.
+    pub fn recipe_at(&self, file: &str, line: usize) -> String {
+        let fpath = Path::new(file);
+        let found = self.files.iter().position(|f| {
+            if f.path.is_empty() {
+                file == "<string>"
+            } else {
+                Path::new(&f.path) == fpath || Path::new(&f.path).ends_with(fpath)
+            }
+        });
+        let fidx = match found {
+            Some(idx) => idx,
+            None => return "none".to_string(),
+        };
+        if line == 0 || line > self.files[fidx].orig_lines.len() {
+            return "none".to_string();
+        }
+        let line = line - 1;
+        let mut starts: Vec<(usize, &str)> =
+            self.recipes.iter().filter(|r| r.loc.file == fidx).map(|r| (r.loc.script_line, r.name.as_str())).collect();
+        for d in self.files[fidx].disabled.iter() {
+            starts.push((d.line, d.name.as_str()));
+        }
+        let found = starts.iter().filter(|(start, _)| *start <= line).max_by_key(|(start, _)| *start);
+        match found {
+            None => {
+                if line < self.recipe_end(fidx, 0) {
+                    "header".to_string()
+                } else {
+                    "none".to_string()
+                }
+            }
+            Some((start, name)) => {
+                if line < self.recipe_end(fidx, *start) {
+                    name.to_string()
+                } else {
+                    "none".to_string()
+                }
+            }
+        }
+    }
+
     /// Returns the content of a recipe that would be executed
     pub fn recipe_content(&self, name: &str) -> Result<RecipeContent, HakuError> {
         if let Ok(desc) = self.find_recipe(name) {
             let fidx = desc.loc.file;
             let sidx = desc.loc.script_line;
-            let mut eidx = self.next_recipe(fidx, sidx);
+            let eidx = self.recipe_end(fidx, sidx);
             let mut content = Vec::new();
-
-            // ignore all doc comments and feature lists related to the next section
-            while eidx > sidx
-                && (self.files[fidx].orig_lines[eidx - 1].trim_start().starts_with("#[")
-                    || self.files[fidx].orig_lines[eidx - 1].trim_start().starts_with("##")
-                    || self.files[fidx].orig_lines[eidx - 1].trim_start().starts_with("//"))
-            {
-                eidx -= 1;
-            }
-
             for lidx in sidx..eidx {
                 content.push(self.files[fidx].orig_lines[lidx].clone());
             }
@@ -492,15 +1019,7 @@ impl Engine {
             }
 
             if sidx != usize::MAX {
-                let mut eidx = self.next_recipe(fidx, sidx);
-                // ignore all doc comments and feature lists related to the next section
-                while eidx > sidx
-                    && (self.files[fidx].orig_lines[eidx - 1].trim_start().starts_with("#[")
-                        || self.files[fidx].orig_lines[eidx - 1].trim_start().starts_with("##")
-                        || self.files[fidx].orig_lines[eidx - 1].trim_start().starts_with("//"))
-                {
-                    eidx -= 1;
-                }
+                let eidx = self.recipe_end(fidx, sidx);
                 let mut content = Vec::new();
                 for lidx in sidx..eidx {
                     content.push(f.orig_lines[lidx].clone());
@@ -514,7 +1033,121 @@ impl Engine {
             }
         }
 
-        Err(HakuError::RecipeNotFoundError(name.to_string()))
+        Err(HakuError::RecipeNotFoundError(name.to_string(), self.recipe_hint(name)))
+    }
+
+    /// Returns a short description of a recipe: its name, arguments, dependencies, and doc
+    /// comment. For a disabled recipe, it also includes the features that enable it.
+    pub fn describe_recipe(&self, name: &str) -> Result<String, HakuError> {
+        if let Ok(desc) = self.find_recipe(name) {
+            return Ok(format!("Active recipe: {}", desc));
+        }
+        for f in self.files.iter() {
+            for r in f.disabled.iter() {
+                if r.name != name {
+                    continue;
+                }
+                let mut s = format!("Disabled recipe: {}", r.name);
+                if !r.desc.is_empty() {
+                    s += &format!(" #{}", r.desc);
+                }
+                if !r.feat.is_empty() {
+                    s += &format!("\nEnabled by: {}", r.feat);
+                }
+                return Ok(s);
+            }
+        }
+        Err(HakuError::RecipeNotFoundError(name.to_string(), self.recipe_hint(name)))
+    }
+
+    /// Returns the list of operations of all loaded scripts after dead code elimination.
+    /// Every script starts with its name, and every operation is prefixed with its line
+    /// number(starting from 1). Used for debugging.
+    pub fn dump_ops(&self) -> Vec<String> {
+        let mut v = Vec::new();
+        for (idx, f) in self.files.iter().enumerate() {
+            v.push(format!("File: {}", self.script_name(idx)));
+            for op in f.ops.iter() {
+                v.push(format!("  {}: {:?}", op.line + 1, op.op));
+            }
+        }
+        v
+    }
+
+    /// Returns locations of all recipes in a machine-readable format: one recipe per line,
+    /// `name<TAB>file<TAB>line`. The line number is 1-based. Disabled recipes have extra
+    /// column `disabled`. A script loaded from a string has file name `<string>`.
+    pub fn dump_recipes(&self) -> Vec<String> {
+        let mut v = Vec::new();
+        for r in self.recipes.iter() {
+            v.push(format!("{}\t{}\t{}", r.name, self.script_name(r.loc.file), r.loc.script_line + 1));
+        }
+        for (idx, file) in self.files.iter().enumerate() {
+            for ds in file.disabled.iter() {
+                v.push(format!("{}\t{}\t{}\tdisabled", ds.name, self.script_name(idx), ds.line + 1));
+            }
+        }
+        v
+    }
+
+    /// Returns warnings about recipe arguments that are never used in the recipe: neither in
+    /// expressions nor in strings and shell commands. One line per unused argument.
+    pub fn unused_arg_warnings(&self) -> Vec<String> {
+        let mut v = Vec::new();
+        for r in self.recipes.iter() {
+            if r.vars.is_empty() {
+                continue;
+            }
+            let mut names = HashSet::new();
+            let ops = &self.files[r.loc.file].ops;
+            for (idx, op) in ops.iter().enumerate().skip(r.loc.line) {
+                if idx > r.loc.line && matches!(op.op, Op::Recipe(..)) {
+                    break;
+                }
+                used_var_names(&op.op, &mut names);
+            }
+            for var in r.vars.iter() {
+                let (name, _, _) = arg_spec(var);
+                let name = name.trim_start_matches('+');
+                if !names.contains(name) {
+                    v.push(format!(
+                        "argument '{}' of recipe '{}' is never used ({}:{})",
+                        name,
+                        r.name,
+                        self.script_name(r.loc.file),
+                        r.loc.script_line + 1
+                    ));
+                }
+            }
+        }
+        v
+    }
+
+    /// Returns a line per disabled recipe that explains why the recipe is disabled: the recipe
+    /// name, its location, feature directives, and the conditions that are not satisfied with
+    /// the current features, e.g., `zip (Taskfile:12): #[feature(zip)] - unmet: feature(zip)`.
+    pub fn dump_disabled(&self) -> Vec<String> {
+        let mut v = Vec::new();
+        for (idx, file) in self.files.iter().enumerate() {
+            for ds in file.disabled.iter() {
+                let reasons = disabled_reasons(&ds.feat, &self.opts);
+                v.push(format!(
+                    "{} ({}:{}): {} - unmet: {}",
+                    ds.name,
+                    self.script_name(idx),
+                    ds.line + 1,
+                    ds.feat,
+                    reasons.join(", ")
+                ));
+            }
+        }
+        v
+    }
+
+    /// Returns all built-in functions grouped by category: category name and a list of
+    /// functions, each with its aliases
+    pub fn function_list() -> Vec<(String, Vec<String>)> {
+        function_list()
     }
 
     /// Returns a list of unique user-defined features found in loaded scripts
@@ -538,67 +1171,302 @@ impl Engine {
     }
 
     /// Finds a recipe location by its name (see `detect_recipes` for details about
-    /// name conflicts): file and line numbers
+    /// name conflicts): file and line numbers.
+    /// If the engine ignores case, an exact match is preferred. Otherwise, the name must
+    /// match only one recipe name case-insensitively.
     fn find_recipe(&self, name: &str) -> Result<RecipeDesc, HakuError> {
         for sec in &self.recipes {
             if sec.name == name {
                 return Ok(sec.clone());
             }
         }
-        Err(HakuError::RecipeNotFoundError(name.to_string()))
-    }
-
-    /// Sets the values to initialize recipe variables (used by a caller).
-    /// Free args are assigned to recipe variables by their ordinal numbers (not by name).
-    pub fn set_free_args(&mut self, args: &[String]) {
-        self.varmgr.free = Vec::from_iter(args.iter().cloned());
+        if self.opts.ignore_case {
+            let low = name.to_lowercase();
+            let mut found: Vec<&RecipeDesc> = Vec::new();
+            for sec in &self.recipes {
+                if sec.name.to_lowercase() == low && !found.iter().any(|f| f.name == sec.name) {
+                    found.push(sec);
+                }
+            }
+            if found.len() == 1 {
+                return Ok(found[0].clone());
+            }
+            if found.len() > 1 {
+                let names: Vec<String> = found.iter().map(|f| f.name.clone()).collect();
+                return Err(HakuError::RecipeAmbiguousError(name.to_string(), names.join(", ")));
+            }
+        }
+        Err(HakuError::RecipeNotFoundError(name.to_string(), self.recipe_hint(name)))
     }
 
-    /// Execute a recipe. If `name` is empty DEFAULT_RECIPE is executed. If `name` is not
-    /// empty the recipe with this names must exist and be active.
-    /// In all cases, the engine runs all the lines until the first recipe in all imported
-    /// scripts.
-    pub fn run_recipe(&mut self, name: &str) -> Result<(), HakuError> {
-        output!(self.opts.verbosity, 1, "Running SECTION '{}'", name);
-        let sec_res = if name.is_empty() {
-            match self.find_recipe(DEFAULT_RECIPE) {
-                Ok(loc) => Some(loc),
-                _ => None,
+    /// Looks for an available recipe with the name that is the closest to `name`. Returns
+    /// `None` if no recipe has a similar name.
+    fn closest_recipe(&self, name: &str) -> Option<&str> {
+        let max_dist = (name.chars().count() / 2).max(1);
+        let mut best: Option<(usize, &str)> = None;
+        for r in self.recipes.iter() {
+            if r.system {
+                continue;
+            }
+            let dist = levenshtein(name, &r.name);
+            if dist > max_dist {
+                continue;
+            }
+            match best {
+                Some((d, _)) if d <= dist => {}
+                _ => best = Some((dist, &r.name)),
             }
-        } else {
-            Some(self.find_recipe(name)?)
-        };
-
-        self.exec_init()?;
-        if let Some(sec) = sec_res {
-            // default recipe can be missing
-            return self.exec_recipe(sec.loc);
         }
-        Err(HakuError::DefaultRecipeError)
+        best.map(|(_, nm)| nm)
     }
 
-    pub(crate) fn set_shell(&mut self, new_shell: Vec<String>) -> FuncResult {
-        // TODO: some sanity checks?
-        if new_shell.is_empty() {
-            return Err("shell cannot be empty".to_string());
+    /// Returns a hint to append to "not found" error message. Returns empty string if no
+    /// recipe has a similar name.
+    fn recipe_hint(&self, name: &str) -> String {
+        match self.closest_recipe(name) {
+            Some(nm) => format!(", did you mean '{}'?", nm),
+            None => String::new(),
         }
-        output!(self.opts.verbosity, 1, "Setting new shell: {:?}", new_shell);
-        self.shell = new_shell;
-        Ok(VarValue::from(1))
     }
 
-    pub(crate) fn set_env_var(&mut self, name: String, value: String) -> FuncResult {
-        if name.is_empty() {
-            return Err("variable name missing".to_string());
-        }
-        output!(self.opts.verbosity, 1, "Change env var {} to '{}'", name, value);
-        self.varmgr.env.insert(name, value);
-        Ok(VarValue::from(1))
+    /// Returns `true` if a script has an active recipe with the name similar to `name`
+    /// (e.g., `name` is a mistyped recipe name).
+    pub fn has_similar_recipe(&self, name: &str) -> bool {
+        self.closest_recipe(name).is_some()
     }
 
-    pub(crate) fn del_env_var(&mut self, name: String) -> FuncResult {
-        if name.is_empty() {
-            return Err("variable name missing".to_string());
+    /// Returns `true` if a script has an active recipe with the given name.
+    pub fn has_recipe(&self, name: &str) -> bool {
+        self.find_recipe(name).is_ok()
+    }
+
+    /// Returns `true` if the default recipe exists and has arguments.
+    pub fn default_has_args(&self) -> bool {
+        match self.find_recipe(DEFAULT_RECIPE) {
+            Ok(r) => !r.vars.is_empty(),
+            Err(_) => false,
+        }
+    }
+
+    /// Replaces the destinations of messages displayed by the engine itself: `out` receives
+    /// executed commands, progress of loops, and trace lines, `err` receives warnings and
+    /// the output of `--debug-vars`.
+    /// It does not affect the output of print functions and external commands.
+    pub fn set_console(&mut self, out: Box<dyn Write>, err: Box<dyn Write>) {
+        self.console = out;
+        *self.console_err.borrow_mut() = err;
+    }
+
+    /// Displays a line in the engine console
+    fn echo(&mut self, text: &str) {
+        let _ = writeln!(self.console, "{}", text);
+    }
+
+    /// Displays a line in the engine error console
+    fn echo_err(&mut self, text: &str) {
+        let _ = writeln!(self.console_err.borrow_mut(), "{}", text);
+    }
+
+    /// Sets the values to initialize recipe variables (used by a caller).
+    /// Free args are assigned to recipe variables by their ordinal numbers (not by name).
+    pub fn set_free_args(&mut self, args: &[String]) {
+        self.varmgr.free = Vec::from_iter(args.iter().cloned());
+    }
+
+    /// Returns the number of free arguments
+    pub(crate) fn free_arg_count(&self) -> usize {
+        self.varmgr.free.len()
+    }
+
+    /// Redirects the output of print functions to a file. A relative path is related to the
+    /// current working directory. Empty path restores printing to standard output.
+    /// The file is truncated unless `append` is `true`.
+    pub(crate) fn set_output(&mut self, path: &str, append: bool) -> FuncResult {
+        self.flush_output()?;
+        self.output = None;
+        if path.is_empty() {
+            output!(self.opts.verbosity, 1, "Output to stdout");
+            return Ok(VarValue::from(1));
+        }
+        let path = self.cwd.join(path);
+        output!(self.opts.verbosity, 1, "Output to {:?}", path);
+        let f = if append { OpenOptions::new().create(true).append(true).open(&path) } else { File::create(&path) };
+        match f {
+            Ok(f) => {
+                self.output = Some(BufWriter::new(f));
+                Ok(VarValue::from(1))
+            }
+            Err(e) => Err(format!("failed to open {:?}: {}", path, e)),
+        }
+    }
+
+    /// Writes a text to the current output: a buffer of a captured recipe, a file set by
+    /// `set_output`, or standard output
+    pub(crate) fn write_output(&mut self, text: &str) -> FuncResult {
+        if let Some(buf) = self.captures.last_mut() {
+            buf.push_str(text);
+            return Ok(VarValue::from(1));
+        }
+        let res = match self.output.as_mut() {
+            Some(f) => f.write_all(text.as_bytes()),
+            None => {
+                print!("{}", text);
+                Ok(())
+            }
+        };
+        match res {
+            Ok(()) => Ok(VarValue::from(1)),
+            Err(e) => Err(format!("failed to write output: {}", e)),
+        }
+    }
+
+    /// Flushes buffered output to the file if the output is redirected
+    fn flush_output(&mut self) -> FuncResult {
+        if let Some(f) = self.output.as_mut() {
+            if let Err(e) = f.flush() {
+                return Err(format!("failed to write output: {}", e));
+            }
+        }
+        Ok(VarValue::from(1))
+    }
+
+    /// Returns `true` if a user-defined feature is enabled in command line
+    pub(crate) fn is_feature_on(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.opts.all_features || self.opts.feats.iter().any(|f| f.to_lowercase() == name)
+    }
+
+    /// Removes up to `cnt` first free arguments and returns the removed ones
+    pub(crate) fn shift_free_args(&mut self, cnt: usize) -> Vec<String> {
+        let cnt = cnt.min(self.varmgr.free.len());
+        self.varmgr.free.drain(..cnt).collect()
+    }
+
+    /// Execute a recipe. If `name` is empty DEFAULT_RECIPE is executed. If `name` is not
+    /// empty the recipe with this names must exist and be active.
+    /// In all cases, the engine runs all the lines until the first recipe in all imported
+    /// scripts.
+    pub fn run_recipe(&mut self, name: &str) -> Result<(), HakuError> {
+        output!(self.opts.verbosity, 1, "Running SECTION '{}'", name);
+        let sec_res =
+            if name.is_empty() { self.find_recipe(DEFAULT_RECIPE).ok() } else { Some(self.find_recipe(name)?) };
+
+        self.exec_init()?;
+        if let Some(sec) = sec_res {
+            // a run is resumed only once: the next recipes run from the beginning
+            let start = mem::take(&mut self.opts.continue_from);
+            let only = mem::take(&mut self.opts.only);
+            let steps = if !only.is_empty() {
+                RecipeSteps::Only(only)
+            } else if !start.is_empty() {
+                RecipeSteps::From(start)
+            } else {
+                RecipeSteps::All
+            };
+            // default recipe can be missing
+            return self.exec_recipe(sec.loc, &steps);
+        }
+        if self.opts.allow_missing_default {
+            output!(self.opts.verbosity, 1, "Default recipe not found, nothing to do");
+            return Ok(());
+        }
+        Err(HakuError::DefaultRecipeError)
+    }
+
+    /// Remembers a temporary file or directory created by a script to remove it later
+    pub(crate) fn track_temp(&mut self, path: PathBuf) {
+        self.temp_paths.push(path);
+    }
+
+    /// Removes all temporary files and directories created by a script. Called when the
+    /// engine is destroyed, so the paths live until the last recipe of a run finishes. Does
+    /// nothing if a caller asked to keep them. Removal errors are not fatal: they are only reported
+    fn clean_temp(&mut self) {
+        if self.opts.keep_temp {
+            return;
+        }
+        for path in self.temp_paths.drain(..) {
+            let res = if path.is_dir() { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+            if let Err(e) = res {
+                if e.kind() != io::ErrorKind::NotFound {
+                    eprintln!("Failed to remove '{}': {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Runs a recipe from inside another one and returns everything the recipe prints: the
+    /// output of print functions and standard output of shell commands. The state of the
+    /// calling recipe(its local variables and loops) is restored after the recipe finishes.
+    pub(crate) fn capture_recipe(&mut self, name: &str) -> FuncResult {
+        let loc = match self.find_recipe(name) {
+            Ok(r) => r.loc,
+            Err(e) => return Err(e.to_string()),
+        };
+        let recipe_vars = mem::take(&mut self.varmgr.recipe_vars);
+        let cond_stack = mem::take(&mut self.cond_stack);
+        let (real_line, file_idx) = (self.real_line, self.file_idx);
+        self.captures.push(String::new());
+        let res = self.exec_recipe(loc, &RecipeSteps::All);
+        let text = self.captures.pop().unwrap_or_default();
+        self.varmgr.recipe_vars = recipe_vars;
+        self.cond_stack = cond_stack;
+        self.real_line = real_line;
+        self.file_idx = file_idx;
+        match res {
+            Ok(()) => Ok(VarValue::Str(text.trim_end().to_string())),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Runs script headers and returns the shell that executes external commands: the default
+    /// one for the current platform or the one set by `shell` function in a script header
+    pub fn resolved_shell(&mut self) -> Result<Vec<String>, HakuError> {
+        self.exec_init()?;
+        Ok(self.shell.clone())
+    }
+
+    /// Returns the executable of the current shell
+    pub(crate) fn shell_name(&self) -> String {
+        self.shell[0].clone()
+    }
+
+    pub(crate) fn set_shell(&mut self, new_shell: Vec<String>) -> FuncResult {
+        // TODO: some sanity checks?
+        if new_shell.is_empty() {
+            return Err("shell cannot be empty".to_string());
+        }
+        output!(self.opts.verbosity, 1, "Setting new shell: {:?}", new_shell);
+        self.shell = new_shell;
+        Ok(VarValue::from(1))
+    }
+
+    pub(crate) fn set_env_var(&mut self, name: String, value: String) -> FuncResult {
+        if name.is_empty() {
+            return Err("variable name missing".to_string());
+        }
+        output!(self.opts.verbosity, 1, "Change env var {} to '{}'", name, value);
+        self.varmgr.env.insert(name, value);
+        Ok(VarValue::from(1))
+    }
+
+    /// Returns `true` if a variable with the name exists: a recipe local, a global, or
+    /// an environment variable. The value of the variable can be empty.
+    pub(crate) fn is_var_defined(&self, name: &str) -> bool {
+        self.varmgr.var(name) != VarValue::Undefined
+    }
+
+    pub(crate) fn unset_var(&mut self, name: String) -> FuncResult {
+        if name.is_empty() {
+            return Err("variable name missing".to_string());
+        }
+        output!(self.opts.verbosity, 1, "Unset var {}", name);
+        Ok(VarValue::from(if self.varmgr.unset_var(&name) { 1 } else { 0 }))
+    }
+
+    pub(crate) fn del_env_var(&mut self, name: String) -> FuncResult {
+        if name.is_empty() {
+            return Err("variable name missing".to_string());
         }
         output!(self.opts.verbosity, 1, "Delete env var {}", name);
         self.varmgr.env.remove(&name);
@@ -611,6 +1479,28 @@ impl Engine {
         Ok(VarValue::from(1))
     }
 
+    /// Prints a shell command or an assignment before executing it if tracing is enabled.
+    /// The line looks like `+ <file>:<line> <statement>`, and all variables in the statement
+    /// are substituted with their values. Other statements are not printed.
+    fn trace_op(&mut self, op: &Op, line: usize) {
+        if !self.opts.trace {
+            return;
+        }
+        let stmt = match op {
+            Op::Shell(_, _, cmd) => cmd.clone(),
+            Op::Assign(..) | Op::DefAssign(..) | Op::EitherAssign(..) | Op::CompoundAssign(..) => {
+                match self.files[self.file_idx].orig_lines.get(line) {
+                    Some(s) => s.trim().to_string(),
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        let stmt = self.varmgr.interpolate(&stmt, true);
+        let text = format!("+ {}:{} {}", self.script_name(self.file_idx), line + 1, stmt);
+        self.echo_err(&text);
+    }
+
     /// Executes a script from the first line until the first recipe or end of the script.
     fn exec_file_init(&mut self, file: usize) -> Result<(), HakuError> {
         let cnt = self.files[file].ops.len();
@@ -619,17 +1509,18 @@ impl Engine {
             let op = self.files[file].ops[i].clone();
             self.real_line = op.line;
             self.file_idx = file;
+            self.trace_op(&op.op, op.line);
             match op.op {
-                Op::Recipe(_, _, _, _) | Op::Return => return Ok(()),
+                Op::Recipe(_, _, _, _, _) | Op::Return => return Ok(()),
                 Op::Include(_, _) => {
                     i += 1;
                 }
-                Op::Error(msg) => return Err(HakuError::UserError(format!("{} at line {}", msg, op.line))),
-                Op::DocComment(_) | Op::Comment(_) => {
+                Op::Error(msg) => return Err(HakuError::UserError(msg, self.error_extra())),
+                Op::DocComment(_) | Op::Comment => {
                     i += 1;
                 }
-                Op::Shell(flags, cmd) => {
-                    self.exec_cmd_shell(flags, &cmd)?;
+                Op::Shell(flags, shell, cmd) => {
+                    self.exec_cmd_shell(flags, &shell, &cmd)?;
                     i += 1;
                 }
                 Op::EitherAssign(chk, name, ops) => {
@@ -644,6 +1535,10 @@ impl Engine {
                     self.exec_assign(&name, &ops)?;
                     i += 1;
                 }
+                Op::CompoundAssign(name, arith, ops) => {
+                    self.exec_compound_assign(&name, &arith, &ops)?;
+                    i += 1;
+                }
                 Op::Func(name, ops) => {
                     self.exec_func(&name, &ops)?;
                     i += 1;
@@ -656,16 +1551,16 @@ impl Engine {
                         i = next;
                     }
                 }
-                Op::For(name, seq) => {
-                    let ok = self.exec_for(&name, seq, i)?;
-                    if ok {
+                Op::For(name, seq, keep_going) => {
+                    // a script header has no recipe flags, so it is never quiet
+                    if self.exec_for(&name, seq, i, keep_going)? {
+                        self.start_progress();
                         i += 1;
                     } else {
                         i = self.find_end(file, i + 1, "for")?;
                     }
                 }
                 Op::While(ops) => {
-                    // must have exact 1 op
                     let ok = self.exec_while(&ops, i)?;
                     if ok {
                         i += 1;
@@ -687,7 +1582,7 @@ impl Engine {
                 }
                 Op::ElseIf(ops) => {
                     i = self.exec_elseif(&ops, file, i)?;
-                } // must have exact 1 op
+                }
                 Op::Cd(flags, p) => {
                     self.exec_cd(flags, &p)?;
                     i += 1;
@@ -721,6 +1616,7 @@ impl Engine {
     fn push_recipe(
         &mut self,
         loc: RecipeLoc,
+        args: Option<Vec<String>>,
         found: Option<&[RecipeItem]>,
         parent: Option<&[String]>,
     ) -> Result<Vec<RecipeItem>, HakuError> {
@@ -730,6 +1626,8 @@ impl Engine {
             loc: RecipeLoc { file: 0, line: 0, script_line: 0 },
             vars: Vec::new(),
             flags: 0,
+            args: None,
+            cleanup: None,
         };
         output!(self.opts.verbosity, 2, "Checking recipe: {:?}", op);
         let mut vc: Vec<RecipeItem> = Vec::new();
@@ -738,11 +1636,17 @@ impl Engine {
             Some(p) => p.iter().map(|a| a.to_string()).collect(),
         };
         match op.op {
-            Op::Recipe(name, flags, vars, deps) => {
-                if vc.iter().any(|s| s.name == name) || deps.iter().any(|d| d == &name) {
+            Op::Recipe(name, flags, vars, deps, cleanup) => {
+                if vc.iter().any(|s| s.name == name) || deps.iter().any(|d| d.name == name) {
                     return Err(HakuError::RecipeRecursionError(name, self.error_extra()));
                 }
-                for dep in deps {
+                for RecipeDep { name: dep, args: dep_args, cond } in deps {
+                    if let Some(cond) = cond {
+                        if !self.exec_op(&cond)?.is_true() {
+                            output!(self.opts.verbosity, 2, "Skipping dependency {}: condition is false", dep);
+                            continue;
+                        }
+                    }
                     if let Some(ps) = parent {
                         if ps.iter().any(|p| p == &dep) {
                             return Err(HakuError::RecipeRecursionError(dep, self.error_extra()));
@@ -755,13 +1659,18 @@ impl Engine {
                     }
                     let next_s = self.find_recipe(&dep)?;
                     parents.push(name.clone());
-                    let mut slist = self.push_recipe(next_s.loc, Some(&vc), Some(&parents))?;
+                    let mut slist = self.push_recipe(next_s.loc, dep_args, Some(&vc), Some(&parents))?;
                     vc.append(&mut slist);
                 }
                 sec_item.name = name;
                 sec_item.loc = loc;
                 sec_item.vars = vars;
                 sec_item.flags = flags;
+                sec_item.args = args;
+                if cleanup.as_ref() == Some(&sec_item.name) {
+                    return Err(HakuError::RecipeRecursionError(sec_item.name, self.error_extra()));
+                }
+                sec_item.cleanup = cleanup;
             }
             _ => unreachable!(),
         }
@@ -770,24 +1679,58 @@ impl Engine {
     }
 
     /// Runs a given recipe. First, it runs all recipe dependencies recursively. Second,
-    /// it runs the body of the given recipe.
-    fn exec_recipe(&mut self, loc: RecipeLoc) -> Result<(), HakuError> {
+    /// it runs the body of the given recipe. If a recipe has a cleanup one(`#[finally]`),
+    /// the cleanup recipe runs right after the body whether the body fails or not.
+    /// `steps` selects which recipes from the list of recipes to run are executed.
+    fn exec_recipe(&mut self, loc: RecipeLoc, steps: &RecipeSteps) -> Result<(), HakuError> {
         output!(self.opts.verbosity, 2, "Start recipe [{}:{}]", loc.file, loc.line);
         self.real_line = loc.script_line;
         self.file_idx = loc.file;
-        let sec = self.push_recipe(loc, None, None)?;
+        let sec = self.push_recipe(loc, None, None, None)?;
         output!(self.opts.verbosity, 2, "recipe call stack: {:?}", sec);
-        let mut idx = 0;
-        while idx < sec.len() {
+        let (mut idx, last) = match steps {
+            RecipeSteps::All => (0, sec.len()),
+            RecipeSteps::From(name) => (self.step_index(&sec, name)?, sec.len()),
+            RecipeSteps::Only(name) => {
+                let idx = self.step_index(&sec, name)?;
+                (idx, idx + 1)
+            }
+        };
+        for (i, skipped) in sec.iter().enumerate() {
+            if i < idx || i >= last {
+                output!(self.opts.verbosity, 1, "Skipping recipe {}: it is not selected to run", skipped.name);
+            }
+        }
+        while idx < last {
             let now = Instant::now();
             let op = &sec[idx];
+            if is_flag_on(op.flags, FLAG_ONCE) && !self.once_done.insert(op.name.clone()) {
+                output!(self.opts.verbosity, 1, "Skipping recipe {}: it has already been run", op.name);
+                idx += 1;
+                continue;
+            }
             output!(self.opts.verbosity, 1, "Starting recipe: {}", op.name);
-            self.enter_recipe(op);
-            self.exec_from(op.loc.file, op.loc.line + 1, op.flags)?;
+            // an isolated recipe does not change environment variables for the rest of the script
+            let env_snapshot =
+                if is_flag_on(op.flags, FLAG_ISOLATE_ENV) { Some(self.varmgr.env.clone()) } else { None };
+            let res = match self.enter_recipe(op) {
+                Ok(()) => self.exec_from(op.loc.file, op.loc.line + 1, op.flags),
+                Err(e) => Err(e),
+            };
             self.leave_recipe();
+            if let Some(env) = env_snapshot {
+                self.varmgr.env = env;
+            }
+            let cleanup_res = match &op.cleanup {
+                None => Ok(()),
+                Some(cleanup) => self.run_cleanup(cleanup),
+            };
+            // the error of the recipe is more important than the cleanup one
+            res?;
+            cleanup_res?;
             let dur = now.elapsed();
             if self.opts.show_time {
-                println!("Section {} finished in {}", op.name, human_duration(dur));
+                self.echo(&format!("Section {} finished in {}", op.name, human_duration(dur)));
             } else {
                 output!(self.opts.verbosity, 1, "Section {} finished in {}", op.name, human_duration(dur));
             }
@@ -796,6 +1739,38 @@ impl Engine {
         Ok(())
     }
 
+    /// Runs the cleanup recipe `name`. It is an error if the recipe is already running as
+    /// a cleanup one(e.g., two recipes clean up after each other)
+    fn run_cleanup(&mut self, name: &str) -> Result<(), HakuError> {
+        output!(self.opts.verbosity, 1, "Running cleanup recipe: {}", name);
+        let (name, loc) = match self.find_recipe(name) {
+            Ok(r) => (r.name, r.loc),
+            Err(e) => return Err(e),
+        };
+        if !self.cleanups.insert(name.clone()) {
+            return Err(HakuError::RecipeRecursionError(name, self.error_extra()));
+        }
+        let res = self.exec_recipe(loc, &RecipeSteps::All);
+        self.cleanups.remove(&name);
+        res
+    }
+
+    /// Returns the position of recipe `name` in the list of recipes to run `sec`. It is an
+    /// error if the recipe is not in the list
+    fn step_index(&self, sec: &[RecipeItem], name: &str) -> Result<usize, HakuError> {
+        let found = match self.find_recipe(name) {
+            Ok(r) => sec.iter().position(|s| s.name == r.name),
+            Err(_) => None,
+        };
+        match found {
+            Some(idx) => Ok(idx),
+            None => {
+                let names: Vec<&str> = sec.iter().map(|s| s.name.as_str()).collect();
+                Err(HakuError::RecipeNotInRunError(name.to_string(), names.join(", ")))
+            }
+        }
+    }
+
     /// Executes a script from a given file and the line in it. Used by run recipe function:
     /// it looks for a recipe location and then executes from that position.
     fn exec_from(&mut self, file: usize, line: usize, sec_flags: u32) -> Result<(), HakuError> {
@@ -805,86 +1780,84 @@ impl Engine {
             let op = (self.files[file].ops[idx]).clone();
             self.real_line = op.line;
             self.file_idx = file;
-            match op.op {
-                Op::Return | Op::Recipe(_, _, _, _) => return Ok(()),
-                Op::Include(_, _) => return Err(HakuError::IncludeInRecipeError(self.error_extra())),
-                Op::Error(msg) => return Err(HakuError::UserError(format!("{} at line {}", msg, op.line))),
-                Op::Shell(flags, cmd) => {
-                    let cmd_flags = sec_flags ^ flags;
-                    self.exec_cmd_shell(cmd_flags, &cmd)?;
-                    idx += 1;
-                }
-                Op::EitherAssign(chk, name, ops) => {
-                    self.exec_either_assign(chk, &name, &ops)?;
-                    idx += 1;
-                }
-                Op::DefAssign(name, ops) => {
-                    self.exec_assign_or(&name, &ops)?;
-                    idx += 1;
-                }
-                Op::Assign(name, ops) => {
-                    self.exec_assign(&name, &ops)?;
-                    idx += 1;
-                }
-                Op::Func(name, ops) => {
-                    self.exec_func(&name, &ops)?;
-                    idx += 1;
-                } // top level - func value is dropped
-                Op::StmtClose => {
-                    let next = self.exec_end()?;
-                    if next == 0 {
-                        idx += 1
-                    } else {
-                        idx = next;
-                    }
-                }
-                Op::For(name, seq) => {
-                    let ok = self.exec_for(&name, seq, idx)?;
-                    if ok {
-                        idx += 1;
-                    } else {
-                        idx = self.find_end(file, idx + 1, "for")?;
-                    }
-                }
-                Op::While(ops) => {
-                    // must have exact 1 op
-                    let ok = self.exec_while(&ops, idx)?;
-                    if ok {
-                        idx += 1;
-                    } else {
-                        idx = self.find_end(file, idx + 1, "while")?;
-                    }
-                }
-                Op::Break => {
-                    idx = self.exec_break(file)?;
-                }
-                Op::Continue => {
-                    idx = self.exec_continue(file)?;
-                }
-                Op::If(ops) => {
-                    idx = self.exec_if(&ops, file, idx)?;
-                } // must have exact 1 op
-                Op::Else => {
-                    idx = self.exec_else(file, idx)?;
-                }
-                Op::ElseIf(ops) => {
-                    idx = self.exec_elseif(&ops, file, idx)?;
-                } // must have exact 1 op
-                Op::Cd(flags, p) => {
-                    let cmd_flags = sec_flags ^ flags;
-                    self.exec_cd(cmd_flags, &p)?;
-                    idx += 1;
-                }
-                Op::Pause => {
-                    self.exec_pause()?;
-                    idx += 1;
+            self.trace_op(&op.op, op.line);
+            match self.exec_stmt(op.op, file, idx, sec_flags) {
+                Ok(Some(next)) => idx = next,
+                Ok(None) => return Ok(()),
+                Err(e @ HakuError::ExecFailureError(..)) => idx = self.skip_failed_iteration(file, e)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes a single statement of a recipe. Returns the index of the next statement to
+    /// execute, or `None` if the recipe is finished.
+    fn exec_stmt(&mut self, op: Op, file: usize, idx: usize, sec_flags: u32) -> Result<Option<usize>, HakuError> {
+        match op {
+            Op::Return | Op::Recipe(_, _, _, _, _) => return Ok(None),
+            Op::Include(_, _) => return Err(HakuError::IncludeInRecipeError(self.error_extra())),
+            Op::Error(msg) => return Err(HakuError::UserError(msg, self.error_extra())),
+            Op::Shell(flags, shell, cmd) => {
+                let cmd_flags = line_flags(sec_flags, flags);
+                self.exec_cmd_shell(cmd_flags, &shell, &cmd)?;
+            }
+            Op::EitherAssign(chk, name, ops) => self.exec_either_assign(chk, &name, &ops)?,
+            Op::DefAssign(name, ops) => self.exec_assign_or(&name, &ops)?,
+            Op::Assign(name, ops) => self.exec_assign(&name, &ops)?,
+            Op::CompoundAssign(name, arith, ops) => self.exec_compound_assign(&name, &arith, &ops)?,
+            Op::Func(name, ops) => {
+                // top level - func value is dropped
+                self.exec_func(&name, &ops)?;
+            }
+            Op::StmtClose => {
+                let next = self.exec_end()?;
+                if next != 0 {
+                    return Ok(Some(next));
                 }
-                _ => {
-                    idx += 1; /* just skip */
+            }
+            Op::For(name, seq, keep_going) => {
+                let ok = self.exec_for(&name, seq, idx, keep_going)?;
+                if ok && !is_flag_on(sec_flags, FLAG_QUIET) {
+                    self.start_progress();
                 }
+                return if ok { Ok(Some(idx + 1)) } else { self.find_end(file, idx + 1, "for").map(Some) };
             }
+            Op::While(ops) => {
+                let ok = self.exec_while(&ops, idx)?;
+                return if ok { Ok(Some(idx + 1)) } else { self.find_end(file, idx + 1, "while").map(Some) };
+            }
+            Op::Break => return self.exec_break(file).map(Some),
+            Op::Continue => return self.exec_continue(file).map(Some),
+            Op::If(ops) => return self.exec_if(&ops, file, idx).map(Some),
+            Op::Else => return self.exec_else(file, idx).map(Some),
+            Op::ElseIf(ops) => return self.exec_elseif(&ops, file, idx).map(Some),
+            Op::Cd(flags, p) => {
+                let cmd_flags = line_flags(sec_flags, flags);
+                self.exec_cd(cmd_flags, &p)?;
+            }
+            Op::Pause => self.exec_pause()?,
+            _ => { /* just skip */ }
         }
-        Ok(())
+        Ok(Some(idx + 1))
+    }
+
+    /// Called when a command fails inside a recipe. If the command is inside a `for` loop
+    /// marked with `#[no-fail-fast]`, the failure is reported and counted, and the loop
+    /// continues with the next item. Returns the index of the loop `end` statement.
+    /// Otherwise, returns the error as is.
+    fn skip_failed_iteration(&mut self, file: usize, err: HakuError) -> Result<usize, HakuError> {
+        let pos = match self.cond_stack.iter().rposition(|c| c.failures.is_some()) {
+            None => return Err(err),
+            Some(pos) => pos,
+        };
+        self.echo_err(&err.to_string());
+        self.cond_stack.truncate(pos + 1);
+        let cnd = &mut self.cond_stack[pos];
+        cnd.failures = cnd.failures.map(|n| n + 1);
+        let line = cnd.line;
+        // step back to point to END statement
+        Ok(self.find_end(file, line + 1, "for")? - 1)
     }
 
     /// Looks for `end` statement for the current if/for/while considering nested if/for/while
@@ -903,7 +1876,7 @@ impl Engine {
                         return Ok(idx + 1);
                     }
                 }
-                Op::If(_) | Op::While(_) | Op::For(_, _) => nesting += 1,
+                Op::If(_) | Op::While(_) | Op::For(_, _, _) => nesting += 1,
                 _ => {}
             }
             idx += 1;
@@ -926,11 +1899,9 @@ impl Engine {
                         return Ok((true, idx + 1));
                     }
                 }
-                Op::If(_) | Op::While(_) | Op::For(_, _) => nesting += 1,
-                Op::ElseIf(_) | Op::Else => {
-                    if nesting == 1 {
-                        return Ok((false, idx));
-                    }
+                Op::If(_) | Op::While(_) | Op::For(_, _, _) => nesting += 1,
+                Op::ElseIf(_) | Op::Else if nesting == 1 => {
+                    return Ok((false, idx));
                 }
                 _ => {}
             }
@@ -946,8 +1917,13 @@ impl Engine {
     ///
     /// Internal function to use by `for` or assignment statement.
     fn exec_cmd(&mut self, cmdline: &str) -> Result<ExecResult, HakuError> {
-        let cmdline = self.varmgr.interpolate(&cmdline, true);
+        let cmdline = self.interpolate(cmdline, true)?;
         let mut eres = ExecResult { code: 0, stdout: String::new() };
+        if self.opts.dry_run && !self.opts.dry_run_smart {
+            // strict dry run: a command is considered successful without any output
+            output!(self.opts.verbosity, 1, "Dry run, skipping: {}", cmdline);
+            return Ok(eres);
+        }
         let mut cmd = Command::new(&self.shell[0]);
         for arg in self.shell[1..].iter() {
             cmd.arg(arg);
@@ -978,6 +1954,38 @@ impl Engine {
         Ok(eres)
     }
 
+    /// Executes a program directly, without a shell: every item of `args` is passed to the
+    /// program as a separate argument as is, so arguments are not split by whitespace and
+    /// special characters are not processed. Returns the exit code and the standard output
+    /// of the program.
+    pub(crate) fn exec_args(&mut self, prog: &str, args: &[String]) -> FuncResult {
+        output!(self.opts.verbosity, 2, "ExecArgs: {} {:?}", prog, args);
+        if self.opts.dry_run && !self.opts.dry_run_smart {
+            output!(self.opts.verbosity, 1, "Dry run, skipping: {} {:?}", prog, args);
+            return Ok(VarValue::Exec(ExecResult { code: 0, stdout: String::new() }));
+        }
+        let mut cmd = Command::new(prog);
+        cmd.args(args);
+        self.augment_cmd(&mut cmd);
+        let out = match cmd.output() {
+            Ok(o) => o,
+            Err(e) => return Err(format!("failed to execute {}: {}", prog, e)),
+        };
+        if !out.status.success() {
+            if let Ok(s) = String::from_utf8(out.stderr) {
+                eprint!("{}", s);
+            }
+        }
+        let stdout = match String::from_utf8(out.stdout) {
+            Ok(s) => s.trim_end().to_string(),
+            Err(_) => String::from("[Non-UTF-8 Output]"),
+        };
+        Ok(VarValue::Exec(ExecResult { code: out.status.code().unwrap_or(-1), stdout }))
+    }
+
+    /// Sets the current working directory and environment variables of the script for an
+    /// external command. Used by backtick and standalone shell commands, and by programs
+    /// executed without a shell.
     fn augment_cmd(&self, cmd: &mut Command) {
         if !self.cwd_history.is_empty() {
             cmd.current_dir(&self.cwd);
@@ -987,25 +1995,119 @@ impl Engine {
         }
     }
 
+    /// Builds a command that runs `cmdline` in `shell`(the current shell if `shell` is empty),
+    /// or runs it directly if a caller disabled the shell. The command gets the current
+    /// working directory and environment variables of the script.
+    fn shell_cmd(&self, shell: &[String], cmdline: &str) -> Result<Command, String> {
+        let mut cmd = if self.opts.no_shell && shell.is_empty() {
+            // without a shell, pipes and redirections would be passed to the program as arguments
+            let args = split_cmdline(cmdline)?;
+            let mut cmd = Command::new(&args[0]);
+            cmd.args(&args[1..]);
+            cmd
+        } else {
+            let shell = if shell.is_empty() { &self.shell } else { shell };
+            let mut cmd = Command::new(&shell[0]);
+            for arg in shell[1..].iter() {
+                cmd.arg(arg);
+            }
+            cmd.arg(cmdline);
+            cmd
+        };
+        self.augment_cmd(&mut cmd);
+        Ok(cmd)
+    }
+
+    /// Starts a shell command in background and returns its handle without waiting for the
+    /// command to finish. In dry-run mode the command is only displayed and the handle is `0`.
+    pub(crate) fn spawn_cmd(&mut self, cmdline: &str) -> FuncResult {
+        let cmdline = match self.interpolate(cmdline, true) {
+            Ok(s) => s,
+            Err(e) => return Err(e.to_string()),
+        };
+        output!(self.opts.verbosity, 2, "Spawn: {}", cmdline);
+        self.echo(&cmdline);
+        if self.opts.dry_run {
+            return Ok(VarValue::Int(0));
+        }
+        let mut cmd = self.shell_cmd(&[], &cmdline)?;
+        match cmd.spawn() {
+            Ok(child) => {
+                self.children.push(Some(child));
+                Ok(VarValue::Int(self.children.len() as i64))
+            }
+            Err(e) => Err(format!("failed to execute {}: {}", cmdline, e)),
+        }
+    }
+
+    /// Waits for a background command started by `spawn_cmd` to finish and returns its exit
+    /// code. A handle can be waited for only once.
+    pub(crate) fn wait_child(&mut self, handle: i64) -> FuncResult {
+        if self.opts.dry_run && handle == 0 {
+            return Ok(VarValue::Int(0));
+        }
+        let child = if handle > 0 { self.children.get_mut(handle as usize - 1) } else { None };
+        let mut child = match child {
+            None => return Err(format!("invalid process handle {}", handle)),
+            Some(c) => match c.take() {
+                None => return Err(format!("process {} has already been waited for", handle)),
+                Some(c) => c,
+            },
+        };
+        match child.wait() {
+            Ok(st) => Ok(VarValue::Int(st.code().unwrap_or(-1) as i64)),
+            Err(e) => Err(format!("failed to wait for process {}: {}", handle, e)),
+        }
+    }
+
+    /// Kills all background commands that are still running. Called when the engine is
+    /// destroyed, so a script does not leave forgotten processes behind
+    fn kill_children(&mut self) {
+        for child in self.children.iter_mut().filter_map(|c| c.take()) {
+            let mut child = child;
+            if let Ok(None) = child.try_wait() {
+                output!(self.opts.verbosity, 1, "Killing background process {}", child.id());
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+    }
+
     /// Executes external command and collects its standard and error output, and exit code.
     /// Before execution the engine substitutes used variables in command line.
     ///
-    /// Used by script lines that are standalone shell calls, like `rm "${filename}"`
-    fn exec_cmd_shell(&mut self, flags: u32, cmdline: &str) -> Result<(), HakuError> {
+    /// Used by script lines that are standalone shell calls, like `rm "${filename}"`.
+    /// If `shell` is not empty, it is used instead of the current shell.
+    fn exec_cmd_shell(&mut self, flags: u32, shell: &[String], cmdline: &str) -> Result<(), HakuError> {
         let no_fail = is_flag_on(flags, FLAG_PASS);
-        let cmdline = self.varmgr.interpolate(&cmdline, true);
+        let cmdline = self.interpolate(cmdline, true)?;
         output!(self.opts.verbosity, 2, "ExecShell[{}]: {}", no_fail, cmdline);
+        if self.opts.dry_run {
+            // in dry-run mode every command is displayed, even a quiet one
+            self.echo(&cmdline);
+            return Ok(());
+        }
         if !is_flag_on(flags, FLAG_QUIET) {
-            println!("{}", cmdline);
+            self.echo(&cmdline);
         }
 
-        let mut cmd = Command::new(&self.shell[0]);
-        for arg in self.shell[1..].iter() {
-            cmd.arg(arg);
-        }
-        cmd.arg(&cmdline);
-        self.augment_cmd(&mut cmd);
-        let result = cmd.status();
+        let mut cmd = match self.shell_cmd(shell, &cmdline) {
+            Ok(cmd) => cmd,
+            Err(e) => return Err(HakuError::NoShellError(cmdline, e, self.error_extra())),
+        };
+        let result = if self.captures.is_empty() {
+            cmd.status()
+        } else {
+            // a recipe output is captured: collect standard output instead of displaying it
+            cmd.stderr(Stdio::inherit());
+            cmd.output().map(|out| {
+                let text = String::from_utf8_lossy(&out.stdout).to_string();
+                if let Some(buf) = self.captures.last_mut() {
+                    buf.push_str(&text);
+                }
+                out.status
+            })
+        };
         let st = match result {
             Ok(exit_status) => exit_status,
             Err(e) => {
@@ -1027,7 +2129,40 @@ impl Engine {
         Ok(())
     }
 
+    /// Executes a shell command up to `attempts` times until it succeeds, waiting `delay`
+    /// between attempts. After every failed attempt the delay is multiplied by `multiplier`,
+    /// but it never exceeds `MAX_RETRY_DELAY`. In dry-run mode the engine does not wait.
+    /// Returns the number of the successful attempt, or the error of the last attempt if
+    /// all of them fail.
+    pub(crate) fn retry_cmd(&mut self, attempts: usize, cmdline: &str, delay: Duration, multiplier: u32) -> FuncResult {
+        if attempts == 0 {
+            return Err("number of attempts must be greater than 0".to_string());
+        }
+        if multiplier == 0 {
+            return Err("delay multiplier must be greater than 0".to_string());
+        }
+        let shell = Vec::new();
+        let mut delay = delay.min(MAX_RETRY_DELAY);
+        for attempt in 1..=attempts {
+            match self.exec_cmd_shell(0, &shell, cmdline) {
+                Ok(()) => return Ok(VarValue::Int(attempt as i64)),
+                Err(e) => {
+                    if attempt == attempts {
+                        return Err(format!("all {} attempts failed: {}", attempts, e));
+                    }
+                    output!(self.opts.verbosity, 1, "Attempt {} failed: {}", attempt, e);
+                }
+            }
+            if !delay.is_zero() && !self.opts.dry_run {
+                thread::sleep(delay);
+            }
+            delay = delay.saturating_mul(multiplier).min(MAX_RETRY_DELAY);
+        }
+        unreachable!()
+    }
+
     /// Evaluates `ops` one by one and assigns the first non-falsy result to variable `name`.
+    /// The rest of `ops` are not evaluated, so their external commands are not executed.
     /// When `chk` is `true` it evaluates and assigns the new value only if the variable is
     /// falsy one(0, empty string, or shell command with non-zero exit code)
     fn exec_either_assign(&mut self, chk: bool, name: &str, ops: &[Op]) -> Result<(), HakuError> {
@@ -1053,28 +2188,47 @@ impl Engine {
         if chk && self.varmgr.var(name).is_true() {
             return Ok(());
         }
-        let cnt = ops.len(); // 1=simple assign, >1=logical
-        let mut val = false;
-        for op in ops.iter() {
-            let v = self.exec_op(op)?;
-            if cnt == 1 {
-                // simple assign
-                self.varmgr.set_var(name, v);
-                return Ok(());
-            }
-            if v.is_true() {
-                val = true;
-                break;
+        let v = self.exec_or_expr(ops)?;
+        self.varmgr.set_var(name, v);
+        Ok(())
+    }
+
+    /// Applies an arithmetic operation `arith`(`+`, `-`, `*`, or `/`) to the current value of
+    /// variable `name` and the result of `ops`, and assigns the result back to the variable.
+    /// Both values are converted to integers. Undefined variable is treated as `0`.
+    fn exec_compound_assign(&mut self, name: &str, arith: &str, ops: &[Op]) -> Result<(), HakuError> {
+        let curr = self.varmgr.var(name).to_int();
+        let val = self.exec_or_expr(ops)?.to_int();
+        let res = match arith {
+            "+" => curr.wrapping_add(val),
+            "-" => curr.wrapping_sub(val),
+            "*" => curr.wrapping_mul(val),
+            "/" => {
+                if val == 0 {
+                    return Err(HakuError::DivisionByZeroError(name.to_string(), self.error_extra()));
+                }
+                curr.wrapping_div(val)
             }
+            _ => unreachable!(),
+        };
+        self.varmgr.set_var(name, VarValue::Int(res));
+        Ok(())
+    }
+
+    /// Evaluates `ops` joined with OR one by one: returns 1 immediately when the first truthy
+    /// value is met, and returns 0 if all items are falsy. The rest items after the first
+    /// truthy one are not evaluated, so their shell commands and functions are not executed.
+    /// A single item is returned as is.
+    fn exec_or_expr(&mut self, ops: &[Op]) -> Result<VarValue, HakuError> {
+        if ops.len() == 1 {
+            return self.exec_op(&ops[0]);
         }
-        if val {
-            let v = VarValue::Int(1);
-            self.varmgr.set_var(name, v);
-        } else {
-            let v = VarValue::Int(0);
-            self.varmgr.set_var(name, v);
+        for op in ops.iter() {
+            if self.exec_op(op)?.is_true() {
+                return Ok(VarValue::Int(1));
+            }
         }
-        Ok(())
+        Ok(VarValue::Int(0))
     }
 
     fn exec_assign_or(&mut self, name: &str, ops: &[Op]) -> Result<(), HakuError> {
@@ -1086,27 +2240,18 @@ impl Engine {
     }
 
     /// Evaluates `ops` one by one: return 1 if all items are evaluated as `true`,
-    /// and returns 0 immediately when the first falsy value is met.
+    /// and returns 0 immediately when the first falsy value is met. The rest items are not
+    /// evaluated. A single item is returned as is.
     fn exec_and_expr(&mut self, ops: &[Op]) -> Result<VarValue, HakuError> {
-        let cnt = ops.len();
-        let mut val = true;
-
+        if ops.len() == 1 {
+            return self.exec_op(&ops[0]);
+        }
         for op in ops.iter() {
-            let v = self.exec_op(op)?;
-            if cnt == 1 {
-                // single value
-                return Ok(v);
-            }
-            if !v.is_true() {
-                val = false;
-                break;
+            if !self.exec_op(op)?.is_true() {
+                return Ok(VarValue::Int(0));
             }
         }
-        if val {
-            Ok(VarValue::Int(1))
-        } else {
-            Ok(VarValue::Int(0))
-        }
+        Ok(VarValue::Int(1))
     }
 
     /// Executes a built-in function. First, it tries to execute as a system function(that
@@ -1115,29 +2260,51 @@ impl Engine {
         output!(self.opts.verbosity, 2, "Exec func {}, args: {:?}", name, ops);
         let mut args: Vec<VarValue> = Vec::new();
         for op in ops.iter() {
+            if let Op::Spread(inner) = op {
+                // now Spread must contain only 1 op - it should be by *.pest rules
+                for o in inner.iter() {
+                    match self.exec_op(o)? {
+                        VarValue::List(v) => args.extend(v.into_iter().map(VarValue::Str)),
+                        v => args.push(v),
+                    }
+                }
+                continue;
+            }
             let v = self.exec_op(op)?;
             args.push(v);
         }
         let r = run_func(name, self, &args);
         output!(self.opts.verbosity, 3, "func {} with {} args returned {:?}", name, ops.len(), r);
-        r.map_err(|s| HakuError::FunctionError(format!("{}: {}", s, self.error_extra())))
+        match r {
+            // `exit` returns the code to stop the run with
+            Ok(code) if name.eq_ignore_ascii_case("exit") => Err(HakuError::Exit(code.to_int() as i32)),
+            r => r.map_err(|msg| {
+                let file = self.script_name(self.file_idx).to_string();
+                let line = if self.real_line == usize::MAX { 0 } else { self.real_line + 1 };
+                HakuError::FunctionError { name: name.to_string(), msg, file, line, extra: self.error_extra() }
+            }),
+        }
     }
 
     /// Evaluates a condition `ops`. If it is true, starts executing `if` body. Otherwise,
     /// looks for corresponding `elseif`/`else`/`end` which comes first.
     fn exec_if(&mut self, ops: &[Op], file: usize, idx: usize) -> Result<usize, HakuError> {
         output!(self.opts.verbosity, 3, "Exec if");
-        assert!(ops.len() == 1);
-        let v = self.exec_op(&ops[0])?;
+        let v = self.exec_or_expr(ops)?;
         if v.is_true() {
             output!(self.opts.verbosity, 3, "   if == true");
-            self.cond_stack.push(CondItem { line: idx, cond: Condition::If(true) });
+            self.cond_stack.push(CondItem { line: idx, cond: Condition::If(true), failures: None, progress: None });
             Ok(idx + 1)
         } else {
             output!(self.opts.verbosity, 3, "   if == false -> look for else/end");
             let (is_end, else_idx) = self.find_else(file, idx + 1, "if")?;
             if !is_end {
-                self.cond_stack.push(CondItem { line: idx, cond: Condition::If(false) });
+                self.cond_stack.push(CondItem {
+                    line: idx,
+                    cond: Condition::If(false),
+                    failures: None,
+                    progress: None,
+                });
             }
             Ok(else_idx)
         }
@@ -1174,16 +2341,15 @@ impl Engine {
         if self.cond_stack.is_empty() {
             return Err(HakuError::StrayElseIfError(self.error_extra()));
         }
-        assert!(ops.len() == 1);
 
         let op = self.cond_stack[self.cond_stack.len() - 1].clone();
         match op.cond {
             Condition::If(c) => {
                 if c {
                     self.cond_stack.pop();
-                    return Ok(self.find_end(file, idx + 1, "else")?);
+                    return self.find_end(file, idx + 1, "else");
                 }
-                let v = self.exec_op(&ops[0])?;
+                let v = self.exec_or_expr(ops)?;
                 if v.is_true() {
                     let mut cnd = match self.cond_stack.pop() {
                         Some(cc) => cc,
@@ -1205,11 +2371,10 @@ impl Engine {
     /// looks for corresponding `end`.
     fn exec_while(&mut self, ops: &[Op], idx: usize) -> Result<bool, HakuError> {
         output!(self.opts.verbosity, 3, "Exec while {:?}", ops);
-        assert!(ops.len() == 1);
-        let v = self.exec_op(&ops[0])?;
+        let v = self.exec_or_expr(ops)?;
         if v.is_true() {
             let lst: Vec<Op> = ops.to_vec();
-            self.cond_stack.push(CondItem { line: idx, cond: Condition::While(lst) });
+            self.cond_stack.push(CondItem { line: idx, cond: Condition::While(lst), failures: None, progress: None });
         }
         Ok(v.is_true())
     }
@@ -1224,9 +2389,7 @@ impl Engine {
             match op.cond {
                 Condition::If(_) => Ok(0), // just continue
                 Condition::While(ref ops) => {
-                    // should be 1 operation
-                    assert!(ops.len() == 1);
-                    let val = self.exec_op(&ops[0])?;
+                    let val = self.exec_or_expr(ops)?;
                     if val.is_true() {
                         let ln = op.line + 1;
                         self.cond_stack.push(op);
@@ -1238,22 +2401,34 @@ impl Engine {
                 Condition::ForList(var, mut vals) => {
                     output!(self.opts.verbosity, 3, "END FOR LIST: {} = {:?}", var, vals);
                     if vals.is_empty() {
-                        return Ok(0);
+                        return self.finish_loop(op.failures);
                     }
                     let val = vals[0].clone();
                     vals.remove(0);
                     self.varmgr.set_var(&var, VarValue::Str(val));
-                    self.cond_stack.push(CondItem { line: op.line, cond: Condition::ForList(var, vals) });
+                    self.cond_stack.push(CondItem {
+                        line: op.line,
+                        cond: Condition::ForList(var, vals),
+                        failures: op.failures,
+                        progress: op.progress.map(|(n, total)| (n + 1, total)),
+                    });
+                    self.show_progress();
                     Ok(op.line + 1)
                 }
                 Condition::ForInt(var, mut curr, end, step) => {
                     curr += step;
                     output!(self.opts.verbosity, 3, "END FOR INT: {} of {}", curr, end);
                     if (step > 0 && curr >= end) || (step < 0 && curr <= end) {
-                        return Ok(0);
+                        return self.finish_loop(op.failures);
                     }
                     self.varmgr.set_var(&var, VarValue::Int(curr));
-                    self.cond_stack.push(CondItem { line: op.line, cond: Condition::ForInt(var, curr, end, step) });
+                    self.cond_stack.push(CondItem {
+                        line: op.line,
+                        cond: Condition::ForInt(var, curr, end, step),
+                        failures: op.failures,
+                        progress: op.progress.map(|(n, total)| (n + 1, total)),
+                    });
+                    self.show_progress();
                     Ok(op.line + 1)
                 }
             }
@@ -1262,6 +2437,50 @@ impl Engine {
         }
     }
 
+    /// Called when a `for` loop starts. If `--progress` is on, calculates the number of the
+    /// loop iterations and prints the progress of the first one.
+    fn start_progress(&mut self) {
+        if !self.opts.progress {
+            return;
+        }
+        let item = match self.cond_stack.last_mut() {
+            Some(item) => item,
+            None => return,
+        };
+        let total = match &item.cond {
+            Condition::ForList(_, rest) => rest.len() + 1,
+            Condition::ForInt(_, start, end, step) => {
+                let (dist, step) = if *step > 0 { (end - start, *step) } else { (start - end, -step) };
+                ((dist + step - 1) / step).max(1) as usize
+            }
+            _ => return,
+        };
+        item.progress = Some((1, total));
+        self.show_progress();
+    }
+
+    /// Prints the progress of the current `for` loop iteration: `[3/10] item`
+    fn show_progress(&mut self) {
+        let (var, n, total) = match self.cond_stack.last() {
+            Some(CondItem { cond: Condition::ForList(var, _), progress: Some((n, total)), .. })
+            | Some(CondItem { cond: Condition::ForInt(var, _, _, _), progress: Some((n, total)), .. }) => {
+                (var.clone(), *n, *total)
+            }
+            _ => return,
+        };
+        let text = format!("[{}/{}] {}", n, total, self.varmgr.var(&var));
+        self.echo(&text);
+    }
+
+    /// Called when a `for` loop is over. Returns an error if any iteration of the loop
+    /// marked with `#[no-fail-fast]` has failed.
+    fn finish_loop(&self, failures: Option<usize>) -> Result<usize, HakuError> {
+        match failures {
+            Some(n) if n > 0 => Err(HakuError::ForFailedError(n, self.error_extra())),
+            _ => Ok(0),
+        }
+    }
+
     /// Breaks the current `for` or `while`.
     fn exec_break(&mut self, file: usize) -> Result<usize, HakuError> {
         output!(self.opts.verbosity, 3, "Exec break");
@@ -1269,7 +2488,8 @@ impl Engine {
             match cnd.cond {
                 Condition::If(_) => continue,
                 _ => {
-                    return Ok(self.find_end(file, cnd.line + 1, "break")?);
+                    self.finish_loop(cnd.failures)?;
+                    return self.find_end(file, cnd.line + 1, "break");
                 }
             }
         }
@@ -1301,8 +2521,9 @@ impl Engine {
     /// Initialize `for` loop. Calculates its execution range or list of values and
     /// starts executing from the first one(if the initial conditions are valid). Otherwise,
     /// skips the loop by looking for the corresponding `end` statement.
-    fn exec_for(&mut self, name: &str, seq: Seq, idx: usize) -> Result<bool, HakuError> {
+    fn exec_for(&mut self, name: &str, seq: Seq, idx: usize, keep_going: bool) -> Result<bool, HakuError> {
         output!(self.opts.verbosity, 3, "Exec for");
+        let failures = if keep_going { Some(0) } else { None };
         match seq {
             Seq::Int(start, end, step) => {
                 output!(self.opts.verbosity, 3, "  FOR: from {} to {} step {}", start, end, step);
@@ -1314,13 +2535,17 @@ impl Engine {
                     return Err(HakuError::ForeverForError(self.error_extra()));
                 }
                 self.varmgr.set_var(name, VarValue::Int(start));
-                self.cond_stack
-                    .push(CondItem { line: idx, cond: Condition::ForInt(name.to_string(), start, end, step) });
+                self.cond_stack.push(CondItem {
+                    line: idx,
+                    cond: Condition::ForInt(name.to_string(), start, end, step),
+                    failures,
+                    progress: None,
+                });
                 return Ok(true);
             }
             Seq::Str(s) => {
                 output!(self.opts.verbosity, 3, "  FOR: whitespace-delimited string {}", s);
-                let s = self.varmgr.interpolate(&s, false);
+                let s = self.interpolate(&s, false)?;
                 let mut v: Vec<String> = if s.find('\n').is_some() {
                     s.trim_end().split('\n').map(|s| s.trim_end().to_string()).collect()
                 } else {
@@ -1332,7 +2557,12 @@ impl Engine {
                 }
                 self.varmgr.set_var(name, VarValue::Str(v[0].clone()));
                 v.remove(0);
-                self.cond_stack.push(CondItem { line: idx, cond: Condition::ForList(name.to_string(), v) });
+                self.cond_stack.push(CondItem {
+                    line: idx,
+                    cond: Condition::ForList(name.to_string(), v),
+                    failures,
+                    progress: None,
+                });
                 return Ok(true);
             }
             Seq::Idents(ids) => {
@@ -1340,10 +2570,15 @@ impl Engine {
                 if ids.is_empty() {
                     return Ok(false);
                 }
-                let first = self.varmgr.interpolate(&ids[0], false);
+                let first = self.interpolate(&ids[0], false)?;
                 self.varmgr.set_var(name, VarValue::Str(first));
-                let v: Vec<String> = ids.iter().skip(1).map(|s| self.varmgr.interpolate(s, false)).collect();
-                self.cond_stack.push(CondItem { line: idx, cond: Condition::ForList(name.to_string(), v) });
+                let v = ids.iter().skip(1).map(|s| self.interpolate(s, false)).collect::<Result<Vec<String>, _>>()?;
+                self.cond_stack.push(CondItem {
+                    line: idx,
+                    cond: Condition::ForList(name.to_string(), v),
+                    failures,
+                    progress: None,
+                });
                 return Ok(true);
             }
             Seq::Exec(s) => match self.exec_cmd(&s) {
@@ -1356,12 +2591,18 @@ impl Engine {
                         }
                         self.varmgr.set_var(name, VarValue::Str(v[0].clone()));
                         v.remove(0);
-                        self.cond_stack.push(CondItem { line: idx, cond: Condition::ForList(name.to_string(), v) });
+                        self.cond_stack.push(CondItem {
+                            line: idx,
+                            cond: Condition::ForList(name.to_string(), v),
+                            failures,
+                            progress: None,
+                        });
                         return Ok(true);
                     } else {
                         output!(self.opts.verbosity, 3, "   FOR exec: FAILURE");
                     };
                 }
+                Err(e @ HakuError::UndefinedVarError(..)) => return Err(e),
                 Err(_) => {
                     output!(self.opts.verbosity, 3, "   FOR exec: FAILURE[2]");
                 }
@@ -1374,8 +2615,12 @@ impl Engine {
                     VarValue::Int(start) => {
                         output!(self.opts.verbosity, 3, "   FOR var int ${} = {}", s, start);
                         self.varmgr.set_var(name, VarValue::Int(start));
-                        self.cond_stack
-                            .push(CondItem { line: idx, cond: Condition::ForInt(name.to_string(), start, start, 1) });
+                        self.cond_stack.push(CondItem {
+                            line: idx,
+                            cond: Condition::ForInt(name.to_string(), start, start, 1),
+                            failures,
+                            progress: None,
+                        });
                         return Ok(true);
                     }
                     VarValue::List(vc) => {
@@ -1390,7 +2635,12 @@ impl Engine {
                                 v.push(s.to_string());
                             }
                         }
-                        self.cond_stack.push(CondItem { line: idx, cond: Condition::ForList(name.to_string(), v) });
+                        self.cond_stack.push(CondItem {
+                            line: idx,
+                            cond: Condition::ForList(name.to_string(), v),
+                            failures,
+                            progress: None,
+                        });
                         return Ok(true);
                     }
                     VarValue::Exec(ex) => {
@@ -1402,7 +2652,12 @@ impl Engine {
                             ex.stdout.trim_end().split('\n').map(|s| s.trim_end().to_string()).collect();
                         self.varmgr.set_var(name, VarValue::Str(v[0].clone()));
                         v.remove(0);
-                        self.cond_stack.push(CondItem { line: idx, cond: Condition::ForList(name.to_string(), v) });
+                        self.cond_stack.push(CondItem {
+                            line: idx,
+                            cond: Condition::ForList(name.to_string(), v),
+                            failures,
+                            progress: None,
+                        });
                         return Ok(true);
                     }
                     VarValue::Str(st) => {
@@ -1413,11 +2668,16 @@ impl Engine {
                         let mut v: Vec<String> = if st.find('\n').is_some() {
                             st.trim_end().split('\n').map(|s| s.trim_end().to_string()).collect()
                         } else {
-                            st.trim_end().split_whitespace().map(|s| s.to_string()).collect()
+                            st.split_whitespace().map(|s| s.to_string()).collect()
                         };
                         self.varmgr.set_var(name, VarValue::Str(v[0].clone()));
                         v.remove(0);
-                        self.cond_stack.push(CondItem { line: idx, cond: Condition::ForList(name.to_string(), v) });
+                        self.cond_stack.push(CondItem {
+                            line: idx,
+                            cond: Condition::ForList(name.to_string(), v),
+                            failures,
+                            progress: None,
+                        });
                         return Ok(true);
                     }
                 }
@@ -1426,6 +2686,12 @@ impl Engine {
         Ok(false)
     }
 
+    /// Substitutes variable values in a string or a shell command. In strict mode an undefined
+    /// variable is an error.
+    fn interpolate(&self, s: &str, flat: bool) -> Result<String, HakuError> {
+        self.varmgr.checked_interpolate(s, flat).map_err(|name| HakuError::UndefinedVarError(name, self.error_extra()))
+    }
+
     fn interpolate_path(&self, path: &str) -> String {
         if path != "~" && !path.starts_with("~/") && !path.starts_with("~\\") {
             return path.to_string();
@@ -1445,21 +2711,24 @@ impl Engine {
         pb.join(PathBuf::from(rest)).to_string_lossy().to_string()
     }
 
-    /// If the corresponding `if` or previous `elseif` condition is true, the function
-    /// finishes `if` execution by looking for its `end`. Otherwise, it evaluates `elseif`
-    /// condition. If it is `true`, it starts executing `elseif` body. If `false`, looks
-    /// for the next `elseif`/`else`/`end` which comes first.
+    /// Restores the directory that was current before the last `cd`. If the directory history
+    /// is empty, the current directory does not change. Returns the new current directory.
+    pub(crate) fn pop_dir(&mut self) -> &PathBuf {
+        if let Some(dir) = self.cwd_history.pop() {
+            self.cwd = dir;
+        }
+        &self.cwd
+    }
+
     fn exec_cd(&mut self, flags: u32, path: &str) -> Result<(), HakuError> {
         output!(self.opts.verbosity, 3, "Exec cd");
-        let path = self.varmgr.interpolate(&path, true);
+        let path = self.interpolate(path, true)?;
         let path = self.interpolate_path(&path);
         if !is_flag_on(flags, FLAG_QUIET) {
-            println!("cd {}", path);
+            self.echo(&format!("cd {}", path));
         }
         if path == "-" {
-            if !self.cwd_history.is_empty() {
-                self.cwd = self.cwd_history.pop().unwrap_or_else(|| self.cwd.clone());
-            }
+            self.pop_dir();
             return Ok(());
         }
         if path == ".." {
@@ -1478,6 +2747,9 @@ impl Engine {
             p
         };
         if !full_path.is_dir() {
+            if is_flag_on(flags, FLAG_PASS) {
+                return Ok(());
+            }
             return Err(HakuError::CdError(full_path.to_string_lossy().to_string(), self.error_extra()));
         }
         mem::swap(&mut self.cwd, &mut full_path);
@@ -1504,11 +2776,12 @@ impl Engine {
         match op {
             Op::Int(i) => Ok(VarValue::Int(*i)),
             Op::Str(s) => {
-                let s = self.varmgr.interpolate(&s, false);
+                let s = self.interpolate(s, false)?;
                 Ok(VarValue::Str(s))
             }
             Op::Var(name) => Ok(self.varmgr.var(name)),
             Op::Exec(s) => match self.exec_cmd(s) {
+                Err(e @ HakuError::UndefinedVarError(..)) => Err(e),
                 Err(_) => Ok(VarValue::Undefined),
                 Ok(er) => Ok(VarValue::Exec(er)),
             },
@@ -1525,7 +2798,15 @@ impl Engine {
                 unreachable!()
             }
             Op::AndExpr(ops) => self.exec_and_expr(ops),
-            Op::Func(name, ops) => self.exec_func(&name, &ops),
+            Op::OrExpr(ops) => self.exec_or_expr(ops),
+            Op::Concat(ops) => {
+                let mut s = String::new();
+                for o in ops.iter() {
+                    s += &self.exec_op(o)?.to_string();
+                }
+                Ok(VarValue::Str(s))
+            }
+            Op::Func(name, ops) => self.exec_func(name, ops),
             Op::Compare(cmp_op, ops) => self.exec_compare(cmp_op, ops),
             _ => unreachable!(),
         }
@@ -1554,52 +2835,133 @@ impl Engine {
 
     /// Executed before staring the next recipe. It does all preparations, like recipe
     /// local variable initialization.
-    fn enter_recipe(&mut self, recipe: &RecipeItem) {
-        output!(self.opts.verbosity, 2, "enter recipe. Vars {:?}, Free {:?}", recipe.vars, self.varmgr.free);
-        if recipe.vars.is_empty() || self.varmgr.free.is_empty() {
-            return;
-        }
+    /// Assigns free arguments to recipe variables. A variadic variable(`+name`) gets all the
+    /// rest arguments as a list. When the list is used in a shell command, its items are
+    /// joined with spaces(see `VarValue::to_flat_string`).
+    /// A variable with trailing `!`(e.g., `env!`) is required: if there is no free argument for it,
+    /// the function returns an error. A variable with a type(e.g., `port:int`) gets a value only
+    /// if the value matches the type.
+    /// A dependency declared with arguments(e.g., `build(prod)`) gets those arguments instead
+    /// of free ones.
+    fn enter_recipe(&mut self, recipe: &RecipeItem) -> Result<(), HakuError> {
+        // a dependency with explicit arguments does not use free ones
+        let free: Vec<String> = match &recipe.args {
+            Some(args) => args.iter().map(|a| self.interpolate(a, false)).collect::<Result<Vec<String>, _>>()?,
+            None => self.varmgr.free.clone(),
+        };
+        output!(self.opts.verbosity, 2, "enter recipe. Vars {:?}, Free {:?}", recipe.vars, free);
 
         // init recipe vars
         let mut idx = 0usize;
         for v in recipe.vars.iter() {
-            if v.starts_with('+') {
-                let nm = v.trim_start_matches('+');
-                let mut out = Vec::new();
-                while idx < self.varmgr.free.len() {
-                    out.push(self.varmgr.free[idx].clone());
-                    idx += 1;
+            let (v, required, tp) = arg_spec(v);
+            if idx >= free.len() {
+                if required {
+                    let nm = v.trim_start_matches('+').to_string();
+                    return Err(HakuError::RecipeArgMissingError(recipe.name.clone(), nm, self.error_extra()));
                 }
-                self.varmgr.set_recipe_var(nm, VarValue::List(out));
-                return;
-            } else {
-                self.varmgr.set_recipe_var(v, VarValue::Str(self.varmgr.free[idx].clone()));
-                idx += 1;
-                if idx >= self.varmgr.free.len() {
-                    return;
+                continue;
+            }
+            let nm = v.trim_start_matches('+');
+            self.warn_shadow(&recipe.name, nm);
+            let last = if v.starts_with('+') { free.len() } else { idx + 1 };
+            for val in free[idx..last].iter() {
+                if let Err(msg) = check_arg_type(val, tp) {
+                    return Err(HakuError::RecipeArgTypeError(
+                        recipe.name.clone(),
+                        nm.to_string(),
+                        val.clone(),
+                        msg,
+                        self.error_extra(),
+                    ));
                 }
             }
+            if v.starts_with('+') {
+                let out = free[idx..].to_vec();
+                self.varmgr.set_recipe_var(nm, VarValue::List(out));
+                return Ok(());
+            }
+            self.varmgr.set_recipe_var(v, VarValue::Str(free[idx].clone()));
+            idx += 1;
+        }
+        Ok(())
+    }
+
+    /// Prints a warning if a recipe argument `name` hides a global variable with the same name
+    fn warn_shadow(&mut self, recipe: &str, name: &str) {
+        if !self.opts.warn_shadow || !self.varmgr.has_global(name) {
+            return;
         }
+        let text = format!(
+            "Warning: argument '{}' of recipe '{}' hides global variable '{}'{}",
+            name,
+            recipe,
+            name,
+            self.error_extra()
+        );
+        self.echo_err(&text);
     }
 
     /// When the last line of a recipe is done, it cleans up temporary resources allocated
-    /// for the recipe (e.g. deletes all recipe local variables)
+    /// for the recipe (e.g. deletes all recipe local variables) and flushes redirected output
     fn leave_recipe(&mut self) {
         self.varmgr.recipe_vars.clear();
         self.cond_stack.clear();
+        if let Err(e) = self.flush_output() {
+            eprintln!("{}", e);
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.kill_children();
+        self.clean_temp();
     }
 }
 
 #[cfg(test)]
 mod vm_test {
     use super::*;
+    use std::cell::RefCell;
     use std::mem;
+    use std::rc::Rc;
 
     struct Prs {
         expr: &'static str,
         tp: Op,
     }
 
+    /// Collects the engine console output to check it in tests
+    #[derive(Clone, Default)]
+    struct Console(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Console {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Console {
+        fn lines(&self) -> Vec<String> {
+            String::from_utf8_lossy(&self.0.borrow()).lines().map(|s| s.to_string()).collect()
+        }
+        fn clear(&self) {
+            self.0.borrow_mut().clear();
+        }
+    }
+
+    /// Redirects the engine console and returns the standard and error output collectors
+    fn console(vm: &mut Engine) -> (Console, Console) {
+        let (out, err) = (Console::default(), Console::default());
+        vm.set_console(Box::new(out.clone()), Box::new(err.clone()));
+        (out, err)
+    }
+
     #[test]
     fn load() {
         let opts = RunOpts::new();
@@ -1612,7 +2974,7 @@ mod vm_test {
         assert_eq!(vm.files[0].disabled.len(), 0);
         assert_eq!(
             mem::discriminant(&vm.files[0].ops[0].op),
-            mem::discriminant(&Op::Recipe(String::new(), 0, Vec::new(), Vec::new()))
+            mem::discriminant(&Op::Recipe(String::new(), 0, Vec::new(), Vec::new(), None))
         );
     }
 
@@ -1636,13 +2998,13 @@ mod vm_test {
             Prs { expr: "if $a > `dir | wc -l` || $b == 'test${zef}' then", tp: Op::If(Vec::new()) },
             Prs { expr: "while `ping ${ip}`:", tp: Op::While(Vec::new()) },
             Prs { expr: "while `ping ${ip}` && $b == 90 do", tp: Op::While(Vec::new()) },
-            Prs { expr: "for a in 1..2:", tp: Op::For(String::new(), Seq::Int(0, 0, 0)) },
-            Prs { expr: "for a in 1..2..8 do", tp: Op::For(String::new(), Seq::Int(0, 0, 0)) },
-            Prs { expr: "for a in 'a b c d' then", tp: Op::For(String::new(), Seq::Int(0, 0, 0)) },
-            Prs { expr: "for a in a b c d :", tp: Op::For(String::new(), Seq::Int(0, 0, 0)) },
-            Prs { expr: "for a in `dir *.*`", tp: Op::For(String::new(), Seq::Int(0, 0, 0)) },
-            Prs { expr: "for a in \"acd def\" \"fgh er\"", tp: Op::For(String::new(), Seq::Int(0, 0, 0)) },
-            Prs { expr: "for a in ${var}", tp: Op::For(String::new(), Seq::Int(0, 0, 0)) },
+            Prs { expr: "for a in 1..2:", tp: Op::For(String::new(), Seq::Int(0, 0, 0), false) },
+            Prs { expr: "for a in 1..2..8 do", tp: Op::For(String::new(), Seq::Int(0, 0, 0), false) },
+            Prs { expr: "for a in 'a b c d' then", tp: Op::For(String::new(), Seq::Int(0, 0, 0), false) },
+            Prs { expr: "for a in a b c d :", tp: Op::For(String::new(), Seq::Int(0, 0, 0), false) },
+            Prs { expr: "for a in `dir *.*`", tp: Op::For(String::new(), Seq::Int(0, 0, 0), false) },
+            Prs { expr: "for a in \"acd def\" \"fgh er\"", tp: Op::For(String::new(), Seq::Int(0, 0, 0), false) },
+            Prs { expr: "for a in ${var}", tp: Op::For(String::new(), Seq::Int(0, 0, 0), false) },
         ];
         for p in parses {
             let opts = RunOpts::new();
@@ -1653,4 +3015,1654 @@ mod vm_test {
             assert_eq!(mem::discriminant(&vm.files[0].ops[0].op), mem::discriminant(&p.tp));
         }
     }
+
+    #[test]
+    fn feature_or_not() {
+        let src = "#[feature(zip | rar)]\npack:\n  echo pack\n#[feature(!debug)]\nrelease:\n  echo release\n\
+                   #[os(haiku) | feature(zip)]\nzip:\n  echo zip";
+        let opts = RunOpts::new().with_features(vec!["rar".to_string(), "debug".to_string()]);
+        let mut vm = Engine::new(opts);
+        let res = vm.load_from_str(src);
+        assert!(res.is_ok());
+        assert!(vm.recipes().iter().any(|r| r.name == "pack"));
+        assert!(vm.disabled_recipes().iter().any(|r| r.name == "release"));
+        assert!(vm.disabled_recipes().iter().any(|r| r.name == "zip"));
+
+        let opts = RunOpts::new().with_features(vec!["zip".to_string()]);
+        let mut vm = Engine::new(opts);
+        let res = vm.load_from_str(src);
+        assert!(res.is_ok());
+        assert!(vm.recipes().iter().any(|r| r.name == "pack"));
+        assert!(vm.recipes().iter().any(|r| r.name == "release"));
+        assert!(vm.recipes().iter().any(|r| r.name == "zip"));
+
+        let opts = RunOpts::new();
+        let mut vm = Engine::new(opts);
+        let res = vm.load_from_str(src);
+        assert!(res.is_ok());
+        assert!(vm.disabled_recipes().iter().any(|r| r.name == "pack"));
+        assert!(vm.recipes().iter().any(|r| r.name == "release"));
+        assert_eq!(vm.user_features(), vec!["zip".to_string(), "rar".to_string(), "debug".to_string()]);
+    }
+
+    fn shell_op(vm: &Engine) -> (Vec<String>, String) {
+        match &vm.files[0].ops[0].op {
+            Op::Shell(_, shell, cmd) => (shell.clone(), cmd.clone()),
+            op => panic!("invalid op: {:?}", op),
+        }
+    }
+
+    #[test]
+    fn shell_override() {
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("!python3: print('hi')").is_ok());
+        assert_eq!(shell_op(&vm), (vec!["python3".to_string(), "-c".to_string()], "print('hi')".to_string()));
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("@!bash -e -c: echo hi").is_ok());
+        let shell = vec!["bash".to_string(), "-e".to_string(), "-c".to_string()];
+        assert_eq!(shell_op(&vm), (shell, "echo hi".to_string()));
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(r"!C:\tools\bash.exe: ls").is_ok());
+        assert_eq!(shell_op(&vm), (vec![r"C:\tools\bash.exe".to_string(), "-c".to_string()], "ls".to_string()));
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(r#"!"C:\Program Files\Git\bin\bash.exe" -e -c: ls"#).is_ok());
+        let shell = vec![r"C:\Program Files\Git\bin\bash.exe".to_string(), "-e".to_string(), "-c".to_string()];
+        assert_eq!(shell_op(&vm), (shell, "ls".to_string()));
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("! grep 'a:b' file").is_ok());
+        assert_eq!(shell_op(&vm), (Vec::new(), "! grep 'a:b' file".to_string()));
+
+        // the default shell does not exist, so only the line with its own shell succeeds
+        #[cfg(windows)]
+        let good = "@!powershell: exit 0";
+        #[cfg(not(windows))]
+        let good = "@!sh: exit 0";
+        let mut vm = Engine::new(RunOpts::new());
+        vm.shell = vec!["haku-no-such-shell".to_string(), "-c".to_string()];
+        assert!(vm.load_from_str(good).is_ok());
+        assert!(matches!(vm.run_recipe(""), Err(HakuError::DefaultRecipeError)));
+        let mut vm = Engine::new(RunOpts::new());
+        vm.shell = vec!["haku-no-such-shell".to_string(), "-c".to_string()];
+        assert!(vm.load_from_str("@exit 0").is_ok());
+        assert!(matches!(vm.run_recipe(""), Err(HakuError::ExecFailureError(..))));
+    }
+
+    #[test]
+    fn error_line_numbers() {
+        let src = "#[feature(none)]\na = 1\n#[feature(none)]\nold:\n  echo old\n  echo old\n\n## failing recipe\nbad:\n  #[feature(none)]\n  echo skipped\n  error \"boom\"\nexec:\n  @exit 3";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        match vm.run_recipe("bad") {
+            Err(HakuError::UserError(msg, extra)) => {
+                assert_eq!(msg, "boom");
+                assert!(extra.contains("at line 12:\n-->   error \"boom\""), "{}", extra);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match vm.run_recipe("exec") {
+            Err(e @ HakuError::ExecFailureError(..)) => {
+                let msg = e.to_string();
+                assert!(msg.contains("at line 14:\n-->   @exit 3"), "{}", msg);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn error_line_snippet() {
+        let src =
+            "v = upcase(\"a\", \"b\")\n_default:\n  echo ok\nfunc:\n  name = \"x\"\n  v = pad-left(${name}, \"-\")\n\
+                   cmd:\n  @exit 5\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let msg = vm.run_recipe("").unwrap_err().to_string();
+        assert!(msg.contains("at line 1:\n--> v = upcase(\"a\", \"b\")"), "{}", msg);
+
+        let src = &src[src.find('\n').unwrap() + 1..];
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let msg = vm.run_recipe("func").unwrap_err().to_string();
+        assert!(msg.contains("at line 5:\n-->   v = pad-left(${name}, \"-\")"), "{}", msg);
+        #[cfg(unix)]
+        {
+            let msg = vm.run_recipe("cmd").unwrap_err().to_string();
+            assert!(msg.starts_with("Failed to execute 'exit 5'"), "{}", msg);
+            assert!(msg.contains("at line 7:\n-->   @exit 5"), "{}", msg);
+        }
+    }
+
+    #[test]
+    fn parse_error_column() {
+        let msg = HakuError::ParseError("if $a ==".to_string(), HakuError::error_pointer("if $a ==", 1, 9)).to_string();
+        assert!(msg.contains("at line 2, column 9"), "{}", msg);
+        assert!(msg.ends_with("--> if $a ==\n            ^"), "{}", msg);
+
+        // a line that is not a valid statement is executed by a shell
+        let src = "if [ -f x ]; then echo x; fi\nfor f in *.txt; do echo $f; done\nifconfig -a\n\
+                   while read l; do echo $l; done < in.txt\nwhile true; do sleep 1; done &\n\
+                   if true; then echo y; fi > log\nif $a ==";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.files[0].ops.len(), 7);
+        assert!(vm.files[0].ops.iter().all(|op| matches!(op.op, Op::Shell(..))));
+    }
+
+    #[test]
+    fn line_continuation() {
+        let src =
+            "echo one \\\n  two \\\n  three\necho \"path\\\\\"\necho path\\\\\necho \"dir\\\necho 'a\\\\' \\\n  b";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let cmds: Vec<String> = vm.files[0]
+            .ops
+            .iter()
+            .map(|op| match &op.op {
+                Op::Shell(_, _, cmd) => format!("{}: {}", op.line, cmd),
+                op => panic!("invalid op: {:?}", op),
+            })
+            .collect();
+        assert_eq!(
+            cmds,
+            vec![
+                "0: echo one two three".to_string(),
+                "3: echo \"path\\\\\"".to_string(),
+                "4: echo path\\\\".to_string(),
+                "5: echo \"dir\\".to_string(),
+                "6: echo 'a\\\\' b".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn bom_and_crlf() {
+        let lf = "## doc\na = 1\n\tif $a == 1\n\t\tb = 2\n\tend\nbuild:\n\techo \\\n\t  ${a}\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(lf).is_ok());
+        let expected = vm.dump_ops();
+
+        let crlf = format!("\u{feff}{}", lf.replace('\n', "\r\n"));
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&crlf).is_ok());
+        assert_eq!(vm.dump_ops(), expected);
+        assert_eq!(vm.files[0].orig_lines[0], "## doc");
+
+        let path = env::temp_dir().join("haku-test-bom-crlf.haku");
+        std::fs::write(&path, &crlf).unwrap();
+        let mut vm = Engine::new(RunOpts::new());
+        let res = vm.load_from_file(&path.to_string_lossy());
+        let _ = std::fs::remove_file(&path);
+        assert!(res.is_ok());
+        assert_eq!(vm.dump_ops()[1..], expected[1..]);
+        assert!(vm.has_recipe("build"));
+    }
+
+    #[test]
+    fn custom_comments() {
+        let src = "; header comment\n// not a comment\n#[feature(none)]\na = 1\n## doc\nbuild:\n  ; inner comment\n  -- another\n  echo ok";
+        let mut vm =
+            Engine::new(RunOpts::new().with_comments(vec![";".to_string(), "--".to_string(), "#".to_string()]));
+        assert!(vm.load_from_str(src).is_ok());
+        let dump = vm.dump_ops();
+        assert_eq!(
+            dump[1..],
+            [
+                "  2: Shell(0, [], \"// not a comment\")".to_string(),
+                "  5: DocComment(\"doc\")".to_string(),
+                "  6: Recipe(\"build\", 0, [], [], None)".to_string(),
+                "  9: Shell(0, [], \"echo ok\")".to_string(),
+            ]
+        );
+
+        // default comments
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("// comment\n; not a comment").is_ok());
+        assert_eq!(vm.dump_ops()[1..], ["  2: Shell(0, [], \"; not a comment\")".to_string()]);
+    }
+
+    #[test]
+    fn ops_dump() {
+        let src = "a = 10\n#[feature(none)]\nb = 5\n\nbuild:\n  echo ${a}";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let dump = vm.dump_ops();
+        assert_eq!(dump[0], "File: <string>");
+        assert!(dump.iter().any(|s| s.starts_with("  5: Recipe(\"build\"")));
+        assert!(dump.iter().any(|s| s.starts_with("  6: Shell(")));
+        // disabled line is removed
+        assert!(!dump.iter().any(|s| s.starts_with("  3: ")));
+    }
+
+    #[test]
+    fn bool_literals() {
+        let src = "on = true\noff = FALSE\nsel = $none ? false ? true\nif $on == true && $off == false && !false\n  res = 1\nelse\n  res = 0\nend\ntrue_val = 5";
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str(src).is_ok());
+        let dump = vm.dump_ops();
+        assert!(dump.iter().any(|s| s == "  1: Assign(\"on\", [OrExpr([AndExpr([Int(1)])])])"), "{:?}", dump);
+        assert!(dump.iter().any(|s| s == "  2: Assign(\"off\", [OrExpr([AndExpr([Int(0)])])])"), "{:?}", dump);
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("on"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("off"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("sel"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("res"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("true_val"), VarValue::Int(5));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn short_circuit() {
+        let mut dir = env::temp_dir();
+        dir.push("haku-short-circuit-test");
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let markers: Vec<PathBuf> = (1..=5).map(|i| dir.join(format!("marker{}", i))).collect();
+        for m in markers.iter() {
+            let _ = std::fs::remove_file(m);
+        }
+        let d = dir.to_string_lossy().to_string();
+        let src = format!(
+            "a = 1 || `touch {d}/marker1`\nb = 0 && `touch {d}/marker2`\nif 1 || `touch {d}/marker3`\n  c = 1\nend\nwhile 0 && `touch {d}/marker4`\nend\ne = 0 || `touch {d}/marker5`",
+            d = d
+        );
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("a"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("b"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("c"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("e"), VarValue::Int(1));
+        for m in markers[..4].iter() {
+            assert!(!m.exists(), "{:?}", m);
+        }
+        // the right side of `||` is evaluated if the left one is falsy
+        assert!(markers[4].exists());
+    }
+
+    #[test]
+    fn or_expr_assign() {
+        let src = "a = \"text\"\nb = 0 || \"text\"\nc = 0 || \"\"\nd = $a || $none && 1\ne = $none\nwhile $e || 0\nend";
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str(src).is_ok());
+        let dump = vm.dump_ops();
+        assert!(
+            dump.iter().any(|s| s == "  2: Assign(\"b\", [OrExpr([AndExpr([Int(0)]), AndExpr([Str(\"text\")])])])"),
+            "{:?}",
+            dump
+        );
+        assert!(vm.run_recipe("").is_ok());
+        // a single value is assigned as is, an OR expression gives 0 or 1
+        assert_eq!(vm.varmgr.var("a"), VarValue::from("text"));
+        assert_eq!(vm.varmgr.var("b"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("c"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("d"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("e"), VarValue::Undefined);
+    }
+
+    #[test]
+    fn quiet_attr() {
+        let src =
+            "#[quiet]\n## silent build\nbuild:\n  echo silent\n  @echo loud\n#[quiet]\n\nrelease:\nloud:\n  echo loud";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let flags = |vm: &Engine, name: &str| vm.recipes().iter().find(|r| r.name == name).unwrap().flags;
+        assert_eq!(flags(&vm, "build"), FLAG_QUIET);
+        assert_eq!(flags(&vm, "release"), FLAG_QUIET);
+        assert_eq!(flags(&vm, "loud"), 0);
+        assert_eq!(vm.recipes().iter().find(|r| r.name == "build").unwrap().desc, "silent build");
+
+        let shells: Vec<u32> = vm.files[0]
+            .ops
+            .iter()
+            .filter_map(|op| if let Op::Shell(f, _, _) = op.op { Some(f) } else { None })
+            .collect();
+        // all lines of a quiet recipe are silent
+        assert!(is_flag_on(line_flags(FLAG_QUIET, shells[0]), FLAG_QUIET));
+        assert!(is_flag_on(line_flags(FLAG_QUIET, shells[1]), FLAG_QUIET));
+        assert!(!is_flag_on(line_flags(0, shells[2]), FLAG_QUIET));
+    }
+
+    #[test]
+    fn flags_combination() {
+        // recipe-quiet + line-`@`
+        assert_eq!(line_flags(FLAG_QUIET, FLAG_QUIET), FLAG_QUIET);
+        // recipe-quiet alone
+        assert_eq!(line_flags(FLAG_QUIET, 0), FLAG_QUIET);
+        // line-`@` alone
+        assert_eq!(line_flags(0, FLAG_QUIET), FLAG_QUIET);
+        assert_eq!(line_flags(0, 0), 0);
+        // flags of different kinds are merged
+        assert_eq!(line_flags(FLAG_PASS, FLAG_QUIET), FLAG_PASS | FLAG_QUIET);
+        assert_eq!(line_flags(FLAG_PASS | FLAG_QUIET, FLAG_PASS), FLAG_PASS | FLAG_QUIET);
+
+        // a failed command in a `-` recipe does not stop it even if the line has `-` too
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("-_default:\n  -@haku-no-such-command\n  @haku-no-such-command\n  res = 1").is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("res"), VarValue::Int(1));
+    }
+
+    #[test]
+    fn trace() {
+        let src = "name = \"app\"\n_default:\n  @echo ${name}\n  i = 1\n  if $i > 5\n    -echo skipped\n  end\n  i += 1\n  -echo ${i}";
+        let mut vm = Engine::new(RunOpts::new().with_trace(true));
+        let (_, err) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(
+            err.lines(),
+            vec![
+                "+ <string>:1 name = \"app\"".to_string(),
+                "+ <string>:3 echo app".to_string(),
+                "+ <string>:4 i = 1".to_string(),
+                "+ <string>:8 i += 1".to_string(),
+                "+ <string>:9 echo 2".to_string(),
+            ]
+        );
+
+        let mut vm = Engine::new(RunOpts::new());
+        let (_, err) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn compound_assign() {
+        let src = "i = 0\nsum = 0\nwhile $i < 5\n  i += 1\n  $sum += $i\nend\nprod = 3\nprod *= 4\nprod -= 2\n${prod} /= 5\nnew += 7";
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("i"), VarValue::Int(5));
+        assert_eq!(vm.varmgr.var("sum"), VarValue::Int(15));
+        assert_eq!(vm.varmgr.var("prod"), VarValue::Int(2));
+        assert_eq!(vm.varmgr.var("new"), VarValue::Int(7));
+
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str("a = 5\na /= 0").is_ok());
+        assert!(matches!(vm.run_recipe(""), Err(HakuError::DivisionByZeroError(..))));
+    }
+
+    #[test]
+    fn concat_op() {
+        let src = "base = \"http://host\"\nport = 8080\nurl = $base ~ \":\" ~ $port ~ '/api'\nnum = 1 ~ 0x10 ~ -2\nsame = $base ~ \"\" == \"http://host\"\nlow = lowcase(\"A\" ~ \"B\")";
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str(src).is_ok());
+        let dump = vm.dump_ops();
+        assert!(dump.iter().any(|s| s.contains("Concat([Int(1), Int(16), Int(-2)])")), "{:?}", dump);
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("url"), VarValue::from("http://host:8080/api"));
+        assert_eq!(vm.varmgr.var("num"), VarValue::from("116-2"));
+        assert_eq!(vm.varmgr.var("same"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("low"), VarValue::from("ab"));
+    }
+
+    #[test]
+    fn grouped_conditions() {
+        let src = "a = 1\nb = 0\nc = 0\nr1 = $a || $b && $c\nr2 = ($a || $b) && $c\nr3 = !($b || $c)\nr4 = ($a && ($b || 1)) == 1\nif ($a || $b) && $c\n  r5 = 1\nelseif ($a || $c) && !$b\n  r5 = 2\nend";
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str(src).is_ok());
+        let dump = vm.dump_ops();
+        assert!(
+            dump.iter().any(|s| s.contains("OrExpr([AndExpr([Var(\"a\")]), AndExpr([Var(\"b\")])])")),
+            "{:?}",
+            dump
+        );
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("r1"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("r2"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("r3"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("r4"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("r5"), VarValue::Int(2));
+    }
+
+    #[test]
+    fn recipes_dump() {
+        let src = "a = 1\n\nbuild:\n  echo build\n\n#[feature(haku-none)]\npack:\n  echo pack\ntest: build\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let dump = vm.dump_recipes();
+        assert_eq!(
+            dump,
+            vec![
+                "build\t<string>\t3".to_string(),
+                "test\t<string>\t9".to_string(),
+                "pack\t<string>\t7\tdisabled".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn recipe_at_line() {
+        let src = "a = 1\n\n## build it\nbuild:\n  echo build\n\n#[feature(haku-none)]\npack:\n  echo pack\ntest: build\n  echo test";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.recipe_at("<string>", 1), "header");
+        assert_eq!(vm.recipe_at("<string>", 2), "header");
+        assert_eq!(vm.recipe_at("<string>", 3), "none");
+        assert_eq!(vm.recipe_at("<string>", 4), "build");
+        assert_eq!(vm.recipe_at("<string>", 6), "build");
+        assert_eq!(vm.recipe_at("<string>", 7), "none");
+        assert_eq!(vm.recipe_at("<string>", 9), "pack");
+        assert_eq!(vm.recipe_at("<string>", 11), "test");
+        assert_eq!(vm.recipe_at("<string>", 12), "none");
+        assert_eq!(vm.recipe_at("<string>", 0), "none");
+        assert_eq!(vm.recipe_at("Taskfile", 4), "none");
+    }
+
+    #[test]
+    fn all_args_var() {
+        let src = "_default:\n  s = \"args: ${@}\"\n  lst = $@\n  cnt = 0\n  for a in $@\n    cnt += 1\n  end";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        vm.set_free_args(&["one".to_string(), "two".to_string()]);
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("s"), VarValue::from("args: one two"));
+        assert_eq!(vm.varmgr.var("lst"), VarValue::List(vec!["one".to_string(), "two".to_string()]));
+        assert_eq!(vm.varmgr.var("cnt"), VarValue::Int(2));
+    }
+
+    #[test]
+    fn argc_func() {
+        let src = "_default:\n  c = argc()\n  d = $#\n  enough = argc() > 1\n  if argc() < 3\n    few = 1\n  end";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        vm.set_free_args(&["one".to_string(), "two".to_string()]);
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("c"), VarValue::Int(2));
+        assert_eq!(vm.varmgr.var("d"), VarValue::Int(2));
+        assert_eq!(vm.varmgr.var("enough"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("few"), VarValue::Int(1));
+    }
+
+    #[test]
+    fn shift_func() {
+        let src = "_default:\n  first = shift()\n  rest = $@\n  two = shift(2)\n  none = shift()\n  left = argc()";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        vm.set_free_args(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("first"), VarValue::from("a"));
+        assert_eq!(vm.varmgr.var("rest"), VarValue::List(vec!["b".to_string(), "c".to_string()]));
+        assert_eq!(vm.varmgr.var("two"), VarValue::List(vec!["b".to_string(), "c".to_string()]));
+        assert_eq!(vm.varmgr.var("none"), VarValue::from(""));
+        assert_eq!(vm.varmgr.var("left"), VarValue::Int(0));
+    }
+
+    #[test]
+    fn dep_args() {
+        let src = "target = \"prod\"\nbuild env mode:\n  res = $res ~ \"[\" ~ $env ~ \"/\" ~ $mode ~ \"]\"\n\
+                   deploy: build(${target}, 'fast run') build2() \n  res = $res ~ \"deploy\"\n\
+                   build2 env:\n  res = $res ~ \"<\" ~ $env ~ \">\"";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        vm.set_free_args(&["free".to_string()]);
+        assert!(vm.run_recipe("deploy").is_ok());
+        assert_eq!(vm.varmgr.var("res"), VarValue::from("[prod/fast run]<>deploy"));
+        let deploy = vm.recipes().iter().find(|r| r.name == "deploy").unwrap();
+        assert_eq!(deploy.depends, vec!["build(${target}, fast run)".to_string(), "build2()".to_string()]);
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("build env:\n  res = $env\nall: build\n").is_ok());
+        vm.set_free_args(&["free".to_string()]);
+        assert!(vm.run_recipe("all").is_ok());
+        assert_eq!(vm.varmgr.var("res"), VarValue::from("free"));
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("a: b(1)\n  x = 1\nb: a(2)\n  x = 2\n").is_ok());
+        assert!(vm.run_recipe("a").is_err());
+    }
+
+    #[test]
+    fn dep_if_attr() {
+        let src = "mode = \"dev\"\ndocs:\n  res = $res ~ \"docs;\"\nbuild:\n  res = $res ~ \"build;\"\n\
+                   #[dep_if(feature(\"docs\"), \"docs\")]\n#[dep_if($mode == \"dev\", build)]\n\
+                   release: build docs\n  res = $res ~ \"release\"";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("release").is_ok());
+        assert_eq!(vm.varmgr.var("res"), VarValue::from("build;release"));
+
+        let mut vm = Engine::new(RunOpts::new().with_features(vec!["Docs".to_string()]));
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("release").is_ok());
+        assert_eq!(vm.varmgr.var("res"), VarValue::from("build;docs;release"));
+
+        let mut vm = Engine::new(RunOpts::new());
+        let res = vm.load_from_str("build:\n#[dep_if(1, docs)]\nrelease: build\n");
+        assert!(matches!(res, Err(HakuError::DepConditionError(..))));
+    }
+
+    #[test]
+    fn finally_attr() {
+        let src = "cleanup:\n  res = $res ~ \"cleanup;\"\n#[finally(cleanup)]\nok:\n  res = \"ok;\"\n\
+                   #[finally(\"cleanup\")]\nfail:\n  res = \"fail;\"\n  error \"stop\"\n  res = \"unreachable\"\n\
+                   #[finally(fail)]\nchain:\n  res = \"\"\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("ok").is_ok());
+        assert_eq!(vm.varmgr.var("res"), VarValue::from("ok;cleanup;"));
+
+        let res = vm.run_recipe("fail");
+        assert!(matches!(res, Err(HakuError::UserError(..))));
+        assert_eq!(vm.varmgr.var("res"), VarValue::from("fail;cleanup;"));
+
+        let res = vm.run_recipe("chain");
+        assert!(matches!(res, Err(HakuError::UserError(..))));
+        assert_eq!(vm.varmgr.var("res"), VarValue::from("fail;cleanup;"));
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("#[finally(self)]\nself:\n").is_ok());
+        assert!(matches!(vm.run_recipe("self"), Err(HakuError::RecipeRecursionError(..))));
+
+        // recipes that clean up after each other
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("#[finally(b)]\na:\n  cnt += 1\n#[finally(a)]\nb:\n  cnt += 1\n").is_ok());
+        assert!(matches!(vm.run_recipe("a"), Err(HakuError::RecipeRecursionError(..))));
+        assert_eq!(vm.varmgr.var("cnt"), VarValue::Int(3));
+        assert!(vm.cleanups.is_empty());
+
+        // the cleanup runs even if the recipe fails before its body starts
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("cleanup:\n  done = 1\n#[finally(cleanup)]\nneeds_arg name!:\n").is_ok());
+        assert!(matches!(vm.run_recipe("needs_arg"), Err(HakuError::RecipeArgMissingError(..))));
+        assert_eq!(vm.varmgr.var("done"), VarValue::Int(1));
+    }
+
+    #[test]
+    fn output_redirect() {
+        let mut path = env::temp_dir();
+        path.push(format!("haku-output-{}.txt", std::process::id()));
+        let fname = path.to_string_lossy().to_string().replace('\\', "/");
+        let src = format!(
+            "_default:\n  set_output(\"{0}\")\n  println(\"line\", 1)\n  print(\"tail\")\n  set_output()\n  \
+             println(\"to stdout\")\nmore:\n  set_output(\"{0}\", true)\n  print_json(\"x\")\n",
+            fname
+        );
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line1\ntail");
+        assert!(vm.run_recipe("more").is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "line1\ntail\"x\"\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn retry_func() {
+        let mut path = env::temp_dir();
+        path.push(format!("haku-retry-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let fname = path.to_string_lossy().to_string();
+        // the command fails until the counter file gets two lines
+        let src = format!(
+            "_default:\n  n = retry(3, \"echo x >> {0}; test $(wc -l < {0}) -ge 2\")\n\
+             fail:\n  retry(2, \"exit 1\")\n",
+            fname
+        );
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("n"), VarValue::Int(2));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\nx\n");
+        let _ = std::fs::remove_file(&path);
+
+        let res = vm.run_recipe("fail");
+        assert!(matches!(res, Err(HakuError::FunctionError { .. })));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn retry_backoff() {
+        let mut path = env::temp_dir();
+        path.push(format!("haku-backoff-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let cmd = format!("echo x >> {}; exit 1", path.to_string_lossy());
+        let mut vm = Engine::new(RunOpts::new());
+        let start = Instant::now();
+        // waits 20ms, 40ms, and 80ms
+        assert!(vm.retry_cmd(4, &cmd, Duration::from_millis(20), 2).is_err());
+        let spent = start.elapsed();
+        assert!(spent >= Duration::from_millis(140), "{:?}", spent);
+        assert!(spent < Duration::from_secs(5), "{:?}", spent);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "x\nx\nx\nx\n");
+        let _ = std::fs::remove_file(&path);
+
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true));
+        let start = Instant::now();
+        // dry run only prints the command, so the first attempt always succeeds
+        assert_eq!(vm.retry_cmd(3, "exit 1", Duration::from_secs(10), 2), Ok(VarValue::Int(1)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(vm.retry_cmd(3, "exit 0", Duration::from_secs(1), 0).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_args_func() {
+        let src = "f = \"my file; rm -rf $HOME\"\n_default:\n  out = run_args(\"printf\", \"%s|\", $f, \"x\")\n\
+                   lst = run_args(\"printf\", \"<%s>\", $names)\n  ok = run_args(\"false\")\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        vm.varmgr.set_var("names", VarValue::List(vec!["a b".to_string(), "c".to_string()]));
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("out").to_string(), "my file; rm -rf $HOME|x|");
+        assert_eq!(vm.varmgr.var("lst").to_string(), "<a b><c>");
+        assert!(!vm.varmgr.var("ok").is_true());
+    }
+
+    #[test]
+    fn include_depth() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("haku-depth-{}", std::process::id()));
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        // main -> inc1 -> inc2 -> inc3
+        let names = ["main.haku", "inc1.haku", "inc2.haku", "inc3.haku"];
+        for (idx, name) in names.iter().enumerate() {
+            let src = match names.get(idx + 1) {
+                Some(next) => format!("include \"{}\"\n", dir.join(next).to_string_lossy().replace('\\', "/")),
+                None => "deep:\n".to_string(),
+            };
+            assert!(std::fs::write(dir.join(name), src).is_ok());
+        }
+        let main = dir.join(names[0]).to_string_lossy().to_string();
+
+        let mut vm = Engine::new(RunOpts::new().with_max_include_depth(3));
+        assert!(vm.load_from_file(&main).is_ok());
+        assert_eq!(vm.files.len(), 4);
+
+        let mut vm = Engine::new(RunOpts::new().with_max_include_depth(2));
+        let res = vm.load_from_file(&main);
+        assert!(matches!(res, Err(HakuError::IncludeDepthError(ref f, 2)) if f.ends_with("inc3.haku")), "{:?}", res);
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_file(&main).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn feature_include() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("haku-feat-inc-{}", std::process::id()));
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let inc = dir.join("windows.haku");
+        assert!(std::fs::write(&inc, "win_var = 1\nwin-build:\n").is_ok());
+        let main = dir.join("main.haku");
+        let src = format!("#[feature(windows)]\ninclude \"{}\"\nbuild:\n", inc.to_string_lossy().replace('\\', "/"));
+        assert!(std::fs::write(&main, src).is_ok());
+        let main = main.to_string_lossy().to_string();
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_file(&main).is_ok());
+        assert_eq!(vm.files.len(), 1);
+        assert!(vm.find_recipe("win-build").is_err());
+
+        let mut vm = Engine::new(RunOpts::new().with_features(vec!["windows".to_string()]));
+        assert!(vm.load_from_file(&main).is_ok());
+        assert_eq!(vm.files.len(), 2);
+        assert!(vm.find_recipe("win-build").is_ok());
+        assert!(vm.run_recipe("build").is_ok());
+        assert_eq!(vm.varmgr.var("win_var"), VarValue::Int(1));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn list_by_file() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("haku-list-file-{}", std::process::id()));
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let inc = dir.join("extra.haku");
+        assert!(std::fs::write(&inc, "## from extra\nbeta:\n_default:\nalpha:\n").is_ok());
+        let main = dir.join("main.haku");
+        let src = format!("include \"{}\"\nzeta:\ngamma arg:\n", inc.to_string_lossy().replace('\\', "/"));
+        assert!(std::fs::write(&main, src).is_ok());
+        let inc = inc.to_string_lossy().replace('\\', "/");
+        let main = main.to_string_lossy().to_string();
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_file(&main).is_ok());
+        let groups = vm.recipe_list_by_file(ListOrder::Name);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0], (main.clone(), vec!["gamma (arg)".to_string(), "zeta".to_string()]));
+        assert_eq!(groups[1].0.replace('\\', "/"), inc);
+        assert_eq!(groups[1].1, vec!["alpha".to_string(), "beta #from extra".to_string()]);
+        let groups = vm.recipe_list_by_file(ListOrder::File);
+        assert_eq!(groups[0].1, vec!["zeta".to_string(), "gamma (arg)".to_string()]);
+        assert_eq!(groups[1].1, vec!["beta #from extra".to_string(), "alpha".to_string()]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn glob_include() {
+        let mut dir = env::temp_dir();
+        dir.push(format!("haku-glob-inc-{}", std::process::id()));
+        let tasks = dir.join("tasks");
+        assert!(std::fs::create_dir_all(&tasks).is_ok());
+        assert!(std::fs::write(tasks.join("b.haku"), "order = $order ~ \"b\"\nb-task:\n").is_ok());
+        assert!(std::fs::write(tasks.join("a.haku"), "order = $order ~ \"a\"\na-task:\n").is_ok());
+        assert!(std::fs::write(tasks.join("c.txt"), "c-task:\n").is_ok());
+        let pattern = tasks.to_string_lossy().replace('\\', "/");
+        let main = dir.join("main.haku");
+        assert!(std::fs::write(&main, format!("include \"{}/*.haku\"\nbuild:\n", pattern)).is_ok());
+        let main = main.to_string_lossy().to_string();
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_file(&main).is_ok());
+        assert_eq!(vm.files.len(), 3);
+        assert!(vm.find_recipe("a-task").is_ok());
+        assert!(vm.find_recipe("b-task").is_ok());
+        assert!(vm.find_recipe("c-task").is_err());
+        assert!(vm.run_recipe("build").is_ok());
+        assert_eq!(vm.varmgr.var("order"), VarValue::from("ba"));
+
+        let main = dir.join("none.haku");
+        assert!(std::fs::write(&main, format!("include \"{}/*.none\"\nbuild:\n", pattern)).is_ok());
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(matches!(vm.load_from_file(&main.to_string_lossy()), Err(HakuError::FileOpenFailure(..))));
+        assert!(std::fs::write(&main, format!("-include \"{}/*.none\"\nbuild:\n", pattern)).is_ok());
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_file(&main.to_string_lossy()).is_ok());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn print_shell() {
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("build:\n  shell(\"bash\", \"-c\")\n").is_ok());
+        let shell = vm.resolved_shell().unwrap();
+        #[cfg(windows)]
+        assert_eq!(shell, vec!["powershell".to_string(), "-c".to_string()]);
+        #[cfg(not(windows))]
+        assert_eq!(shell, vec!["sh".to_string(), "-cu".to_string()]);
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("shell(\"bash\", \"-e\", \"-c\")\nbuild:\n").is_ok());
+        assert_eq!(vm.resolved_shell().unwrap(), vec!["bash".to_string(), "-e".to_string(), "-c".to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn capture_recipe_func() {
+        let src = "_default:\n  for i in 1..3\n    out = capture_recipe(\"greet\")\n    cnt += 1\n  end\n\
+                   greet:\n  println(\"first line\")\n  @echo second line\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("out"), VarValue::from("first line\nsecond line"));
+        assert_eq!(vm.varmgr.var("cnt"), VarValue::Int(2));
+        assert!(vm.captures.is_empty());
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("_default:\n  capture_recipe(\"none\")\n").is_ok());
+        assert!(vm.run_recipe("").is_err());
+    }
+
+    #[test]
+    fn exit_func() {
+        let src = "_default:\n  before = 1\n  exit(0)\n  after = 1\nfail:\n  exit(\"5\")\n\
+                   outer:\n  out = capture_recipe(\"fail\")\n\
+                   done:\n  stopped = lowcase(\"YES\")\n#[finally(done)]\nwrapped:\n  exit(2)\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(matches!(vm.run_recipe(""), Err(HakuError::Exit(0))));
+        assert_eq!(vm.varmgr.var("before"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("after"), VarValue::Undefined);
+        assert!(matches!(vm.run_recipe("fail"), Err(HakuError::Exit(5))));
+        // a recipe run by a function cannot stop the run with its own code
+        assert!(matches!(vm.run_recipe("outer"), Err(HakuError::FunctionError { .. })));
+        // functions of the cleanup recipe work after `exit`
+        assert!(matches!(vm.run_recipe("wrapped"), Err(HakuError::Exit(2))));
+        assert_eq!(vm.varmgr.var("stopped"), VarValue::from("yes"));
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str("_default:\n  exit(10000000000)\n").is_ok());
+        assert!(matches!(vm.run_recipe(""), Err(HakuError::FunctionError { .. })));
+    }
+
+    #[test]
+    fn spread_args() {
+        let src = "needles = fields(\"cat dog fox\", 0, 1, 2)\nempty = fields(\"\", 0, 1)\n\
+                   has = contains(\"hot dog\", ...$needles)\nnone = contains(\"bird\", ...$needles)\n\
+                   whole = contains(\"cat dog fox\", $needles)\n\
+                   cnt = fields(\"a b c\", ...fields(\"0 2\", 0, 1))\n\
+                   s = \"dog\"\nsingle = contains(\"hot dog\", ...$s)\n_default:\n";
+        let mut vm = Engine::new(RunOpts::new());
+        let res = vm.load_from_str(src);
+        assert!(res.is_ok(), "{:?}", res);
+        let r = vm.run_recipe("");
+        assert!(r.is_ok(), "{:?}", r);
+        assert_eq!(vm.varmgr.var("has"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("none"), VarValue::Int(0));
+        // without spread the list is passed as a single value: "cat\ndog\nfox"
+        assert_eq!(vm.varmgr.var("whole"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("cnt"), VarValue::List(vec!["a".to_string(), "c".to_string()]));
+        assert!(vm.varmgr.var("single").is_true());
+    }
+
+    #[test]
+    fn once_attr() {
+        let src = "#[once]\nsetup:\n  echo setup\nfirst: setup\n  echo first\n\
+                   second: setup\n  echo second\nplain:\n  echo plain\n";
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("first").is_ok());
+        assert!(vm.run_recipe("second").is_ok());
+        assert!(vm.run_recipe("setup").is_ok());
+        assert!(vm.run_recipe("plain").is_ok());
+        assert!(vm.run_recipe("plain").is_ok());
+        let exp: Vec<String> = ["echo setup", "echo first", "echo second", "echo plain", "echo plain"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(out.lines(), exp);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn either_assign_short_circuit() {
+        let mut path = env::temp_dir();
+        path.push(format!("haku-either-{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let p = path.to_string_lossy().to_string();
+        let src = format!(
+            "a = $none ? \"found\" ? `echo a >> {0}`\nb = \"set\"\nb ?= $none ? `echo b >> {0}`\n\
+             c ?= `echo c >> {0}` ? \"late\"\n_default:\n",
+            p
+        );
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("a"), VarValue::from("found"));
+        assert_eq!(vm.varmgr.var("b"), VarValue::from("set"));
+        // the command in `c` is executed because `c` is undefined, it succeeds and is assigned
+        assert!(vm.varmgr.var("c").is_true());
+        assert_ne!(vm.varmgr.var("c"), VarValue::from("late"));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "c\n");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn strict_vars() {
+        let src = "_default:\n  greeting = \"Hello\"\n  msg = \"${greting}, world\"\n  done = 1\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("msg"), VarValue::from(", world"));
+
+        let mut vm = Engine::new(RunOpts::new().with_strict(true));
+        assert!(vm.load_from_str(src).is_ok());
+        match vm.run_recipe("") {
+            Err(HakuError::UndefinedVarError(name, _)) => assert_eq!(name, "greting"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(vm.varmgr.var("done"), VarValue::Undefined);
+
+        let src = "_default:\n  msg = \"$${greting} ${greting:-x}\"\n  cmd = `echo ${greting}`\n";
+        let mut vm = Engine::new(RunOpts::new().with_strict(true).with_dry_run(true));
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(matches!(vm.run_recipe(""), Err(HakuError::UndefinedVarError(_, _))));
+        assert_eq!(vm.varmgr.var("msg"), VarValue::from("${greting} x"));
+    }
+
+    #[test]
+    fn unused_args() {
+        let src = "all:\n  echo all\nbuild target mode +rest:\n  echo ${target}\nlint strict! lvl:int opt: \
+                   check(${lvl})\n  if $strict == \"yes\"\n    echo ${opt:-none}\n  end\n\
+                   check level:\n  cnt = 0\n  for a in ${level}\n    cnt += 1\n  end\n\
+                   #[dep_if($flag != \"\", all)]\nflagged flag: all\n  echo flagged\n";
+        let mut vm = Engine::new(RunOpts::new());
+        let res = vm.load_from_str(src);
+        assert!(res.is_ok(), "{:?}", res);
+        let warns = vm.unused_arg_warnings();
+        assert_eq!(warns.len(), 2, "{:?}", warns);
+        assert_eq!(warns[0], "argument 'mode' of recipe 'build' is never used (<string>:3)");
+        assert_eq!(warns[1], "argument 'rest' of recipe 'build' is never used (<string>:3)");
+    }
+
+    #[test]
+    fn shadow_warning() {
+        let src = "dir = \"out\"\nmode = \"debug\"\n_default: build\nbuild dir name mode:\n  v = \"${dir}\"\n";
+        let mut vm = Engine::new(RunOpts::new().with_warn_shadow(true));
+        let (_, err) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        vm.set_free_args(&["dist".to_string(), "app".to_string()]);
+        assert!(vm.run_recipe("").is_ok());
+        // `mode` gets no value, so it does not hide the global variable
+        let warns: Vec<String> = err.lines().into_iter().filter(|s| s.starts_with("Warning:")).collect();
+        assert_eq!(warns.len(), 1, "{:?}", warns);
+        assert!(warns[0].starts_with("Warning: argument 'dir' of recipe 'build' hides global variable 'dir'"));
+        // shadowing still works
+        assert_eq!(vm.varmgr.var("v"), VarValue::from("dist"));
+        assert_eq!(vm.varmgr.var("dir"), VarValue::from("out"));
+
+        let mut vm = Engine::new(RunOpts::new());
+        let (_, err) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert!(err.lines().is_empty());
+    }
+
+    #[test]
+    fn list_order() {
+        let src = "zeta:\n  echo z\n## first\nalpha:\n  echo a\n_default:\n  echo h\nmid arg: alpha\n  echo m\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.recipe_list(), vec!["alpha #first", "mid (arg): alpha", "zeta"]);
+        assert_eq!(vm.recipe_list_by(ListOrder::Name), vm.recipe_list());
+        assert_eq!(vm.recipe_list_by(ListOrder::File), vec!["zeta", "alpha #first", "mid (arg): alpha"]);
+    }
+
+    #[test]
+    fn disabled_reasons() {
+        let src = "#[feature(zip)]\nzip:\n  echo zip\n#[feature(rar)]\n#[os(linux,windows,macos,freebsd), feature(zip) | feature(tgz)]\n\
+                   pack:\n  echo pack\n#[feature(!rar)]\nplain:\n  echo plain\n";
+        let mut vm = Engine::new(RunOpts::new().with_features(vec!["rar".to_string()]));
+        assert!(vm.load_from_str(src).is_ok());
+        let mut lines = vm.dump_disabled();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "pack (<string>:6): #[feature(rar)]#[os(linux,windows,macos,freebsd), feature(zip) | feature(tgz)] - \
+                 unmet: feature(zip) | feature(tgz)",
+                "plain (<string>:9): #[feature(!rar)] - unmet: feature(!rar)",
+                "zip (<string>:2): #[feature(zip)] - unmet: feature(zip)",
+            ]
+        );
+    }
+
+    #[test]
+    fn all_features() {
+        let src = "#[feature(zip)]\nzip:\n  echo zip\n#[feature(!zip)]\nplain:\n  echo plain\n\
+                   check:\n  on = feature(\"rar\")\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.recipe_list(), vec!["check", "plain"]);
+
+        let mut vm = Engine::new(RunOpts::new().with_all_features(true));
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.recipe_list(), vec!["check", "zip"]);
+        assert!(vm.run_recipe("check").is_ok());
+        assert_eq!(vm.varmgr.var("on"), VarValue::from(1));
+    }
+
+    #[test]
+    fn feature_implies() {
+        let src = "feature release implies optimized, stripped\nfeature optimized implies lto\n\
+                   #[feature(lto)]\nlink:\n  echo lto\n#[feature(stripped)]\nstrip:\n  echo strip\n\
+                   check:\n  on = feature(\"optimized\")\n";
+        let mut vm = Engine::new(RunOpts::new().with_features(vec!["Release".to_string()]));
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.recipe_list(), vec!["check", "link", "strip"]);
+        assert!(vm.run_recipe("check").is_ok());
+        assert_eq!(vm.varmgr.var("on"), VarValue::from(1));
+
+        let mut vm = Engine::new(RunOpts::new().with_features(vec!["optimized".to_string()]));
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.recipe_list(), vec!["check", "link"]);
+
+        let src = "feature a implies b\nfeature b implies c\nfeature c implies a\n_default:\n  echo a\n";
+        let mut vm = Engine::new(RunOpts::new());
+        match vm.load_from_str(src) {
+            Err(HakuError::FeatureCycleError(s, _)) => assert_eq!(s, "a -> b -> c -> a"),
+            r => panic!("cycle must be detected: {:?}", r.err()),
+        }
+    }
+
+    #[test]
+    fn func_error_fields() {
+        let src = "_default:\n  echo ok\n  v = pad-left(\"a\")\n";
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true));
+        assert!(vm.load_from_str(src).is_ok());
+        let err = vm.run_recipe("").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Function call error: 'function 'pad-left' expects 3 arguments, got 1:  at line 3:\n-->   v = pad-left(\"a\")'"
+        );
+        match err {
+            HakuError::FunctionError { name, msg, file, line, .. } => {
+                assert_eq!(name, "pad-left");
+                assert_eq!(msg, "function 'pad-left' expects 3 arguments, got 1");
+                assert_eq!(file, "<string>");
+                assert_eq!(line, 3);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        // a script loaded from a string includes a file: names must not be mixed up
+        let path = env::temp_dir().join("haku-test-func-error.haku");
+        std::fs::write(&path, "inc:\n  v = upcase()\n").unwrap();
+        let inc = path.to_string_lossy().replace('\\', "/");
+        let mut vm = Engine::new(RunOpts::new());
+        let res = vm.load_from_str(&format!("include \"{}\"\n_default:\n  v = upcase()\n", inc));
+        let _ = std::fs::remove_file(&path);
+        assert!(res.is_ok(), "{:?}", res);
+        match vm.run_recipe("inc") {
+            Err(HakuError::FunctionError { file, line, .. }) => assert_eq!((file, line), (inc.clone(), 2)),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match vm.run_recipe("") {
+            Err(HakuError::FunctionError { file, line, .. }) => assert_eq!((file, line), ("<string>".to_string(), 3)),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(vm.dump_ops()[0], "File: <string>");
+        assert_eq!(vm.recipe_at(&inc, 2), "inc");
+        assert_eq!(vm.recipe_at("<string>", 3), "_default");
+    }
+
+    #[test]
+    fn func_arity() {
+        let src = "few:\n  v = pad-left(\"a\", \"-\")\nmany:\n  v = upcase(\"a\", \"b\")\n\
+                   none:\n  v = os(\"linux\")\nrange:\n  v = replace(\"a\")\nok:\n  v = pad-left(\"a\", \"-\", 3)\n\
+                   least:\n  v = contains()\nempty:\n  v = stem()\nnoinc:\n  v = inc()\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let cases = [
+            ("few", "function 'pad-left' expects 3 arguments, got 2"),
+            ("many", "function 'upcase' expects 1 argument, got 2"),
+            ("none", "function 'os' expects no arguments, got 1"),
+            ("range", "function 'replace' expects from 2 to 3 arguments, got 1"),
+            ("least", "function 'contains' expects at least 1 argument, got 0"),
+            ("empty", "function 'stem' expects 1 argument, got 0"),
+            ("noinc", "function 'inc' expects at least 1 argument, got 0"),
+        ];
+        for (recipe, msg) in cases.iter() {
+            match vm.run_recipe(recipe) {
+                Err(HakuError::FunctionError { msg: s, extra, .. }) => {
+                    assert_eq!(&s, msg);
+                    assert!(extra.contains("line"), "{}", extra);
+                }
+                r => panic!("{} must fail: {:?}", recipe, r),
+            }
+        }
+        assert!(vm.run_recipe("ok").is_ok());
+        assert_eq!(vm.varmgr.var("v"), VarValue::from("--a"));
+
+        // optional arguments and spreading an empty list keep working
+        let src = "none = glob(\"haku-no-such-file-*\")\n\
+                   starts = starts-with(\"abc\")\nends = ends-with(\"abc\")\nhas = contains(\"abc\", ...$none)\n\
+                   rx = match(\"abc\", ...$none)\nfiles = is-file(...$none)\npath = join(...$none)\n\
+                   sorted = sort-num(...$none)\nquoted = quote(...$none)\n_default:\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let r = vm.run_recipe("");
+        assert!(r.is_ok(), "{:?}", r);
+        assert_eq!(vm.varmgr.var("starts"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("ends"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("has"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("files"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("quoted"), VarValue::from(""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn for_no_fail_fast() {
+        let body = "  for i in a b c\n    ran = $ran ~ $i\n    test ${i} != b\n    ok = $ok ~ $i\n  end\n";
+        let src = format!("ran = \"\"\nok = \"\"\nstrict:\n{0}keep:\n  #[no-fail-fast]\n{0}", body);
+        let mut vm = Engine::new(RunOpts::new());
+        let (_, err) = console(&mut vm);
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(matches!(vm.run_recipe("keep"), Err(HakuError::ForFailedError(1, _))));
+        assert!(err.lines().iter().any(|l| l.contains("test ${i} != b")), "{:?}", err.lines());
+        assert_eq!(vm.varmgr.var("ran"), VarValue::from("abc"));
+        assert_eq!(vm.varmgr.var("ok"), VarValue::from("ac"));
+
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(matches!(vm.run_recipe("strict"), Err(HakuError::ExecFailureError(..))));
+        assert_eq!(vm.varmgr.var("ran"), VarValue::from("ab"));
+
+        let mut vm = Engine::new(RunOpts::new());
+        let res = vm.load_from_str("_default:\n  #[no-fail-fast]\n  echo a\n");
+        assert!(matches!(res, Err(HakuError::LoopAttrError(_))));
+
+        // the attribute at the end of the script is ignored with a warning
+        let mut vm = Engine::new(RunOpts::new());
+        let (_, err) = console(&mut vm);
+        assert!(vm.load_from_str("_default:\n  echo a\n  #[no-fail-fast]\n").is_ok());
+        assert_eq!(err.lines(), vec!["Warning: attribute #[no-fail-fast] at the end is ignored".to_string()]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn debug_vars_console() {
+        let mut vm = Engine::new(RunOpts::new().with_debug_vars(true).with_verbosity(1));
+        let (_, err) = console(&mut vm);
+        assert!(vm.load_from_str("a = 1\nb = \"x\\ny\"\n_default:\n  c = $a\n  @echo ${b}").is_ok());
+        // the second line of the value is run as a separate command and fails
+        let _ = vm.run_recipe("");
+        let lines = err.lines();
+        assert!(lines.iter().any(|l| l.starts_with("[var] a -> ")), "{:?}", lines);
+        assert!(
+            lines.contains(&"Warning: multi-line value of 'b' is used in a shell command".to_string()),
+            "{:?}",
+            lines
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn spawn_wait() {
+        let src = "h = spawn(\"exit 3\")\ncode = wait($h)\nlong = spawn(\"sleep 30\")\n\
+                   _default:\n  echo ok\nagain:\n  c = wait($h)\nbad:\n  c = wait(100)\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("h").to_int(), 1);
+        assert_eq!(vm.varmgr.var("code").to_int(), 3);
+        assert!(vm.run_recipe("again").is_err());
+        assert!(vm.run_recipe("bad").is_err());
+
+        // the process that is still running is killed, so the test does not wait 30 seconds
+        let start = Instant::now();
+        assert!(vm.children[1].is_some());
+        vm.kill_children();
+        assert!(vm.children.iter().all(|c| c.is_none()));
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true));
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("code").to_int(), 0);
+        assert!(vm.children.is_empty());
+    }
+
+    #[test]
+    fn clean_temp() {
+        let src = "f = tempfile(\"haku-test-\")\nd = tempdir()\n_default:\n  echo ok\nfail:\n  x = tempfile(1, 2)\n";
+        // temporary paths live through the whole chain and are removed when the run finishes
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        let (f, d) = (vm.varmgr.var("f").to_string(), vm.varmgr.var("d").to_string());
+        assert!(Path::new(&f).file_name().unwrap().to_string_lossy().starts_with("haku-test-"));
+        assert!(vm.run_recipe("").is_ok());
+        assert!(Path::new(&f).is_file());
+        assert!(Path::new(&d).is_dir());
+        let f2 = vm.varmgr.var("f").to_string();
+        assert_ne!(f, f2);
+        drop(vm);
+        assert!(!Path::new(&f).exists());
+        assert!(!Path::new(&d).exists());
+        assert!(!Path::new(&f2).exists());
+
+        // temporary files are removed after a failed recipe as well
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("fail").is_err());
+        let f = vm.varmgr.var("f").to_string();
+        drop(vm);
+        assert!(!Path::new(&f).exists());
+
+        let mut vm = Engine::new(RunOpts::new().with_clean_temp(false));
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        let (f, d) = (vm.varmgr.var("f").to_string(), vm.varmgr.var("d").to_string());
+        drop(vm);
+        assert!(Path::new(&f).is_file());
+        assert!(Path::new(&d).is_dir());
+        let _ = std::fs::remove_file(&f);
+        let _ = std::fs::remove_dir(&d);
+    }
+
+    #[test]
+    fn for_progress() {
+        let src = "_default:\n  for i in a b c\n    for j in 0..5..2\n    end\n  end\n\
+                   #[quiet]\nsilent:\n  for i in a b\n  end\n";
+        let mut vm = Engine::new(RunOpts::new().with_progress(true));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        let mut expected = Vec::new();
+        for (n, i) in ["a", "b", "c"].iter().enumerate() {
+            expected.push(format!("[{}/3] {}", n + 1, i));
+            for (k, j) in ["0", "2", "4"].iter().enumerate() {
+                expected.push(format!("[{}/3] {}", k + 1, j));
+            }
+        }
+        assert_eq!(out.lines(), expected);
+
+        out.clear();
+        assert!(vm.run_recipe("silent").is_ok());
+        assert!(out.lines().is_empty());
+
+        let mut vm = Engine::new(RunOpts::new());
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert!(out.lines().is_empty());
+    }
+
+    #[test]
+    fn cmdline_split() {
+        let args = split_cmdline(r#"git commit  -m "fix \"a\" b" 'it''s' c\ d"#).unwrap();
+        assert_eq!(args, vec!["git", "commit", "-m", "fix \"a\" b", "its", "c d"]);
+        assert_eq!(split_cmdline("echo ''").unwrap(), vec!["echo", ""]);
+        assert_eq!(split_cmdline(r"type C:\dir\file a\\b \'").unwrap(), vec!["type", r"C:\dir\file", r"a\b", "'"]);
+        assert_eq!(split_cmdline("echo 'a | b'").unwrap(), vec!["echo", "a | b"]);
+        for cmd in ["ls | wc -l", "make && make install", "echo a > f", "echo $(date)", "echo `date`", "echo \"a"] {
+            assert!(split_cmdline(cmd).is_err(), "{}", cmd);
+        }
+        assert!(split_cmdline("  ").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn no_builtin_shell() {
+        let src = "_default:\n  out = capture-recipe(\"say\")\nsay:\n  echo \"a  b\" 'c'\n\
+                   pipe:\n  echo a | wc -l\nforced:\n  @!sh -c: echo a | grep a\n";
+        let mut vm = Engine::new(RunOpts::new().with_no_shell(true));
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("out"), VarValue::from("a  b c"));
+        match vm.run_recipe("pipe") {
+            Err(HakuError::NoShellError(cmd, msg, _)) => {
+                assert_eq!(cmd, "echo a | wc -l");
+                assert_eq!(msg, "shell operator '|' is not supported");
+            }
+            r => panic!("pipe must be rejected: {:?}", r),
+        }
+        assert!(vm.run_recipe("forced").is_ok());
+    }
+
+    #[test]
+    fn isolate_env() {
+        let src = "set-env(\"HAKU_ISO_KEEP\", \"global\")\n#[isolate-env]\nisolated:\n  set-env(\"HAKU_ISO_NEW\", \"1\")\n\
+                   del-env(\"HAKU_ISO_KEEP\")\n  inside = $HAKU_ISO_NEW\nshared:\n  set-env(\"HAKU_ISO_SHARED\", \"2\")\n\
+                   check:\n  new = $HAKU_ISO_NEW\n  keep = $HAKU_ISO_KEEP\n  shared = $HAKU_ISO_SHARED\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("isolated").is_ok());
+        assert_eq!(vm.varmgr.var("inside"), VarValue::from("1"));
+        assert!(vm.run_recipe("shared").is_ok());
+        assert!(vm.run_recipe("check").is_ok());
+        assert_eq!(vm.varmgr.var("new"), VarValue::Undefined);
+        assert_eq!(vm.varmgr.var("keep"), VarValue::from("global"));
+        assert_eq!(vm.varmgr.var("shared"), VarValue::from("2"));
+    }
+
+    #[test]
+    fn continue_from() {
+        let src = "fetch:\n  echo fetch\nbuild: fetch\n  echo build\ntest: build\n  echo test\n\
+                   deploy: test\n  echo deploy\n";
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true).with_continue_from("test"));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("deploy").is_ok());
+        assert_eq!(out.lines(), vec!["echo test".to_string(), "echo deploy".to_string()]);
+        // only the first run is resumed
+        out.clear();
+        assert!(vm.run_recipe("build").is_ok());
+        assert_eq!(out.lines(), vec!["echo fetch".to_string(), "echo build".to_string()]);
+
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true).with_continue_from("deploy"));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        match vm.run_recipe("build") {
+            Err(HakuError::RecipeNotInRunError(name, order)) => {
+                assert_eq!(name, "deploy");
+                assert_eq!(order, "fetch, build");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(out.lines().is_empty());
+    }
+
+    #[test]
+    fn only_recipe() {
+        let src = "fetch:\n  echo fetch\ncompile mode: fetch\n  echo compile ${mode}\nbuild: compile(release)\n  echo build\n";
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true).with_only("compile"));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("build").is_ok());
+        assert_eq!(out.lines(), vec!["echo compile release".to_string()]);
+
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true).with_only("build"));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("compile").is_err());
+        assert!(out.lines().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dry_run_modes() {
+        let src = "_default:\n  if `echo yes` == \"yes\"\n    @echo taken\n  else\n    echo other\n  end\n  \
+                   v = `echo value`\n  touch ${v}.txt\n";
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true).with_dry_run_smart(true));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(out.lines(), vec!["echo taken".to_string(), "touch value.txt".to_string()]);
+
+        let mut vm = Engine::new(RunOpts::new().with_dry_run(true));
+        let (out, _) = console(&mut vm);
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(out.lines(), vec!["echo other".to_string(), "touch .txt".to_string()]);
+    }
+
+    #[test]
+    fn cwd_func() {
+        let mut dir = env::temp_dir();
+        dir.push("haku-cwd-test");
+        dir.push("sub");
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let parent = dir.parent().unwrap().to_string_lossy().to_string();
+        let src =
+            format!("_default:\n  start = cwd()\n  @cd {}\n  @cd sub\n  sub = pwd()\n  @cd ..\n  up = cwd()", parent);
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        let start = env::current_dir().unwrap().to_string_lossy().to_string();
+        assert_eq!(vm.varmgr.var("start"), VarValue::from(start));
+        assert_eq!(vm.varmgr.var("sub"), VarValue::from(dir.to_string_lossy().to_string()));
+        assert_eq!(vm.varmgr.var("up"), VarValue::from(parent));
+    }
+
+    #[test]
+    fn cd_cmd() {
+        let mut dir = env::temp_dir();
+        dir.push("haku-cd-test");
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let marker = dir.join("marker.txt");
+        let _ = std::fs::remove_file(&marker);
+        let dirname = dir.to_string_lossy().to_string();
+
+        let src = format!("_default:\n  @cd {}\n  @-cd haku-no-such-dir\n  @echo done > marker.txt", dirname);
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert!(marker.is_file());
+        assert_eq!(vm.cwd, dir);
+
+        let src = format!("_default:\n  @cd {}\n  @cd haku-no-such-dir\n  @echo done", dirname);
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(matches!(vm.run_recipe(""), Err(HakuError::CdError(..))));
+        let _ = std::fs::remove_file(&marker);
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn cd_backtick() {
+        let mut dir = env::temp_dir();
+        dir.push("haku-cd-backtick-test");
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let dir = dir.canonicalize().unwrap();
+        let src = format!("_default:\n  @cd {}\n  d = `pwd -P`", dir.display());
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("d").to_string(), dir.to_string_lossy().to_string());
+    }
+
+    #[test]
+    fn popd_func() {
+        let mut dir = env::temp_dir();
+        dir.push("haku-popd-test");
+        dir.push("sub");
+        assert!(std::fs::create_dir_all(&dir).is_ok());
+        let parent = dir.parent().unwrap().to_string_lossy().to_string();
+        let start = env::current_dir().unwrap().to_string_lossy().to_string();
+        let src = format!(
+            "_default:\n  @cd {}\n  @cd sub\n  d1 = popd()\n  d2 = popd()\n  d3 = popd()\n  d4 = cwd()",
+            parent
+        );
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(&src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("d1"), VarValue::from(parent));
+        assert_eq!(vm.varmgr.var("d2"), VarValue::from(start.clone()));
+        assert_eq!(vm.varmgr.var("d3"), VarValue::from(start.clone()));
+        assert_eq!(vm.varmgr.var("d4"), VarValue::from(start));
+    }
+
+    #[test]
+    fn defined_func() {
+        let src = "_default:\n  empty = \"\"\n  full = \"value\"\n  r1 = defined(\"empty\")\n  r2 = is_set(\"full\")\n  r3 = defined(\"haku_no_such_var\")\n  r4 = defined(\"empty\", \"haku_no_such_var\")\n  r5 = !$empty";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("r1"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("r2"), VarValue::Int(1));
+        assert_eq!(vm.varmgr.var("r3"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("r4"), VarValue::Int(0));
+        assert_eq!(vm.varmgr.var("r5"), VarValue::Int(1));
+    }
+
+    #[test]
+    fn help_func() {
+        let src = "_default:\n  lst = help()\n## build the app\nbuild mode: prep\nprep:\n_before:\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        let lst = vm.varmgr.var("lst").to_string();
+        assert!(lst.starts_with("Available:"), "{}", lst);
+        assert!(lst.contains("    build (mode): prep #build the app"), "{}", lst);
+        assert!(lst.contains("    prep"), "{}", lst);
+        assert!(!lst.contains("_before"), "{}", lst);
+        assert!(!lst.contains("_default"), "{}", lst);
+
+        // the list goes to the same place as the output of print functions
+        let src = "_default:\n  out = capture-recipe(\"show\")\nshow:\n  help()\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("out").to_string(), "Available:\n    show");
+    }
+
+    #[test]
+    fn recipe_describe() {
+        let src = "## build the app\nbuild ver: clean\n  echo ${ver}\nclean:\n  echo\n## install it\n#[feature(inst)]\ninstall:\n  echo";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let s = vm.describe_recipe("build").unwrap();
+        assert!(s.starts_with("Active recipe: build"));
+        assert!(s.contains("ver"));
+        assert!(s.contains("clean"));
+        assert!(s.contains("build the app"));
+        let s = vm.describe_recipe("install").unwrap();
+        assert!(s.starts_with("Disabled recipe: install"));
+        assert!(s.contains("install it"));
+        assert!(s.contains("feature(inst)"));
+        assert!(vm.describe_recipe("deploy").is_err());
+    }
+
+    #[test]
+    fn default_recipe_args() {
+        let src = "_default env os:\n  result = \"${env}-${os}\"\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.default_has_args());
+        vm.set_free_args(&["arg1".to_string(), "arg2".to_string()]);
+        assert!(vm.run_recipe("").is_ok());
+        assert_eq!(vm.varmgr.var("result"), VarValue::from("arg1-arg2"));
+    }
+
+    #[test]
+    fn variadic_args() {
+        let src = "run first +args:\n  result = `echo ${args}`\n  all = \"${args}\"";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let args: Vec<String> = ["cmd", "a", "b c", "d"].iter().map(|s| s.to_string()).collect();
+        vm.set_free_args(&args);
+        assert!(vm.run_recipe("run").is_ok());
+        // strings keep the list items on separate lines
+        assert_eq!(vm.varmgr.var("all"), VarValue::from("a\nb c\nd"));
+        #[cfg(not(windows))]
+        assert_eq!(vm.varmgr.var("result").to_string(), "a b c d");
+    }
+
+    #[test]
+    fn required_args() {
+        let src = "deploy env! +hosts!:\n  result = \"${env}: ${hosts}\"\nbuild ver mode!:\n  echo";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.find_recipe("deploy").unwrap().vars, vec!["env!".to_string(), "+hosts!".to_string()]);
+        match vm.run_recipe("deploy") {
+            Err(e @ HakuError::RecipeArgMissingError(..)) => {
+                assert!(e.to_string().starts_with("Recipe 'deploy' requires argument 'env'"), "{}", e);
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        vm.set_free_args(&["prod".to_string()]);
+        match vm.run_recipe("deploy") {
+            Err(HakuError::RecipeArgMissingError(_, arg, _)) => assert_eq!(arg, "hosts"),
+            r => panic!("unexpected result: {:?}", r),
+        }
+        vm.set_free_args(&["prod".to_string(), "host1".to_string()]);
+        assert!(vm.run_recipe("deploy").is_ok());
+        assert_eq!(vm.varmgr.var("result"), VarValue::from("prod: host1"));
+        vm.set_free_args(&["1.0".to_string()]);
+        assert!(matches!(vm.run_recipe("build"), Err(HakuError::RecipeArgMissingError(..))));
+    }
+
+    #[test]
+    fn arg_type_word_boundary() {
+        // a dependency that starts with a type name is not an argument type
+        let src =
+            "deploy env:integration pathfinder\n  echo deploy\nintegration:\n  echo int\npathfinder:\n  echo path\n\
+                   serve port:int:\n  echo ${port}\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let recipes = vm.recipes();
+        let deploy = recipes.iter().find(|r| r.name == "deploy").unwrap();
+        assert_eq!(deploy.vars, vec!["env".to_string()]);
+        assert_eq!(deploy.depends, vec!["integration".to_string(), "pathfinder".to_string()]);
+        let serve = recipes.iter().find(|r| r.name == "serve").unwrap();
+        assert_eq!(serve.vars, vec!["port:int".to_string()]);
+
+        // without the recipe separator after arguments, a type name is a dependency
+        let src = "build port:int\n  echo ${port}\ncheck a:path b\n  echo ${a}\nint:\n  echo int\n\
+                   path:\n  echo path\nb:\n  echo b\n";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let recipes = vm.recipes();
+        let build = recipes.iter().find(|r| r.name == "build").unwrap();
+        assert_eq!(build.vars, vec!["port".to_string()]);
+        assert_eq!(build.depends, vec!["int".to_string()]);
+        let check = recipes.iter().find(|r| r.name == "check").unwrap();
+        assert_eq!(check.vars, vec!["a".to_string()]);
+        assert_eq!(check.depends, vec!["path".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn typed_args() {
+        let src = "serve port:int mode!:enum(dev|prod) +files:path:\n  result = \"${port} ${mode} ${files}\"";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let dir = env::temp_dir().to_string_lossy().to_string();
+        let args = |v: &[&str]| -> Vec<String> { v.iter().map(|s| s.to_string()).collect() };
+
+        vm.set_free_args(&args(&["8080", "dev", &dir]));
+        assert!(vm.run_recipe("serve").is_ok());
+        assert_eq!(vm.varmgr.var("result"), VarValue::from(format!("8080 dev {}", dir)));
+
+        vm.set_free_args(&args(&["http", "dev"]));
+        match vm.run_recipe("serve") {
+            Err(e @ HakuError::RecipeArgTypeError(..)) => {
+                assert!(
+                    e.to_string()
+                        .starts_with("Invalid value 'http' of argument 'port' of recipe 'serve': must be an integer"),
+                    "{}",
+                    e
+                );
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        vm.set_free_args(&args(&["0x1F", "test"]));
+        match vm.run_recipe("serve") {
+            Err(HakuError::RecipeArgTypeError(_, arg, _, msg, _)) => {
+                assert_eq!(arg, "mode");
+                assert_eq!(msg, "must be one of: dev, prod");
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        vm.set_free_args(&args(&["1", "prod", &dir, "haku-no-such-path"]));
+        assert!(matches!(vm.run_recipe("serve"), Err(HakuError::RecipeArgTypeError(..))));
+    }
+
+    #[test]
+    fn header_resets_vars_in_chain() {
+        let src = "log ?= \"start\"\nclean:\n  log = \"${log},clean\"\nbuild:\n  log = \"${log},build\"";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.has_recipe("clean"));
+        assert!(!vm.has_recipe("deploy"));
+        assert!(vm.run_recipe("clean").is_ok());
+        assert!(vm.run_recipe("build").is_ok());
+        assert_eq!(vm.varmgr.var("log"), VarValue::from("start,clean,build"));
+
+        // the header runs before every recipe, so a plain assignment resets the variable
+        let src = "log = \"start\"\nclean:\n  log = \"${log},clean\"\nbuild:\n  log = \"${log},build\"";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.run_recipe("clean").is_ok());
+        assert_eq!(vm.varmgr.var("log"), VarValue::from("start,clean"));
+        assert!(vm.run_recipe("build").is_ok());
+        assert_eq!(vm.varmgr.var("log"), VarValue::from("start,build"));
+    }
+
+    #[test]
+    fn missing_default() {
+        let src = "a = 10\nbuild:\n  a = 20";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let res = vm.run_recipe("");
+        assert!(matches!(res, Err(HakuError::DefaultRecipeError)));
+
+        let mut vm = Engine::new(RunOpts::new().with_allow_missing_default(true));
+        assert!(vm.load_from_str(src).is_ok());
+        let res = vm.run_recipe("");
+        assert!(res.is_ok());
+        // the header is still executed
+        assert_eq!(vm.varmgr.var("a"), VarValue::Int(10));
+        // explicitly set recipe must exist
+        assert!(vm.run_recipe("deploy").is_err());
+    }
+
+    #[test]
+    fn recipe_suggestion() {
+        assert_eq!(levenshtein("build", "build"), 0);
+        assert_eq!(levenshtein("biuld", "build"), 2);
+        assert_eq!(levenshtein("", "test"), 4);
+        assert_eq!(levenshtein("tst", "test"), 1);
+
+        let src = "build:\n  echo build\ntest:\n  echo test\n_default:\n  echo default";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        let err = vm.find_recipe("biuld").unwrap_err().to_string();
+        assert_eq!(err, "Recipe 'biuld' not found, did you mean 'build'?");
+        let err = vm.find_recipe("tets").unwrap_err().to_string();
+        assert!(err.ends_with("did you mean 'test'?"));
+        let err = vm.find_recipe("deploy").unwrap_err().to_string();
+        assert_eq!(err, "Recipe 'deploy' not found");
+        // system recipes are never suggested
+        let err = vm.find_recipe("default").unwrap_err().to_string();
+        assert_eq!(err, "Recipe 'default' not found");
+        assert!(vm.has_similar_recipe("biuld"));
+        assert!(!vm.has_similar_recipe("deploy"));
+    }
+
+    #[test]
+    fn recipe_ignore_case() {
+        let src = "build:\n  echo build\nTest:\n  echo Test\ntest:\n  echo test";
+        let mut vm = Engine::new(RunOpts::new());
+        assert!(vm.load_from_str(src).is_ok());
+        assert!(vm.find_recipe("Build").is_err());
+
+        let mut vm = Engine::new(RunOpts::new().with_ignore_case(true));
+        assert!(vm.load_from_str(src).is_ok());
+        assert_eq!(vm.find_recipe("Build").unwrap().name, "build");
+        assert_eq!(vm.find_recipe("BUILD").unwrap().name, "build");
+        // exact match wins
+        assert_eq!(vm.find_recipe("test").unwrap().name, "test");
+        match vm.find_recipe("TEST") {
+            Err(HakuError::RecipeAmbiguousError(name, list)) => {
+                assert_eq!(name, "TEST");
+                assert!(list.contains("Test") && list.contains("test"));
+            }
+            _ => panic!("ambiguous name must fail"),
+        }
+    }
 }